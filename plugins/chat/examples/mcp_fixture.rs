@@ -0,0 +1,332 @@
+//! 用于测试的最小 MCP 服务器
+//!
+//! 默认通过 stdio 收发 JSON-RPC；`--http` 使用 streamable HTTP (路径 /mcp)，
+//! `--sse` 使用旧版 SSE (路径 /sse 与 /messages)，两者均监听随机端口并在第一行输出地址，
+//! 且要求 `Authorization: Bearer test-token`
+//!
+//! 工具：echo、add、crash (退出进程)、change (发送 tools/list_changed 并新增 extra 工具)、
+//! expire (使所有 HTTP 会话失效)、sleep (等待 seconds 秒)、cancelled (返回收到的取消通知数)
+
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Sender, channel};
+use std::sync::{Arc, Mutex};
+
+const TOKEN: &str = "Bearer test-token";
+
+#[derive(Default)]
+struct State {
+    /// 是否已新增 extra 工具
+    changed: bool,
+    /// streamable HTTP 的会话
+    sessions: Vec<String>,
+    /// 旧版 SSE 的会话，消息通过通道写入事件流
+    streams: HashMap<String, Sender<Value>>,
+    next_session: u32,
+    /// 收到的取消通知数
+    cancelled: u32,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+fn main() {
+    let state = Shared::default();
+    match std::env::args().nth(1).as_deref() {
+        Some("--http") => serve(state, false),
+        Some("--sse") => serve(state, true),
+        _ => stdio(state),
+    }
+}
+
+/// 每条消息在单独的线程中处理，sleep 不会阻塞其他请求
+fn stdio(state: Shared) {
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else { break };
+        let Ok(request) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            let messages = handle(&state, &request);
+            let mut stdout = std::io::stdout().lock();
+            for message in messages {
+                writeln!(stdout, "{}", message).unwrap();
+            }
+            stdout.flush().unwrap();
+        });
+    }
+}
+
+/// 处理一条消息，返回需要发送的通知与回复
+fn handle(state: &Shared, request: &Value) -> Vec<Value> {
+    if request["method"] == "notifications/cancelled" {
+        state.lock().unwrap().cancelled += 1;
+    }
+    // 通知与客户端的回复不需要处理
+    let Some(id) = request.get("id").cloned() else {
+        return vec![];
+    };
+    let Some(method) = request["method"].as_str() else {
+        return vec![];
+    };
+    let params = &request["params"];
+    let mut messages = vec![];
+    let result = match method {
+        "initialize" => json!({
+            "protocolVersion": params["protocolVersion"],
+            "capabilities": { "tools": { "listChanged": true } },
+            "serverInfo": { "name": "fixture", "version": "0.1.0" }
+        }),
+        "tools/list" => {
+            let mut tools = vec![
+                tool(
+                    "echo",
+                    "原样返回文本",
+                    json!({ "text": { "type": "string" } }),
+                ),
+                tool(
+                    "add",
+                    "计算两个整数的和",
+                    json!({ "a": { "type": "integer" }, "b": { "type": "integer" } }),
+                ),
+                tool("crash", "让服务器退出", json!({})),
+                tool("change", "修改工具列表", json!({})),
+                tool("expire", "使会话失效", json!({})),
+                tool(
+                    "sleep",
+                    "等待若干秒",
+                    json!({ "seconds": { "type": "integer" } }),
+                ),
+                tool("cancelled", "返回收到的取消通知数", json!({})),
+            ];
+            if state.lock().unwrap().changed {
+                tools.push(tool("extra", "新增的工具", json!({})));
+            }
+            json!({ "tools": tools })
+        }
+        "tools/call" => {
+            let args = &params["arguments"];
+            let text = match params["name"].as_str().unwrap_or_default() {
+                "echo" => args["text"].as_str().unwrap_or_default().to_string(),
+                "add" => {
+                    let sum = args["a"].as_i64().unwrap_or(0) + args["b"].as_i64().unwrap_or(0);
+                    sum.to_string()
+                }
+                "crash" => std::process::exit(1),
+                "change" => {
+                    state.lock().unwrap().changed = true;
+                    messages.push(json!({
+                        "jsonrpc": "2.0",
+                        "method": "notifications/tools/list_changed"
+                    }));
+                    "changed".to_string()
+                }
+                "expire" => {
+                    state.lock().unwrap().sessions.clear();
+                    "expired".to_string()
+                }
+                "sleep" => {
+                    let seconds = args["seconds"].as_u64().unwrap_or(0);
+                    std::thread::sleep(std::time::Duration::from_secs(seconds));
+                    "slept".to_string()
+                }
+                "cancelled" => state.lock().unwrap().cancelled.to_string(),
+                "extra" => "extra".to_string(),
+                name => {
+                    messages.push(json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "result": {
+                            "content": [{ "type": "text", "text": format!("unknown tool {}", name) }],
+                            "isError": true
+                        }
+                    }));
+                    return messages;
+                }
+            };
+            json!({ "content": [{ "type": "text", "text": text }] })
+        }
+        method => {
+            messages.push(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": format!("method not found: {}", method) }
+            }));
+            return messages;
+        }
+    };
+    messages.push(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+    messages
+}
+
+fn tool(name: &str, description: &str, properties: Value) -> Value {
+    json!({
+        "name": name,
+        "description": description,
+        "inputSchema": { "type": "object", "properties": properties }
+    })
+}
+
+fn serve(state: Shared, legacy: bool) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let path = if legacy { "/sse" } else { "/mcp" };
+    println!("http://{}{}", listener.local_addr().unwrap(), path);
+    std::io::stdout().flush().unwrap();
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            let _ = connection(stream, &state, legacy);
+        });
+    }
+}
+
+/// 简单的 HTTP/1.1 请求
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (key, value) = line.split_once(':')?;
+        headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+    }
+    let length = headers
+        .get("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, headers: &[(&str, String)], body: &str) {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: keep-alive\r\n",
+        status,
+        body.len()
+    );
+    for (key, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    response.push_str("\r\n");
+    response.push_str(body);
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn connection(stream: TcpStream, state: &Shared, legacy: bool) -> Option<()> {
+    let mut writer = stream.try_clone().ok()?;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let request = read_request(&mut reader)?;
+        if request.headers.get("authorization").map(String::as_str) != Some(TOKEN) {
+            respond(&mut writer, "401 Unauthorized", &[], "");
+            continue;
+        }
+        match (legacy, request.method.as_str(), request.path.as_str()) {
+            (false, "POST", "/mcp") => streamable(&mut writer, state, &request),
+            (false, _, "/mcp") => respond(&mut writer, "405 Method Not Allowed", &[], ""),
+            (true, "GET", "/sse") => return legacy_stream(writer, state),
+            (true, "POST", path) if path.starts_with("/messages?session=") => {
+                let session = &path["/messages?session=".len()..];
+                let sender = state.lock().unwrap().streams.get(session).cloned();
+                let (Some(sender), Ok(message)) =
+                    (sender, serde_json::from_slice::<Value>(&request.body))
+                else {
+                    respond(&mut writer, "404 Not Found", &[], "");
+                    continue;
+                };
+                respond(&mut writer, "202 Accepted", &[], "");
+                for reply in handle(state, &message) {
+                    let _ = sender.send(reply);
+                }
+            }
+            _ => respond(&mut writer, "404 Not Found", &[], ""),
+        }
+    }
+}
+
+/// streamable HTTP：单条回复使用 JSON，带通知时使用事件流
+fn streamable(writer: &mut TcpStream, state: &Shared, request: &Request) {
+    let Ok(message) = serde_json::from_slice::<Value>(&request.body) else {
+        respond(writer, "400 Bad Request", &[], "");
+        return;
+    };
+    let mut headers = vec![];
+    if message["method"] == "initialize" {
+        let mut state = state.lock().unwrap();
+        state.next_session += 1;
+        let session = format!("session-{}", state.next_session);
+        state.sessions.push(session.clone());
+        headers.push(("Mcp-Session-Id", session));
+    } else {
+        let session = request.headers.get("mcp-session-id");
+        let known = session.is_some_and(|s| state.lock().unwrap().sessions.contains(s));
+        if !known {
+            respond(writer, "404 Not Found", &[], "");
+            return;
+        }
+    }
+
+    let messages = handle(state, &message);
+    match messages.as_slice() {
+        [] => respond(writer, "202 Accepted", &headers, ""),
+        [reply] => {
+            headers.push(("Content-Type", "application/json".to_string()));
+            respond(writer, "200 OK", &headers, &reply.to_string());
+        }
+        _ => {
+            headers.push(("Content-Type", "text/event-stream".to_string()));
+            let body: String = messages
+                .iter()
+                .map(|m| format!("event: message\r\ndata: {}\r\n\r\n", m))
+                .collect();
+            respond(writer, "200 OK", &headers, &body);
+        }
+    }
+}
+
+/// 旧版 SSE：先发送 endpoint 事件，之后的回复都写入此事件流
+fn legacy_stream(mut writer: TcpStream, state: &Shared) -> Option<()> {
+    let (sender, receiver) = channel();
+    let session = {
+        let mut state = state.lock().unwrap();
+        state.next_session += 1;
+        let session = state.next_session.to_string();
+        state.streams.insert(session.clone(), sender);
+        session
+    };
+    let head =
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n";
+    writer.write_all(head.as_bytes()).ok()?;
+    let endpoint = format!("event: endpoint\ndata: /messages?session={}\n\n", session);
+    writer.write_all(endpoint.as_bytes()).ok()?;
+    for message in receiver {
+        let event = format!("event: message\ndata: {}\n\n", message);
+        writer.write_all(event.as_bytes()).ok()?;
+    }
+    Some(())
+}
@@ -0,0 +1,238 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// 命令参数错误，注册器会附带命令用法回复给用户
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// 解析后的命令参数
+///
+/// 支持引号包裹的参数、`--flag` 开关与 `--key=value` 选项，`--` 之后的内容均视为位置参数
+// 部分方法仅供自定义命令使用
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone)]
+pub struct Args {
+    name: String,
+    positional: Vec<String>,
+    flags: HashMap<String, Option<String>>,
+    rest: String,
+}
+
+#[allow(dead_code)]
+impl Args {
+    /// 解析去除前缀后的命令文本
+    pub fn parse(input: &str) -> Result<Args, UsageError> {
+        let input = input.trim();
+        let (name, rest) = match input.find(char::is_whitespace) {
+            Some(i) => (&input[..i], input[i..].trim_start()),
+            None => (input, ""),
+        };
+
+        let mut args = Args {
+            name: name.to_lowercase(),
+            rest: rest.to_string(),
+            ..Default::default()
+        };
+        let mut flags_end = false;
+        for token in tokenize(rest)? {
+            if flags_end || !is_flag(&token) {
+                args.positional.push(token);
+            } else if token == "--" {
+                flags_end = true;
+            } else {
+                let flag = token.trim_start_matches('-');
+                match flag.split_once('=') {
+                    Some((k, v)) => args.flags.insert(k.to_string(), Some(v.to_string())),
+                    None => args.flags.insert(flag.to_string(), None),
+                };
+            }
+        }
+        Ok(args)
+    }
+
+    /// 将工具调用的 JSON 参数转换为命令参数
+    ///
+    /// 所有字段均作为选项传入，schema 中 required 的字段按顺序作为位置参数
+    pub fn from_json(name: &str, args: &Value, schema: &Value) -> Args {
+        let to_string = |v: &Value| match v {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        };
+        let mut positional = Vec::new();
+        let mut flags = HashMap::new();
+        if let Some(obj) = args.as_object() {
+            let required = schema["required"].as_array().into_iter().flatten();
+            for key in required.filter_map(Value::as_str) {
+                if let Some(v) = obj.get(key) {
+                    positional.push(to_string(v));
+                }
+            }
+            for (k, v) in obj {
+                flags.insert(k.clone(), Some(to_string(v)));
+            }
+        }
+        Args {
+            name: name.to_lowercase(),
+            rest: positional.join(" "),
+            positional,
+            flags,
+        }
+    }
+
+    /// 命令名称 (小写)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 命令名之后的原始文本
+    pub fn rest(&self) -> &str {
+        &self.rest
+    }
+
+    /// 所有位置参数
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// 位置参数个数
+    pub fn len(&self) -> usize {
+        self.positional.len()
+    }
+
+    /// 是否没有位置参数
+    pub fn is_empty(&self) -> bool {
+        self.positional.is_empty()
+    }
+
+    /// 读取第 index 个位置参数，不存在时返回 None
+    pub fn get<T: FromStr>(&self, index: usize) -> Result<Option<T>, UsageError> {
+        self.positional
+            .get(index)
+            .map(|v| {
+                v.parse::<T>()
+                    .map_err(|_| UsageError(format!("无效的参数: {}", v)))
+            })
+            .transpose()
+    }
+
+    /// 读取第 index 个位置参数，不存在时报错
+    pub fn require<T: FromStr>(&self, index: usize, what: &str) -> Result<T, UsageError> {
+        self.get(index)?
+            .ok_or_else(|| UsageError(format!("缺少参数: {}", what)))
+    }
+
+    /// 是否包含开关 (`--name` 或 `-n`)
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+
+    /// 读取选项值 (`--name=value`)
+    pub fn option<T: FromStr>(&self, name: &str) -> Result<Option<T>, UsageError> {
+        match self.flags.get(name) {
+            Some(Some(v)) => v
+                .parse::<T>()
+                .map(Some)
+                .map_err(|_| UsageError(format!("无效的选项 --{}: {}", name, v))),
+            Some(None) => Err(UsageError(format!("选项 --{} 需要一个值", name))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// 以 - 开头且不是负数的参数视为开关
+fn is_flag(token: &str) -> bool {
+    token.len() > 1 && token.starts_with('-') && token.parse::<f64>().is_err()
+}
+
+/// 按空白切分，支持 "" '' “” 引号
+fn tokenize(input: &str) -> Result<Vec<String>, UsageError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut quote: Option<char> = None;
+
+    for c in input.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None => match c {
+                '"' | '\'' => {
+                    quote = Some(c);
+                    in_token = true;
+                }
+                '“' => {
+                    quote = Some('”');
+                    in_token = true;
+                }
+                c if c.is_whitespace() => {
+                    if in_token {
+                        tokens.push(std::mem::take(&mut current));
+                        in_token = false;
+                    }
+                }
+                c => {
+                    current.push(c);
+                    in_token = true;
+                }
+            },
+        }
+    }
+    if quote.is_some() {
+        return Err(UsageError("引号未闭合".to_string()));
+    }
+    if in_token {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = Args::parse("Remind 10m \"喝 水\" --private --at=12:00 -5").unwrap();
+        assert_eq!(args.name(), "remind");
+        assert_eq!(args.rest(), "10m \"喝 水\" --private --at=12:00 -5");
+        assert_eq!(args.positional(), ["10m", "喝 水", "-5"]);
+        assert!(args.flag("private"));
+        assert_eq!(
+            args.option::<String>("at").unwrap().as_deref(),
+            Some("12:00")
+        );
+        assert_eq!(args.get::<i32>(2).unwrap(), Some(-5));
+        assert!(args.get::<i32>(0).is_err());
+        assert!(args.require::<String>(3, "内容").is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let schema = serde_json::json!({ "required": ["prompt", "count"] });
+        let args = Args::from_json(
+            "seedream",
+            &serde_json::json!({ "count": 2, "prompt": "一只猫", "hd": true }),
+            &schema,
+        );
+        assert_eq!(args.positional(), ["一只猫", "2"]);
+        assert_eq!(args.rest(), "一只猫 2");
+        assert_eq!(args.option::<bool>("hd").unwrap(), Some(true));
+    }
+
+    #[test]
+    fn test_parse_quotes() {
+        let args = Args::parse("seedream “一只 猫” -- --raw").unwrap();
+        assert_eq!(args.positional(), ["一只 猫", "--raw"]);
+        assert!(Args::parse("seedream \"未闭合").is_err());
+    }
+}
@@ -0,0 +1,492 @@
+use crate::commands::*;
+use crate::message::synthetic_event;
+use crate::secrets::error;
+use crate::user_manager::{ChatRole, Message as OpenaiMsg, MessageContent};
+use kovi::chrono::{Timelike, Utc};
+use kovi::croner::Cron;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// 定时任务发送的目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Target {
+    Group(i64),
+    User(i64),
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Group(id) => write!(f, "group:{}", id),
+            Target::User(id) => write!(f, "user:{}", id),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("无效的目标: {}，格式如 group:123 或 user:456", s);
+        let (kind, id) = s.split_once([':', '：']).ok_or_else(err)?;
+        let id = id.trim().parse().map_err(|_| err())?;
+        match kind.trim().to_lowercase().as_str() {
+            "group" | "g" | "群" => Ok(Target::Group(id)),
+            "user" | "u" | "私聊" => Ok(Target::User(id)),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl TryFrom<String> for Target {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Target> for String {
+    fn from(value: Target) -> Self {
+        value.to_string()
+    }
+}
+
+/// 定时任务的动作
+pub enum Action<'a> {
+    /// 发送固定消息
+    Text(&'a str),
+    /// 按人设由 AI 生成消息
+    Prompt(&'a str),
+    /// 执行命令
+    Command(&'a str),
+}
+
+/// 任务的创建者，command 动作以其身份执行
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Creator {
+    pub id: i64,
+    /// 创建时的角色，执行时不会高于其当前角色
+    pub role: Role,
+}
+
+/// 定时任务，text、prompt、command 三者选一
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Broadcast {
+    /// cron 表达式，如 "0 8 * * *"
+    pub cron: String,
+    /// 发送的目标
+    pub targets: Vec<Target>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// 创建者，配置文件中的任务默认为所有者
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creator: Option<Creator>,
+}
+
+impl Broadcast {
+    pub fn action(&self) -> Result<Action<'_>, String> {
+        match (&self.text, &self.prompt, &self.command) {
+            (Some(t), None, None) => Ok(Action::Text(t)),
+            (None, Some(p), None) => Ok(Action::Prompt(p)),
+            (None, None, Some(c)) => Ok(Action::Command(c)),
+            _ => Err("text、prompt 与 command 必须且只能设置一个".to_string()),
+        }
+    }
+
+    pub fn schedule(&self) -> Result<Cron, String> {
+        Cron::new(&self.cron)
+            .parse()
+            .map_err(|e| format!("无效的 cron 表达式 {}: {}", self.cron, e))
+    }
+
+    /// 检查任务是否有效
+    pub fn check(&self) -> Result<(), String> {
+        self.schedule()?;
+        self.action()?;
+        if self.targets.is_empty() {
+            return Err("没有发送目标".to_string());
+        }
+        Ok(())
+    }
+
+    /// 简短描述
+    fn describe(&self) -> String {
+        let action = match self.action() {
+            Ok(Action::Text(t)) => format!("发送 {}", t),
+            Ok(Action::Prompt(p)) => format!("生成 {}", p),
+            Ok(Action::Command(c)) => format!("执行 {}", c),
+            Err(e) => e,
+        };
+        let targets: Vec<String> = self.targets.iter().map(|t| t.to_string()).collect();
+        format!("[{}] -> {} {}", self.cron, targets.join(","), action)
+    }
+}
+
+/// 后台按 cron 表达式执行定时任务，精确到分钟
+pub fn spawn_scheduler(res: Arc<Resources>, registry: Arc<CommandRegistry>) {
+    kovi::spawn(async move {
+        loop {
+            // 等待到下一分钟开始
+            let secs = 60 - Utc::now().second() as u64;
+            kovi::tokio::time::sleep(Duration::from_secs(secs)).await;
+
+            let config = res.config.load();
+            let Some(now) = config
+                .local_time(Utc::now())
+                .with_second(0)
+                .and_then(|t| t.with_nanosecond(0))
+            else {
+                continue;
+            };
+            for (name, job) in all_jobs(&res, &config).await {
+                let matched = job
+                    .schedule()
+                    .is_ok_and(|c| c.is_time_matching(&now).unwrap_or(false));
+                if matched {
+                    info!("Run broadcast {}", name);
+                    let res = Arc::clone(&res);
+                    let registry = Arc::clone(&registry);
+                    kovi::spawn(async move { run(&res, &registry, &job).await });
+                }
+            }
+        }
+    });
+}
+
+/// 配置文件与数据库中的所有任务，配置中的任务编号以 c 开头
+async fn all_jobs(res: &Resources, config: &Config) -> Vec<(String, Broadcast)> {
+    let mut jobs: Vec<(String, Broadcast)> = config
+        .broadcasts
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, job)| (format!("c{}", i + 1), config_job(res, job)))
+        .collect();
+    match res.user_manager.list_broadcasts().await {
+        Ok(v) => jobs.extend(v.into_iter().map(|(id, job)| (id.to_string(), job))),
+        Err(e) => error!("Failed to load broadcasts: {}", e),
+    }
+    jobs
+}
+
+/// 配置文件中的任务，未指定创建者时以所有者身份执行
+fn config_job(res: &Resources, job: &Broadcast) -> Broadcast {
+    let mut job = job.clone();
+    if job.creator.is_none() {
+        job.creator = res.bot.get_main_admin().ok().map(|id| Creator {
+            id,
+            role: Role::Owner,
+        });
+    }
+    job
+}
+
+/// 执行任务，跳过已关闭或静音的群
+async fn run(res: &Resources, registry: &CommandRegistry, job: &Broadcast) {
+    let action = match job.action() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Invalid broadcast: {}", e);
+            return;
+        }
+    };
+    for target in &job.targets {
+        let group = match target {
+            Target::Group(id) => match res.user_manager.get_group(*id).await {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    error!("Failed to load settings of group {}: {}", id, e);
+                    continue;
+                }
+            },
+            Target::User(_) => None,
+        };
+        if group
+            .as_ref()
+            .is_some_and(|g| !g.enabled() || g.muted(Utc::now()))
+        {
+            info!("Skip broadcast to {}", target);
+            continue;
+        }
+        if let Err(e) = send(res, registry, job, *target, &action, group.as_ref()).await {
+            error!("Failed to broadcast to {}: {}", target, e);
+        }
+    }
+}
+
+/// 向单个目标执行动作
+async fn send(
+    res: &Resources,
+    registry: &CommandRegistry,
+    job: &Broadcast,
+    target: Target,
+    action: &Action<'_>,
+    group: Option<&GroupSettings>,
+) -> Result<(), Error> {
+    let persona = group.and_then(|g| g.persona.as_deref());
+    let message = match action {
+        Action::Text(text) => KoviMsg::from(*text),
+        Action::Prompt(prompt) => {
+            let mut messages = vec![OpenaiMsg {
+                role: ChatRole::User,
+                content: MessageContent::Text(prompt.to_string()),
+            }];
+            let reply = res
+                .client
+                .chat_without_tools(&mut messages, persona)
+                .await?;
+            let MessageContent::Text(text) = reply.content else {
+                return Err(anyhow!("Reply contain Multi"));
+            };
+            res.renderer.to_message(text).await
+        }
+        Action::Command(command) => {
+            // 以创建者的角色执行，角色被降低时以当前角色为准
+            let creator = job
+                .creator
+                .ok_or_else(|| anyhow!("Broadcast has no creator, please add it again"))?;
+            let role = creator
+                .role
+                .min(res.user_manager.get_role(creator.id).await?);
+            // 群聊中以创建者的会话执行，私聊中以目标用户的会话执行
+            let (group_id, user_id) = match target {
+                Target::Group(id) => (Some(id), creator.id),
+                Target::User(id) => (None, id),
+            };
+            let event = synthetic_event(&res.bot, group_id, user_id, command)?;
+            return registry
+                .run_scheduled(command, &event, role, group, res)
+                .await
+                .map_err(Error::msg);
+        }
+    };
+    match target {
+        Target::Group(id) => res.bot.send_group_msg(id, message),
+        Target::User(id) => res.bot.send_private_msg(id, message),
+    }
+    Ok(())
+}
+
+/// broadcast 命令
+pub struct BroadcastCommand;
+
+#[async_trait]
+impl Command for BroadcastCommand {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    fn description(&self) -> &'static str {
+        "管理定时发送的消息"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["定时"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "broadcast list | broadcast add <cron> <目标> <text|prompt|command> <内容> | broadcast remove <编号> | broadcast run <编号>"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "broadcast add \"0 8 * * *\" here prompt 用你的口吻向大家道早安",
+            "broadcast add \"30 12 * * 1-5\" group:123456 command image",
+            "broadcast add \"0 22 * * *\" user:10001,user:10002 text 该睡觉了",
+            "broadcast list",
+            "broadcast remove 2",
+        ]
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        match ctx.args.positional().first().map(String::as_str) {
+            None | Some("list" | "列表") => list(ctx).await,
+            Some("add" | "添加") => add(ctx).await,
+            Some("remove" | "删除") => remove(ctx).await,
+            Some("run" | "执行") => run_now(ctx).await,
+            Some(other) => Err(UsageError(format!("未知操作: {}", other)).into()),
+        }
+    }
+}
+
+/// 列出任务
+async fn list(ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+    let config = Arc::clone(&ctx.config);
+    let mut lines: Vec<String> = config
+        .broadcasts
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, job)| format!("#c{} {}", i + 1, job.describe()))
+        .collect();
+    for (id, job) in ctx.resources.user_manager.list_broadcasts().await? {
+        lines.push(format!("#{} {}", id, job.describe()));
+    }
+    if lines.is_empty() {
+        ctx.reply("没有定时任务");
+    } else {
+        ctx.reply(format!("定时任务:\n{}", lines.join("\n")));
+    }
+    Ok(true)
+}
+
+/// 添加任务，目标为 here 时发送到当前会话
+async fn add(ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+    let cron: String = ctx.args.require(1, "cron 表达式")?;
+    let targets: String = ctx.args.require(2, "目标")?;
+    let kind: String = ctx.args.require(3, "动作")?;
+    let content = ctx.args.positional().get(4..).unwrap_or_default().join(" ");
+    if content.is_empty() {
+        return Err(UsageError("缺少内容".to_string()).into());
+    }
+
+    let targets = targets
+        .split([',', '，'])
+        .map(|t| match t {
+            "here" | "这里" => Ok(match ctx.msg.group_id {
+                Some(id) => Target::Group(id),
+                None => Target::User(ctx.msg.user_id),
+            }),
+            t => t.parse(),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(UsageError)?;
+    let mut job = Broadcast {
+        cron,
+        targets,
+        text: None,
+        prompt: None,
+        command: None,
+        creator: Some(Creator {
+            id: ctx.user.id,
+            role: ctx.role,
+        }),
+    };
+    match kind.as_str() {
+        "text" | "消息" => job.text = Some(content),
+        "prompt" | "生成" => job.prompt = Some(content),
+        "command" | "命令" => job.command = Some(content),
+        _ => return Err(UsageError(format!("未知动作: {}", kind)).into()),
+    }
+    job.check().map_err(UsageError)?;
+
+    let id = ctx.resources.user_manager.add_broadcast(&job).await?;
+    info!("User {} added broadcast {}", ctx.user.id, id);
+    ctx.reply(format!("已添加定时任务 #{} {}", id, job.describe()));
+    Ok(true)
+}
+
+/// 删除任务，配置文件中的任务需修改配置
+async fn remove(ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+    let id: String = ctx.args.require(1, "编号")?;
+    let id = id.trim_start_matches('#');
+    if id.starts_with('c') {
+        ctx.reply("配置文件中的任务请修改 config.toml");
+        return Ok(true);
+    }
+    let id: i64 = id
+        .parse()
+        .map_err(|_| UsageError(format!("无效的编号: {}", id)))?;
+    if ctx.resources.user_manager.remove_broadcast(id).await? {
+        info!("User {} removed broadcast {}", ctx.user.id, id);
+        ctx.reply(format!("已删除定时任务 #{}", id));
+    } else {
+        ctx.reply(format!("没有找到定时任务 #{}", id));
+    }
+    Ok(true)
+}
+
+/// 立即执行任务，用于测试
+async fn run_now(ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+    let id: String = ctx.args.require(1, "编号")?;
+    let id = id.trim_start_matches('#');
+    let config = Arc::clone(&ctx.config);
+    let job = if let Some(index) = id.strip_prefix('c') {
+        index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| config.broadcasts.as_ref()?.get(i.checked_sub(1)?))
+            .map(|job| config_job(ctx.resources, job))
+    } else {
+        let jobs = ctx.resources.user_manager.list_broadcasts().await?;
+        jobs.into_iter()
+            .find(|(job_id, _)| job_id.to_string() == id)
+            .map(|(_, job)| job)
+    };
+    let Some(job) = job else {
+        ctx.reply(format!("没有找到定时任务 #{}", id));
+        return Ok(true);
+    };
+
+    run(ctx.resources, ctx.registry, &job).await;
+    ctx.reply(format!("已执行定时任务 #{}", id));
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::broadcast::*;
+
+    #[test]
+    fn test_broadcast() {
+        let job: Broadcast = kovi::toml::from_str(
+            r#"
+            cron = "0 8 * * *"
+            targets = ["group:123", "user:456"]
+            prompt = "向大家道早安"
+            "#,
+        )
+        .unwrap();
+        assert!(job.check().is_ok());
+        assert_eq!(job.targets, [Target::Group(123), Target::User(456)]);
+        assert!(matches!(job.action(), Ok(Action::Prompt(_))));
+        assert_eq!(job.creator, None);
+
+        let owned: Broadcast = kovi::toml::from_str(
+            r#"
+            cron = "0 8 * * *"
+            targets = ["group:123"]
+            command = "status"
+            creator = { id = 10001, role = "admin" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            owned.creator,
+            Some(Creator {
+                id: 10001,
+                role: Role::Admin
+            })
+        );
+
+        let eight =
+            kovi::chrono::DateTime::parse_from_rfc3339("2026-10-18T08:00:00+08:00").unwrap();
+        assert!(job.schedule().unwrap().is_time_matching(&eight).unwrap());
+
+        let bad = Broadcast {
+            cron: "every morning".to_string(),
+            text: Some("早".to_string()),
+            command: Some("image".to_string()),
+            ..job
+        };
+        assert!(bad.schedule().is_err());
+        assert!(bad.action().is_err());
+        assert!("channel:1".parse::<Target>().is_err());
+    }
+}
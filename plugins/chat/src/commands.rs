@@ -1,11 +1,36 @@
-pub use crate::user_manager::User;
-pub use kovi::log::info;
-pub use kovi::{Message as KoviMsg, MsgEvent};
-use std::collections::HashMap;
-pub use std::path::PathBuf;
+pub use crate::args::{Args, UsageError};
+use crate::broadcast::BroadcastCommand;
+pub use crate::config::{Config, ConfigStore};
+pub use crate::cooldown::{Cooldown, Scope};
+use crate::cooldown::{CooldownTracker, format_duration};
+pub use crate::group_settings::GroupSettings;
+use crate::group_settings::{
+    ContextScope, GroupCfgCommand, MuteCommand, UnmuteCommand, context_id,
+};
+use crate::mcp_loader::FunctionDef;
+pub use crate::openai_api::OpenaiClient;
+use crate::openai_api::ToolHandler;
+use crate::pending::{PendingReplies, StopCommand};
+pub use crate::permission::{Permission, Role};
+use crate::reload::ReloadCommand;
+use crate::reminder::RemindCommand;
+pub use crate::render::MarkdownRenderer;
+use crate::search::SearchCommand;
+pub use crate::secrets::Secrets;
+pub(crate) use crate::secrets::info;
+use crate::secrets::{error, warn};
+use crate::summary::SummaryCommand;
+pub use crate::user_manager::{User, UserManager};
+pub use anyhow::{Error, anyhow};
+pub use async_trait::async_trait;
+pub use kovi::{Message as KoviMsg, MsgEvent, RuntimeBot};
+pub use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+pub use std::path::{Path, PathBuf};
 pub use std::sync::Arc;
 
 /// 命令 Trait
+#[async_trait]
 pub trait Command: Send + Sync {
     /// 命令名称
     fn name(&self) -> &'static str;
@@ -13,105 +38,679 @@ pub trait Command: Send + Sync {
     /// 命令描述
     fn description(&self) -> &'static str;
 
-    /// 执行命令，如果命令匹配返回 true
-    fn execute(
-        &self,
-        text: &str,
-        msg: &Arc<MsgEvent>,
-        user: &mut User,
-        registry: &CommandRegistry,
-        data_dir: PathBuf,
-    ) -> bool;
+    /// 命令别名
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// 命令用法，不含前缀，如 "seedream <提示词>"
+    fn usage(&self) -> &'static str {
+        self.name()
+    }
+
+    /// 使用示例，不含前缀
+    fn examples(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// 帮助中的分类
+    fn category(&self) -> &'static str {
+        "其他"
+    }
+
+    /// 执行命令所需权限
+    fn permission(&self) -> Permission {
+        Permission::User
+    }
+
+    /// 冷却时间
+    fn cooldown(&self) -> Cooldown {
+        Cooldown::default()
+    }
+
+    /// 可用范围
+    fn scope(&self) -> Scope {
+        Scope::Any
+    }
+
+    /// 作为工具提供给 AI 时的参数 JSON Schema，返回 None 时不提供 (默认)
+    ///
+    /// 参数以选项传入，required 中的参数还会按顺序作为位置参数传入
+    fn tool_parameters(&self) -> Option<Value> {
+        None
+    }
+
+    /// 执行命令，返回 Ok(true) 表示已处理，Ok(false) 时交给 AI 回复，执行失败返回 Err
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error>;
+}
+
+/// 命令可访问的运行时资源
+#[derive(Clone)]
+pub struct Resources {
+    /// Kovi 运行时 Bot
+    pub bot: Arc<RuntimeBot>,
+    /// 共享的 HTTP 客户端
+    pub http_client: Arc<reqwest::Client>,
+    /// OpenAI 客户端
+    pub client: Arc<OpenaiClient>,
+    /// 用户管理器
+    pub user_manager: Arc<UserManager>,
+    /// Markdown 渲染器
+    pub renderer: Arc<MarkdownRenderer>,
+    /// 插件配置，可在运行时重载
+    pub config: Arc<ConfigStore>,
+    /// 密钥存储
+    pub secrets: Arc<Secrets>,
+    /// 插件数据目录
+    pub data_path: Arc<PathBuf>,
+    /// 进行中的 AI 回复
+    pub replies: Arc<PendingReplies>,
+}
+
+/// 命令执行上下文
+pub struct CommandContext<'a> {
+    /// 文本信息
+    #[allow(dead_code)] // 供自定义命令使用
+    pub text: &'a str,
+    /// 解析后的命令参数
+    pub args: Args,
+    /// 原始的 MsgEvent
+    pub msg: &'a Arc<MsgEvent>,
+    /// 用户信息，包含 ID 和与 AI 的聊天记录
+    pub user: &'a mut User,
+    /// 用户角色
+    pub role: Role,
+    /// 用户是否为当前群的群主或群管理员
+    pub group_admin: bool,
+    /// 当前群的设置，私聊时为 None
+    pub group: Option<&'a GroupSettings>,
+    /// 命令注册器，用于查看或调用其他命令
+    pub registry: &'a CommandRegistry,
+    /// 此命令的专属储存目录，不会默认创建
+    pub data_dir: PathBuf,
+    /// 作为工具调用时收集的输出
+    output: Option<Vec<String>>,
+    /// 插件配置，为命令开始执行时的快照
+    pub config: Arc<Config>,
+    /// 共享资源，包括 Bot、HTTP 客户端、用户管理器、可重载的配置等
+    pub resources: &'a Resources,
+}
+
+impl CommandContext<'_> {
+    /// 回复消息，作为工具调用时纯文本回复只交给 AI，其余消息照常发送
+    pub fn reply<T: Into<KoviMsg>>(&mut self, msg: T) {
+        let mut msg = msg.into();
+        if crate::message::is_synthetic(self.msg) {
+            msg = crate::message::without_reply(msg);
+        }
+        let Some(output) = &mut self.output else {
+            self.msg.reply(msg);
+            return;
+        };
+
+        let mut text = String::new();
+        let mut text_only = true;
+        for segment in msg.iter() {
+            match segment.type_.as_str() {
+                "text" => text.push_str(segment.data["text"].as_str().unwrap_or_default()),
+                "reply" => {}
+                kind => {
+                    text.push_str(&format!("[{}]", kind));
+                    text_only = false;
+                }
+            }
+        }
+        if text_only {
+            output.push(text);
+        } else {
+            output.push(format!("{}（已发送给用户）", text));
+            self.msg.reply(msg);
+        }
+    }
+
+    /// 是否由 AI 作为工具调用
+    pub fn is_tool_call(&self) -> bool {
+        self.output.is_some()
+    }
 }
 
 /// 命令注册器
 pub struct CommandRegistry {
-    commands: HashMap<String, Box<dyn Command>>,
+    /// 按注册顺序保存的命令
+    commands: Vec<Box<dyn Command>>,
+    /// 名称与别名到命令下标的映射
+    names: HashMap<String, usize>,
+    /// 命令冷却记录
+    cooldowns: CooldownTracker,
 }
 
 impl CommandRegistry {
     /// 创建空注册器
     fn new() -> Self {
         CommandRegistry {
-            commands: HashMap::new(),
+            commands: Vec::new(),
+            names: HashMap::new(),
+            cooldowns: CooldownTracker::default(),
         }
     }
 
-    /// 注册命令
+    /// 注册命令，名称或别名与已注册命令冲突时拒绝注册
     pub fn register<C: Command + 'static>(&mut self, cmd: C) {
-        self.commands.insert(cmd.name().to_string(), Box::new(cmd));
+        if let Err(e) = self.try_register(cmd) {
+            error!("{}", e);
+        }
+    }
+
+    /// 注册命令，返回冲突错误
+    pub fn try_register<C: Command + 'static>(&mut self, cmd: C) -> Result<(), Error> {
+        let keys: Vec<String> = std::iter::once(cmd.name())
+            .chain(cmd.aliases().iter().copied())
+            .map(str::to_lowercase)
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return Err(anyhow!("Invalid command name {:?}", key));
+            }
+            if let Some(&index) = self.names.get(key) {
+                return Err(anyhow!(
+                    "Command {} conflicts with {} on {:?}",
+                    cmd.name(),
+                    self.commands[index].name(),
+                    key
+                ));
+            }
+            if keys[..i].contains(key) {
+                return Err(anyhow!("Command {} repeats {:?}", cmd.name(), key));
+            }
+        }
+
+        let index = self.commands.len();
+        self.commands.push(Box::new(cmd));
+        for key in keys {
+            self.names.insert(key, index);
+        }
+        Ok(())
+    }
+
+    /// 按名称或别名查找命令
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.names
+            .get(&name.to_lowercase())
+            .map(|&i| self.commands[i].as_ref())
+    }
+
+    /// 查找消息对应的命令，返回命令与去除前缀后的文本
+    pub fn find<'t>(&self, text: &'t str, prefix: &str) -> Option<(&dyn Command, &'t str)> {
+        // 没有命令前缀则不是命令
+        let body = text.trim().strip_prefix(prefix)?;
+        let name = body.split_whitespace().next().unwrap_or_default();
+        Some((self.get(name)?, body))
     }
 
     /// 处理消息，返回 true 表示命令已处理，不再 AI 回复
-    pub fn handle(
+    pub async fn handle(
         &self,
         text: &str,
         msg: &Arc<MsgEvent>,
         user: &mut User,
-        data_dir: &PathBuf,
+        role: Role,
+        group: Option<&GroupSettings>,
+        res: &Resources,
     ) -> bool {
-        for cmd in self.commands.values() {
-            if cmd.execute(
-                text,
-                msg,
-                user,
-                self,
-                data_dir.join(cmd.name().replace('/', "_").replace('\0', "")),
-            ) {
-                return true;
+        let config = res.config.load();
+        let prefix = config.command_prefix();
+        let Some((cmd, body)) = self.find(text, prefix) else {
+            return false;
+        };
+
+        let caller = Caller::new(msg, role, group, res);
+        let result = match self.permit(cmd, &caller) {
+            Ok(()) => match Args::parse(body) {
+                Ok(args) => self.run(cmd, text, args, &caller, user, false).await,
+                Err(e) => Err(usage_hint(cmd, prefix, &e)),
+            },
+            Err(e) => {
+                info!("User {} denied to run {}: {}", user.id, cmd.name(), e);
+                Err(e)
+            }
+        };
+        match result {
+            Ok((handled, _)) => handled,
+            Err(e) => {
+                msg.reply(KoviMsg::from(e));
+                true
             }
         }
-        false
     }
 
-    /// 获取所有命令及描述
-    pub fn list_commands(&self) -> Vec<(String, String)> {
-        self.commands
-            .values()
-            .map(|c| (c.name().to_string(), c.description().to_string()))
+    /// 以创建者的角色在指定会话执行命令，用于定时任务，text 为不含前缀的命令
+    pub async fn run_scheduled(
+        &self,
+        text: &str,
+        msg: &Arc<MsgEvent>,
+        role: Role,
+        group: Option<&GroupSettings>,
+        res: &Resources,
+    ) -> Result<(), String> {
+        let name = text.split_whitespace().next().unwrap_or_default();
+        let cmd = self
+            .get(name)
+            .ok_or_else(|| format!("未知命令: {}", name))?;
+        let caller = Caller::new(msg, role, group, res);
+        self.permit(cmd, &caller)?;
+        let args = Args::parse(text).map_err(|e| e.to_string())?;
+        // 使用临时用户，不影响任何人的聊天记录
+        let mut user = User {
+            id: msg.user_id,
+            history: Vec::new(),
+            reasoning: None,
+        };
+        self.run(cmd, text, args, &caller, &mut user, false)
+            .await
+            .map(|_| ())
+    }
+
+    /// 检查调用范围与权限
+    fn permit(&self, cmd: &dyn Command, caller: &Caller<'_>) -> Result<(), String> {
+        cmd.scope().check(caller.msg.group_id)?;
+        if caller.group.is_some_and(|g| !g.allows_command(cmd.name())) {
+            return Err("此命令在本群已禁用".to_string());
+        }
+        if !cmd.permission().allows(caller.role, caller.group_admin) {
+            return Err(format!("权限不足，需要{}权限", cmd.permission()));
+        }
+        Ok(())
+    }
+
+    /// 检查冷却并执行命令，返回是否已处理与工具调用时收集的输出，失败时返回提示
+    async fn run(
+        &self,
+        cmd: &dyn Command,
+        text: &str,
+        args: Args,
+        caller: &Caller<'_>,
+        user: &mut User,
+        tool: bool,
+    ) -> Result<(bool, Vec<String>), String> {
+        let Caller {
+            msg,
+            role,
+            group_admin,
+            group,
+            res,
+        } = *caller;
+        let user_id = user.id;
+
+        // 检查冷却，管理员不受限制
+        let cooldown = if role >= Role::Admin {
+            Cooldown::default()
+        } else {
+            cmd.cooldown()
+        };
+        if let Err(remaining) = self
+            .cooldowns
+            .acquire(cmd.name(), &cooldown, user_id, msg.group_id)
+        {
+            return Err(format!(
+                "命令冷却中，请在 {} 后重试",
+                format_duration(remaining)
+            ));
+        }
+
+        let mut ctx = CommandContext {
+            text,
+            args,
+            msg,
+            user,
+            role,
+            group_admin,
+            group,
+            registry: self,
+            data_dir: res
+                .data_path
+                .join(cmd.name().replace('/', "_").replace('\0', "")),
+            output: tool.then(Vec::new),
+            config: res.config.load(),
+            resources: res,
+        };
+        let result = cmd.execute(&mut ctx).await;
+        let output = ctx.output.take().unwrap_or_default();
+        match result {
+            Ok(true) => Ok((true, output)),
+            Ok(false) => {
+                // 未处理的调用不计入冷却
+                self.cooldowns
+                    .release(cmd.name(), &cooldown, user_id, msg.group_id);
+                Ok((false, output))
+            }
+            Err(e) => {
+                if let Some(e) = e.downcast_ref::<UsageError>() {
+                    self.cooldowns
+                        .release(cmd.name(), &cooldown, user_id, msg.group_id);
+                    let config = res.config.load();
+                    let prefix = config.command_prefix();
+                    Err(usage_hint(cmd, prefix, e))
+                } else {
+                    error!("Command {} failed: {:?}", cmd.name(), e);
+                    Err(format!("命令执行失败: {}", e))
+                }
+            }
+        }
+    }
+
+    /// 按注册顺序获取所有命令
+    pub fn commands(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.iter().map(|c| c.as_ref())
+    }
+}
+
+/// 参数错误时的提示
+fn usage_hint(cmd: &dyn Command, prefix: &str, e: &UsageError) -> String {
+    format!("{}\n用法: {}{}", e, prefix, cmd.usage())
+}
+
+/// 命令调用者
+#[derive(Clone, Copy)]
+struct Caller<'a> {
+    msg: &'a Arc<MsgEvent>,
+    role: Role,
+    group_admin: bool,
+    group: Option<&'a GroupSettings>,
+    res: &'a Resources,
+}
+
+impl<'a> Caller<'a> {
+    fn new(
+        msg: &'a Arc<MsgEvent>,
+        role: Role,
+        group: Option<&'a GroupSettings>,
+        res: &'a Resources,
+    ) -> Self {
+        Caller {
+            msg,
+            role,
+            group_admin: crate::permission::is_group_admin(msg),
+            group,
+            res,
+        }
+    }
+}
+
+/// 将声明了 tool_parameters 的命令作为工具提供给 AI
+pub struct CommandTools<'a> {
+    registry: &'a CommandRegistry,
+    caller: Caller<'a>,
+    user_id: i64,
+}
+
+impl<'a> CommandTools<'a> {
+    pub fn new(
+        registry: &'a CommandRegistry,
+        msg: &'a Arc<MsgEvent>,
+        user_id: i64,
+        role: Role,
+        group: Option<&'a GroupSettings>,
+        res: &'a Resources,
+    ) -> Self {
+        CommandTools {
+            registry,
+            caller: Caller::new(msg, role, group, res),
+            user_id,
+        }
+    }
+}
+
+#[async_trait]
+impl ToolHandler for CommandTools<'_> {
+    fn definitions(&self) -> Vec<FunctionDef> {
+        // 只提供当前用户有权使用的命令
+        self.registry
+            .commands()
+            .filter(|cmd| self.registry.permit(*cmd, &self.caller).is_ok())
+            .filter_map(|cmd| {
+                Some(FunctionDef {
+                    name: cmd.name().to_string(),
+                    description: cmd.description().to_string(),
+                    parameters: cmd.tool_parameters()?,
+                })
+            })
             .collect()
     }
+
+    async fn call(&self, name: &str, arguments: Value) -> Result<String, Error> {
+        let Some((cmd, schema)) = self
+            .registry
+            .get(name)
+            .and_then(|cmd| Some((cmd, cmd.tool_parameters()?)))
+        else {
+            return Err(anyhow!("未知工具: {}", name));
+        };
+        self.registry
+            .permit(cmd, &self.caller)
+            .map_err(|e| anyhow!(e))?;
+
+        let args = Args::from_json(cmd.name(), &arguments, &schema);
+        let text = format!("{} {}", cmd.name(), args.rest());
+        // 工具调用使用临时用户，不会修改聊天记录
+        let mut user = User {
+            id: self.user_id,
+            history: Vec::new(),
+            reasoning: None,
+        };
+        info!(
+            "User {} called command {} as tool",
+            self.user_id,
+            cmd.name()
+        );
+        match self
+            .registry
+            .run(cmd, text.trim(), args, &self.caller, &mut user, true)
+            .await
+        {
+            Ok((true, output)) if output.is_empty() => Ok("命令已执行".to_string()),
+            Ok((true, output)) => Ok(output.join("\n")),
+            Ok((false, _)) => Ok("命令未执行".to_string()),
+            Err(e) => Err(anyhow!(e)),
+        }
+    }
+
+    fn progress(&self, text: &str) {
+        self.caller.msg.reply(text);
+    }
 }
 
-/// --------- 内置命令 ---------
+// --------- 内置命令 ---------
+
+/// 每页帮助显示的命令数
+const HELP_PAGE_SIZE: usize = 15;
 
 /// help 命令，不持有注册器引用
 pub struct HelpCommand;
 
+#[async_trait]
 impl Command for HelpCommand {
     fn name(&self) -> &'static str {
         "help"
     }
 
     fn description(&self) -> &'static str {
-        "显示所有可用命令及说明"
+        "显示可用命令，或查看某个命令的详细用法"
     }
 
-    fn execute(
-        &self,
-        text: &str,
-        msg: &Arc<MsgEvent>,
-        _user: &mut User,
-        registry: &CommandRegistry,
-        _data_dir: PathBuf,
-    ) -> bool {
-        if text.trim() == "help" {
-            let commands = registry.list_commands();
-            let mut output = String::from("可用命令:\n");
-            for (name, desc) in commands {
-                output.push_str(&format!("{}: {}\n", name, desc));
+    fn aliases(&self) -> &'static [&'static str] {
+        &["帮助"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "help [命令|页码]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["help seedream", "help 2"]
+    }
+
+    fn category(&self) -> &'static str {
+        "基础"
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let prefix = ctx.config.command_prefix();
+        // 与执行时的检查相同，包括本群禁用的命令
+        let caller = Caller {
+            msg: ctx.msg,
+            role: ctx.role,
+            group_admin: ctx.group_admin,
+            group: ctx.group,
+            res: ctx.resources,
+        };
+        let visible = |cmd: &&dyn Command| ctx.registry.permit(*cmd, &caller).is_ok();
+
+        // 查看单个命令
+        let first = ctx.args.positional().first();
+        if let Some(name) = first.filter(|v| v.parse::<usize>().is_err()) {
+            let cmd = ctx
+                .registry
+                .get(name)
+                .filter(visible)
+                .ok_or_else(|| UsageError(format!("未知命令: {}", name)))?;
+            ctx.reply(KoviMsg::from(command_detail(cmd, prefix)));
+            return Ok(true);
+        }
+
+        // 按分类分组并排序
+        let mut groups: BTreeMap<&str, Vec<&dyn Command>> = BTreeMap::new();
+        for cmd in ctx.registry.commands().filter(visible) {
+            groups.entry(cmd.category()).or_default().push(cmd);
+        }
+        for cmds in groups.values_mut() {
+            cmds.sort_by_key(|c| c.name());
+        }
+        let pages = help_pages(&groups, prefix);
+        let footer = |page: usize| {
+            format!(
+                "\n第 {}/{} 页，发送 {}help <页码> 查看其他页",
+                page,
+                pages.len(),
+                prefix
+            )
+        };
+
+        // 指定页码
+        if let Some(page) = ctx.args.get::<usize>(0)? {
+            let text = pages
+                .get(page.wrapping_sub(1))
+                .ok_or_else(|| UsageError(format!("页码超出范围 (共 {} 页)", pages.len())))?;
+            ctx.reply(KoviMsg::from(format!("{}{}", text, footer(page))));
+            return Ok(true);
+        }
+        if pages.len() <= 1 {
+            ctx.reply(KoviMsg::from(pages.concat().trim_end().to_string()));
+            return Ok(true);
+        }
+
+        // 内容较长时依次尝试图片、合并转发、分页
+        match ctx
+            .resources
+            .renderer
+            .to_image(help_markdown(&groups, prefix))
+            .await
+        {
+            Ok(v) => {
+                ctx.reply(v);
+                return Ok(true);
             }
-            let reply = KoviMsg::from(&output);
-            msg.reply(reply);
-            true
-        } else {
-            false
+            Err(e) => warn!("Failed to render help: {}", e),
+        }
+        let nodes = pages.iter().map(KoviMsg::from).collect();
+        match crate::message::reply_forward(&ctx.resources.bot, ctx.msg, nodes).await {
+            Ok(()) => return Ok(true),
+            Err(e) => warn!("{}", e),
+        }
+        ctx.reply(KoviMsg::from(format!("{}{}", pages[0], footer(1))));
+        Ok(true)
+    }
+}
+
+/// 单个命令的详细说明
+fn command_detail(cmd: &dyn Command, prefix: &str) -> String {
+    let mut output = format!("{}{} - {}", prefix, cmd.name(), cmd.description());
+    output.push_str(&format!("\n用法: {}{}", prefix, cmd.usage()));
+    if !cmd.aliases().is_empty() {
+        output.push_str(&format!("\n别名: {}", cmd.aliases().join(", ")));
+    }
+    if !cmd.examples().is_empty() {
+        output.push_str("\n示例:");
+        for example in cmd.examples() {
+            output.push_str(&format!("\n  {}{}", prefix, example));
+        }
+    }
+    if cmd.permission() != Permission::User {
+        output.push_str(&format!("\n权限: {}", cmd.permission()));
+    }
+    let cooldown = cmd.cooldown();
+    let limits: Vec<String> = [
+        ("每人", cooldown.user),
+        ("每群", cooldown.group),
+        ("全局", cooldown.global),
+    ]
+    .into_iter()
+    .filter_map(|(k, v)| Some(format!("{} {}", k, format_duration(v?))))
+    .collect();
+    if !limits.is_empty() {
+        output.push_str(&format!("\n冷却: {}", limits.join("，")));
+    }
+    output
+}
+
+/// 将分组后的命令分页，跨页时重复分类标题
+fn help_pages(groups: &BTreeMap<&str, Vec<&dyn Command>>, prefix: &str) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut page = String::new();
+    let mut count = 0;
+    for (category, cmds) in groups {
+        for (i, cmd) in cmds.iter().enumerate() {
+            if count == HELP_PAGE_SIZE {
+                pages.push(std::mem::take(&mut page));
+                count = 0;
+            }
+            if i == 0 || count == 0 {
+                page.push_str(&format!("【{}】\n", category));
+            }
+            page.push_str(&format!(
+                "{}{}: {}\n",
+                prefix,
+                cmd.name(),
+                cmd.description()
+            ));
+            count += 1;
+        }
+    }
+    if !page.is_empty() || pages.is_empty() {
+        pages.push(page);
+    }
+    pages
+}
+
+/// 渲染为图片时使用的 Markdown
+fn help_markdown(groups: &BTreeMap<&str, Vec<&dyn Command>>, prefix: &str) -> String {
+    let mut output = String::from("# 可用命令\n");
+    for (category, cmds) in groups {
+        output.push_str(&format!("\n## {}\n\n", category));
+        for cmd in cmds {
+            output.push_str(&format!(
+                "- `{}{}` {}\n",
+                prefix,
+                cmd.name(),
+                cmd.description()
+            ));
         }
     }
+    output
 }
 /// clear 命令
 pub struct ClearCommand;
 
+#[async_trait]
 impl Command for ClearCommand {
     fn name(&self) -> &'static str {
         "clear"
@@ -121,23 +720,266 @@ impl Command for ClearCommand {
         "清空用户对话记录（此操作不可恢复！）"
     }
 
-    fn execute(
-        &self,
-        text: &str,
-        msg: &Arc<MsgEvent>,
-        user: &mut User,
-        _registry: &CommandRegistry,
-        _data_dir: PathBuf,
-    ) -> bool {
-        if text.trim() == "clear" {
-            user.history.clear();
-            info!("User {} cleared history", user.id);
-            let reply = KoviMsg::from("历史记录已清理");
-            msg.reply(reply);
-            true
+    fn aliases(&self) -> &'static [&'static str] {
+        &["清空"]
+    }
+
+    fn category(&self) -> &'static str {
+        "基础"
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let cleared = clear_history(
+            &ctx.resources.user_manager,
+            &ctx.config,
+            ctx.msg.group_id,
+            ctx.role,
+            ctx.group_admin,
+            ctx.user,
+        )
+        .await?;
+        let Some(cleared) = cleared else {
+            ctx.reply(format!(
+                "本群共享聊天记录，清空需要{}权限",
+                Permission::GroupAdmin
+            ));
+            return Ok(true);
+        };
+        info!("User {} cleared history of {}", ctx.user.id, cleared);
+        let reply = if cleared == ctx.user.id {
+            KoviMsg::from("历史记录已清理")
         } else {
-            false
+            KoviMsg::from("本群共享的历史记录已清理")
+        };
+        ctx.reply(reply);
+        Ok(true)
+    }
+}
+
+/// 清空聊天记录，返回被清空记录的 ID，权限不足时返回 None
+///
+/// 群聊使用全群共享的上下文时清空群的记录，需要群管理权限；否则清空 user，由调用方保存
+async fn clear_history(
+    user_manager: &UserManager,
+    config: &Config,
+    group_id: Option<i64>,
+    role: Role,
+    group_admin: bool,
+    user: &mut User,
+) -> Result<Option<i64>, Error> {
+    let shared = match group_id {
+        Some(group_id) => {
+            let settings = user_manager.get_group(group_id).await?;
+            (settings.context(config) == ContextScope::Group).then(|| context_id(group_id))
         }
+        None => None,
+    };
+    let Some(id) = shared else {
+        user.history.clear();
+        user.reasoning = None;
+        return Ok(Some(user.id));
+    };
+    if !Permission::GroupAdmin.allows(role, group_admin) {
+        return Ok(None);
+    }
+    // 与 AI 回复使用同一把锁，避免进行中的回复写回旧记录
+    let _lock = user_manager.lock_context(id).await;
+    let mut context = user_manager.load_user(id).await?;
+    context.history.clear();
+    context.reasoning = None;
+    user_manager.save_user(&context).await?;
+    Ok(Some(id))
+}
+
+/// think 命令
+pub struct ThinkCommand;
+
+#[async_trait]
+impl Command for ThinkCommand {
+    fn name(&self) -> &'static str {
+        "think"
+    }
+
+    fn description(&self) -> &'static str {
+        "查看上一条回复的思考过程"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["思考"]
+    }
+
+    fn category(&self) -> &'static str {
+        "基础"
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let reply = match &ctx.user.reasoning {
+            Some(v) => KoviMsg::from(format!("思考过程:\n{}", v)),
+            None => KoviMsg::from("上一条回复没有思考过程"),
+        };
+        ctx.reply(reply);
+        Ok(true)
+    }
+}
+
+/// 从 At 或第一个参数中获取目标用户，返回用户 ID 与后续参数的起始位置
+fn target_user(ctx: &CommandContext<'_>) -> Result<(i64, usize), Error> {
+    let origin_msg = crate::message::OneBotMessage::from_json(&ctx.msg.original_json)?;
+    if let Some(id) = origin_msg
+        .find_at()
+        .into_iter()
+        .find(|&id| id != ctx.msg.self_id)
+    {
+        return Ok((id, 0));
+    }
+    Ok((ctx.args.require(0, "QQ 号或 @用户")?, 1))
+}
+
+/// 判断操作者能否修改目标用户的角色
+fn check_role_change(ctx: &CommandContext<'_>, target: i64, current: Role) -> Result<(), Error> {
+    if target == ctx.user.id {
+        return Err(anyhow!("不能修改自己的角色"));
+    }
+    if current >= ctx.role {
+        return Err(anyhow!("不能修改{}的角色", current));
+    }
+    Ok(())
+}
+
+/// grant 命令
+pub struct GrantCommand;
+
+#[async_trait]
+impl Command for GrantCommand {
+    fn name(&self) -> &'static str {
+        "grant"
+    }
+
+    fn description(&self) -> &'static str {
+        "设置用户角色 (banned/user/trusted/admin)"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["授权"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "grant <QQ号|@用户> <角色>"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["grant 123456 trusted", "grant @用户 banned"]
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let (target, next) = target_user(ctx)?;
+        let role: Role = ctx.args.require(next, "角色")?;
+        if role == Role::Owner {
+            return Err(anyhow!("所有者由 Kovi 主管理员配置决定"));
+        }
+        if role >= ctx.role {
+            return Err(anyhow!("只能授予低于自己的角色"));
+        }
+        let current = ctx.resources.user_manager.get_role(target).await?;
+        check_role_change(ctx, target, current)?;
+
+        ctx.resources.user_manager.set_role(target, role).await?;
+        info!(
+            "User {} set role of {} to {}",
+            ctx.user.id,
+            target,
+            role.as_str()
+        );
+        ctx.reply(KoviMsg::from(format!("已将 {} 设为{}", target, role)));
+        Ok(true)
+    }
+}
+
+/// revoke 命令
+pub struct RevokeCommand;
+
+#[async_trait]
+impl Command for RevokeCommand {
+    fn name(&self) -> &'static str {
+        "revoke"
+    }
+
+    fn description(&self) -> &'static str {
+        "撤销用户角色，恢复为普通用户"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["撤销"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "revoke <QQ号|@用户>"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let (target, _) = target_user(ctx)?;
+        let current = ctx.resources.user_manager.get_role(target).await?;
+        check_role_change(ctx, target, current)?;
+
+        ctx.resources
+            .user_manager
+            .set_role(target, Role::User)
+            .await?;
+        info!("User {} revoked role of {}", ctx.user.id, target);
+        ctx.reply(KoviMsg::from(format!("已将 {} 恢复为普通用户", target)));
+        Ok(true)
+    }
+}
+
+/// roles 命令
+pub struct RolesCommand;
+
+#[async_trait]
+impl Command for RolesCommand {
+    fn name(&self) -> &'static str {
+        "roles"
+    }
+
+    fn description(&self) -> &'static str {
+        "查看所有非普通用户的角色"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["角色"]
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let roles = ctx.resources.user_manager.list_roles().await?;
+        let mut output = String::from("用户角色:");
+        for (id, role) in roles {
+            output.push_str(&format!("\n{}: {}", id, role));
+        }
+        ctx.reply(KoviMsg::from(output));
+        Ok(true)
     }
 }
 
@@ -146,7 +988,126 @@ impl Default for CommandRegistry {
     fn default() -> Self {
         let mut registry = CommandRegistry::new();
         registry.register(ClearCommand);
+        registry.register(StopCommand);
         registry.register(HelpCommand);
+        registry.register(ThinkCommand);
+        registry.register(GrantCommand);
+        registry.register(RevokeCommand);
+        registry.register(RolesCommand);
+        registry.register(ReloadCommand);
+        registry.register(GroupCfgCommand);
+        registry.register(MuteCommand);
+        registry.register(UnmuteCommand);
+        registry.register(RemindCommand);
+        registry.register(BroadcastCommand);
+        registry.register(SummaryCommand);
+        registry.register(SearchCommand);
         registry
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::*;
+
+    struct Named(&'static str, &'static [&'static str]);
+
+    #[async_trait]
+    impl Command for Named {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn description(&self) -> &'static str {
+            ""
+        }
+
+        fn aliases(&self) -> &'static [&'static str] {
+            self.1
+        }
+
+        async fn execute(&self, _ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn test_register_conflict() {
+        let mut registry = CommandRegistry::default();
+        assert!(registry.try_register(Named("status", &["状态"])).is_ok());
+        assert!(registry.try_register(Named("Status", &[])).is_err());
+        assert!(registry.try_register(Named("info", &["状态"])).is_err());
+        assert!(registry.try_register(Named("ping", &["ping"])).is_err());
+        assert_eq!(registry.get("帮助").map(|c| c.name()), Some("help"));
+        assert_eq!(registry.get("STATUS").map(|c| c.name()), Some("status"));
+        assert!(registry.get("info").is_none());
+    }
+
+    #[test]
+    fn test_help_pages() {
+        const NAMES: [&str; 20] = [
+            "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
+            "r", "s", "t",
+        ];
+        let cmds: Vec<Named> = NAMES.iter().map(|n| Named(n, &[])).collect();
+        let mut groups: BTreeMap<&str, Vec<&dyn Command>> = BTreeMap::new();
+        groups.insert("其他", cmds.iter().map(|c| c as &dyn Command).collect());
+        groups.insert("基础", vec![&HelpCommand]);
+
+        let pages = help_pages(&groups, "/");
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].starts_with("【其他】\n/a: "));
+        assert!(pages[1].starts_with("【其他】\n/p: "));
+        assert!(pages[1].contains("【基础】\n/help: "));
+    }
+
+    #[test]
+    fn test_clear_history() {
+        use crate::group_settings::{ContextScope, context_id};
+        use crate::user_manager::{ChatRole, Message, MessageContent};
+
+        let dir = std::env::temp_dir().join(format!("rosbot-clear-{}", std::process::id()));
+        let runtime = kovi::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let db = UserManager::open(dir.join("users.db")).await.unwrap();
+            let config = Config::default();
+            let message = Message {
+                role: ChatRole::User,
+                content: MessageContent::Text("你好".to_string()),
+            };
+            let mut shared = db.load_user(context_id(1)).await.unwrap();
+            shared.history.push(message.clone());
+            db.save_user(&shared).await.unwrap();
+            let mut user = db.load_user(10).await.unwrap();
+            user.history.push(message);
+
+            // 每人独立的上下文只清空自己的记录
+            let cleared = clear_history(&db, &config, Some(1), Role::User, false, &mut user);
+            assert_eq!(cleared.await.unwrap(), Some(10));
+            assert!(user.history.is_empty());
+
+            // 全群共享的上下文需要群管理权限
+            let settings = GroupSettings {
+                context: Some(ContextScope::Group),
+                ..Default::default()
+            };
+            db.save_group(1, &settings).await.unwrap();
+            let cleared = clear_history(&db, &config, Some(1), Role::User, false, &mut user);
+            assert_eq!(cleared.await.unwrap(), None);
+            assert_eq!(db.load_user(context_id(1)).await.unwrap().history.len(), 1);
+            let cleared = clear_history(&db, &config, Some(1), Role::User, true, &mut user);
+            assert_eq!(cleared.await.unwrap(), Some(context_id(1)));
+            assert!(
+                db.load_user(context_id(1))
+                    .await
+                    .unwrap()
+                    .history
+                    .is_empty()
+            );
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
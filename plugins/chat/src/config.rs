@@ -1,7 +1,26 @@
-use kovi::log::{error, info};
-use kovi::utils::{load_toml_data, save_toml_data};
+use crate::broadcast::Broadcast;
+use crate::group_settings::{ContextScope, QuietHours, Trigger};
+use crate::mcp_client::McpServerConfig;
+use crate::openai_api::{DEFAULT_MAX_CONTINUATIONS, DEFAULT_MAX_TOOL_ROUNDS};
+use crate::secrets::Secrets;
+use crate::secrets::info;
+use anyhow::{Error, anyhow};
+use kovi::chrono::{DateTime, FixedOffset, Local, Utc};
+use kovi::utils::save_toml_data;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// 修改后需要重启才能生效的配置项
+const RESTART_KEYS: [&str; 4] = ["proxy", "markdown_render", "render_fonts", "mcp_servers"];
+/// 差异中只显示是否修改的配置项
+const SECRET_KEYS: [&str; 2] = ["bearer_token", "mcp_servers"];
+/// 覆盖配置项的环境变量前缀，如 ROSBOT_BEARER_TOKEN
+const ENV_PREFIX: &str = "ROSBOT_";
+/// 默认的命令前缀
+pub const DEFAULT_COMMAND_PREFIX: &str = "/";
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
@@ -11,21 +30,363 @@ pub struct Config {
     pub(crate) model: String,
     pub(crate) system_prompt: String,
     pub(crate) temperature: Option<f32>,
+    pub(crate) top_p: Option<f32>,
+    pub(crate) presence_penalty: Option<f32>,
+    pub(crate) frequency_penalty: Option<f32>,
+    pub(crate) seed: Option<i64>,
+    pub(crate) stop: Option<Vec<String>>,
+    pub(crate) response_format: Option<Value>,
+    #[serde(alias = "max_output_token", alias = "max_tokens")]
     pub(crate) max_output_tokens: Option<u32>,
+    pub(crate) max_tokens_field: Option<String>,
+    pub(crate) extra_body: Option<Map<String, Value>>,
+    pub(crate) model_vision: Option<bool>,
+    pub(crate) vision_model: Option<String>,
+    pub(crate) vision_prompt: Option<String>,
     pub(crate) msg_limit: Option<usize>,
     pub(crate) token_limit: Option<usize>,
+    pub(crate) max_continuations: Option<u32>,
+    pub(crate) content_filter_reply: Option<String>,
+    pub(crate) max_tool_rounds: Option<u32>,
+    pub(crate) tool_timeout: Option<u64>,
+    pub(crate) max_tool_calls: Option<u32>,
+    pub(crate) tool_time_budget: Option<u64>,
+    pub(crate) tool_progress_delay: Option<u64>,
+    pub(crate) command_prefix: Option<String>,
+    pub(crate) markdown_render: Option<bool>,
+    pub(crate) render_fonts: Option<Vec<String>>,
+    pub(crate) group_trigger: Option<Trigger>,
+    pub(crate) group_context: Option<ContextScope>,
+    pub(crate) group_quota: Option<u32>,
+    pub(crate) quiet_hours: Option<QuietHours>,
+    pub(crate) timezone: Option<String>,
+    pub(crate) sleep_reply: Option<String>,
+    pub(crate) broadcasts: Option<Vec<Broadcast>>,
+    pub(crate) group_log_days: Option<u32>,
+    pub(crate) summary_chunk_tokens: Option<usize>,
+    pub(crate) mcp_servers: Option<Vec<McpServerConfig>>,
 }
 
 impl Config {
-    pub fn from_file(path: PathBuf) -> Self {
+    /// 读取并校验配置文件，不存在时写入模板
+    pub fn load(path: &Path, secrets: &Secrets) -> Result<Self, Error> {
         if !path.is_file() {
-            error!("Config is not exist");
             info!("Create new config: {}", path.display());
-            let config = Config::default();
-            save_toml_data(&config, path).expect("Failed to write new config");
-            panic!()
+            save_toml_data(&Config::default(), path)
+                .map_err(|e| anyhow!("Failed to write new config: {}", e))?;
+            return Err(anyhow!(
+                "Config does not exist, a template has been written to {}, please edit it and restart",
+                path.display()
+            ));
+        }
+        let mut config = Config::read(path)?;
+        config.resolve_secrets(secrets)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 从密钥存储中取出 Token，优先于配置文件中的值
+    fn resolve_secrets(&mut self, secrets: &Secrets) -> Result<(), Error> {
+        self.bearer_token = match secrets.get("bearer_token")? {
+            Some(v) => v,
+            None => secrets.resolve(&self.bearer_token)?,
+        };
+        // MCP 服务器的请求头可能包含 Token
+        for server in self.mcp_servers.iter_mut().flatten() {
+            for value in server.headers.values_mut() {
+                *value = secrets.resolve(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 读取配置文件并应用环境变量，不存在时不会创建
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let config: Config = kovi::toml::from_str(&text)
+            .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+        config.with_env(|key| std::env::var(key).ok())
+    }
+
+    /// 使用环境变量覆盖配置项，变量名为 ROSBOT_ 加大写的配置项名
+    fn with_env(self, var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let Value::Object(base) = serde_json::to_value(&self)? else {
+            return Ok(self);
+        };
+        let mut fields = base.clone();
+        let mut changed = false;
+        for (key, value) in fields.iter_mut() {
+            let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            let Some(raw) = var(&name) else {
+                continue;
+            };
+            // 字符串配置项直接使用原文，其余按 TOML 值解析
+            *value = match value {
+                Value::String(_) => Value::String(raw),
+                _ => match kovi::toml::from_str::<kovi::toml::Table>(&format!("v = {}", raw)) {
+                    Ok(mut t) => {
+                        let parsed = serde_json::to_value(t.remove("v"))?;
+                        // 未设置的可选项无法得知类型，如 ROSBOT_TIMEZONE=8，
+                        // 解析后的值不符合该项的类型时改用原文
+                        let mut probe = base.clone();
+                        probe.insert(key.clone(), parsed.clone());
+                        match serde_json::from_value::<Config>(Value::Object(probe)) {
+                            Ok(_) => parsed,
+                            Err(_) => Value::String(raw),
+                        }
+                    }
+                    Err(_) => Value::String(raw),
+                },
+            };
+            info!("Config {} is overridden by {}", key, name);
+            changed = true;
+        }
+        if !changed {
+            return Ok(self);
+        }
+        serde_json::from_value(Value::Object(fields))
+            .map_err(|e| anyhow!("Invalid config from environment: {}", e))
+    }
+
+    /// 检查配置，返回所有问题
+    pub fn problems(&self) -> Vec<String> {
+        let default = Config::default();
+        let mut problems = Vec::new();
+
+        match reqwest::Url::parse(&self.api_url) {
+            _ if self.api_url == default.api_url => {
+                problems.push("api_url is still the placeholder value".to_string())
+            }
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => problems.push(format!(
+                "api_url is not a valid http(s) URL: {}",
+                self.api_url
+            )),
+        }
+        if let Some(proxy) = &self.proxy
+            && let Err(e) = reqwest::Proxy::all(proxy)
+        {
+            problems.push(format!("proxy is invalid: {}", e));
+        }
+        if self.bearer_token.trim().is_empty() {
+            problems.push("bearer_token is empty".to_string());
+        } else if self.bearer_token == default.bearer_token {
+            problems.push("bearer_token is still the placeholder value".to_string());
+        }
+        if self.model.trim().is_empty() {
+            problems.push("model is empty".to_string());
+        } else if self.model == default.model {
+            problems.push("model is still the placeholder value".to_string());
+        }
+        if self.system_prompt == default.system_prompt {
+            problems.push("system_prompt is still the placeholder value".to_string());
+        }
+
+        let ranges = [
+            ("temperature", self.temperature, 0.0, 2.0),
+            ("top_p", self.top_p, 0.0, 1.0),
+            ("presence_penalty", self.presence_penalty, -2.0, 2.0),
+            ("frequency_penalty", self.frequency_penalty, -2.0, 2.0),
+        ];
+        for (key, value, min, max) in ranges {
+            if let Some(v) = value
+                && !(min..=max).contains(&v)
+            {
+                problems.push(format!(
+                    "{} must be between {} and {}, got {}",
+                    key, min, max, v
+                ));
+            }
+        }
+        if let Some(field) = &self.max_tokens_field
+            && !matches!(
+                field.as_str(),
+                "max_tokens" | "max_completion_tokens" | "max_output_tokens"
+            )
+        {
+            problems.push(format!("max_tokens_field is unknown: {}", field));
+        }
+
+        // 两个限制需同时设置才会生效
+        let msg_limit = self.msg_limit.unwrap_or_default();
+        let token_limit = self.token_limit.unwrap_or_default();
+        if (msg_limit == 0) != (token_limit == 0) {
+            problems.push(
+                "msg_limit and token_limit must be both set to non-zero values or both unset"
+                    .to_string(),
+            );
+        }
+
+        if self.model_vision == Some(false)
+            && self
+                .vision_model
+                .as_deref()
+                .is_none_or(|m| m.trim().is_empty())
+        {
+            problems.push("vision_model is required when model_vision is false".to_string());
+        }
+        if let Some(prefix) = &self.command_prefix
+            && prefix.chars().any(char::is_whitespace)
+        {
+            problems.push("command_prefix must not contain whitespace".to_string());
+        }
+        if let Some(tz) = &self.timezone
+            && tz.parse::<FixedOffset>().is_err()
+        {
+            problems.push(format!(
+                "timezone must be a UTC offset like +08:00, got {}",
+                tz
+            ));
+        }
+        if self.tool_timeout == Some(0) {
+            problems.push("tool_timeout must be greater than 0".to_string());
+        }
+        if self.max_tool_calls == Some(0) {
+            problems.push("max_tool_calls must be greater than 0".to_string());
+        }
+        if self.tool_time_budget == Some(0) {
+            problems.push("tool_time_budget must be greater than 0".to_string());
+        }
+        if self.summary_chunk_tokens == Some(0) {
+            problems.push("summary_chunk_tokens must be greater than 0".to_string());
+        }
+        for (i, job) in self.broadcasts.iter().flatten().enumerate() {
+            if let Err(e) = job.check() {
+                problems.push(format!("broadcasts[{}] is invalid: {}", i, e));
+            }
+        }
+        for (i, server) in self.mcp_servers.iter().flatten().enumerate() {
+            if let Err(e) = server.check() {
+                problems.push(format!("mcp_servers[{}] is invalid: {}", i, e));
+            }
+            if self
+                .mcp_servers
+                .iter()
+                .flatten()
+                .take(i)
+                .any(|s| s.name == server.name)
+            {
+                problems.push(format!("mcp_servers has duplicate name {}", server.name));
+            }
+        }
+        for font in self.render_fonts.iter().flatten() {
+            if !Path::new(font).is_file() {
+                problems.push(format!("render_fonts file does not exist: {}", font));
+            }
+        }
+        problems
+    }
+
+    /// 校验配置，一次报告所有问题
+    pub fn validate(&self) -> Result<(), Error> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "Config has {} problem(s):\n  - {}",
+            problems.len(),
+            problems.join("\n  - ")
+        ))
+    }
+
+    /// 命令前缀，未配置时为 "/"
+    pub fn command_prefix(&self) -> &str {
+        self.command_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_COMMAND_PREFIX)
+    }
+
+    /// 转换为配置的时区，未配置时使用系统时区
+    pub fn local_time(&self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self
+            .timezone
+            .as_deref()
+            .and_then(|t| t.parse::<FixedOffset>().ok())
+        {
+            Some(tz) => time.with_timezone(&tz),
+            None => time.with_timezone(&Local).fixed_offset(),
+        }
+    }
+
+    /// 与新配置比较，返回变更项
+    pub fn diff(&self, new: &Config) -> Vec<String> {
+        let (Ok(Value::Object(old)), Ok(Value::Object(new))) =
+            (serde_json::to_value(self), serde_json::to_value(new))
+        else {
+            return Vec::new();
+        };
+        let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+        let mut changes = Vec::new();
+        for key in keys {
+            let (a, b) = (
+                old.get(key).unwrap_or(&Value::Null),
+                new.get(key).unwrap_or(&Value::Null),
+            );
+            if a == b {
+                continue;
+            }
+            let mut line = if SECRET_KEYS.contains(&key.as_str()) {
+                format!("{}: (已修改)", key)
+            } else {
+                format!("{}: {} -> {}", key, brief(a), brief(b))
+            };
+            if RESTART_KEYS.contains(&key.as_str()) {
+                line.push_str(" (需重启生效)");
+            }
+            changes.push(line);
+        }
+        changes
+    }
+}
+
+/// 截断过长的配置值
+fn brief(value: &Value) -> String {
+    const LIMIT: usize = 40;
+    let text = value.to_string();
+    match text.char_indices().nth(LIMIT) {
+        Some((i, _)) => format!("{}…", &text[..i]),
+        None => text,
+    }
+}
+
+/// 运行中的配置，重载时整体替换
+pub struct ConfigStore {
+    path: PathBuf,
+    secrets: Arc<Secrets>,
+    current: RwLock<Arc<Config>>,
+}
+
+impl ConfigStore {
+    pub fn new(path: PathBuf, secrets: Arc<Secrets>, config: Config) -> Self {
+        ConfigStore {
+            path,
+            secrets,
+            current: RwLock::new(Arc::new(config)),
         }
-        load_toml_data(Config::default(), path).expect("Fail to load config")
+    }
+
+    /// 配置文件路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 当前配置的快照
+    pub fn load(&self) -> Arc<Config> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// 重新读取并校验配置文件，成功时替换当前配置并返回新旧配置
+    pub fn reload(&self) -> Result<(Arc<Config>, Arc<Config>), Error> {
+        self.secrets.reload()?;
+        let mut new = Config::read(&self.path)?;
+        new.resolve_secrets(&self.secrets)?;
+        new.validate()?;
+        let new = Arc::new(new);
+        let old = std::mem::replace(&mut *self.current.write().unwrap(), Arc::clone(&new));
+        Ok((old, new))
     }
 }
 
@@ -38,9 +399,127 @@ impl Default for Config {
             model: "Your Model".to_string(),
             system_prompt: "System Promote".to_string(),
             temperature: None,
+            top_p: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            seed: None,
+            stop: None,
+            response_format: None,
             max_output_tokens: None,
+            max_tokens_field: None,
+            extra_body: None,
+            model_vision: Some(true),
+            vision_model: None,
+            vision_prompt: None,
             msg_limit: Some(30),
             token_limit: Some(5000),
+            max_continuations: Some(DEFAULT_MAX_CONTINUATIONS),
+            content_filter_reply: None,
+            max_tool_rounds: Some(DEFAULT_MAX_TOOL_ROUNDS),
+            tool_timeout: None,
+            max_tool_calls: None,
+            tool_time_budget: None,
+            tool_progress_delay: None,
+            command_prefix: Some(DEFAULT_COMMAND_PREFIX.to_string()),
+            markdown_render: Some(true),
+            render_fonts: None,
+            group_trigger: None,
+            group_context: None,
+            group_quota: None,
+            quiet_hours: None,
+            timezone: None,
+            sleep_reply: None,
+            broadcasts: None,
+            group_log_days: None,
+            summary_chunk_tokens: None,
+            mcp_servers: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::*;
+
+    #[test]
+    fn test_config_diff() {
+        let old = Config::default();
+        let new = Config {
+            model: "deepseek-chat".to_string(),
+            bearer_token: "sk-secret".to_string(),
+            proxy: Some("socks5://127.0.0.1:1080".to_string()),
+            ..Default::default()
+        };
+        let changes = old.diff(&new);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0], "bearer_token: (已修改)");
+        assert_eq!(changes[1], r#"model: "Your Model" -> "deepseek-chat""#);
+        assert!(changes[2].starts_with("proxy: null -> ") && changes[2].ends_with("(需重启生效)"));
+        assert!(old.diff(&Config::default()).is_empty());
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let config = Config {
+            api_url: "ftp://example.com".to_string(),
+            temperature: Some(3.0),
+            token_limit: None,
+            ..Default::default()
+        };
+        let problems = config.problems();
+        for expected in [
+            "api_url is not a valid http(s) URL",
+            "bearer_token is still the placeholder value",
+            "model is still the placeholder value",
+            "system_prompt is still the placeholder value",
+            "temperature must be between 0 and 2",
+            "msg_limit and token_limit must be both set",
+        ] {
+            assert!(
+                problems.iter().any(|p| p.starts_with(expected)),
+                "missing {:?} in {:?}",
+                expected,
+                problems
+            );
         }
+
+        let config = Config {
+            api_url: "https://api.deepseek.com/chat/completions".to_string(),
+            bearer_token: "sk-secret".to_string(),
+            model: "deepseek-chat".to_string(),
+            system_prompt: "你是迷迭香".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let env = |key: &str| match key {
+            "ROSBOT_BEARER_TOKEN" => Some("sk-env".to_string()),
+            "ROSBOT_TEMPERATURE" => Some("0.5".to_string()),
+            "ROSBOT_PROXY" => Some("socks5://127.0.0.1:1080".to_string()),
+            _ => None,
+        };
+        let config = config.with_env(env).unwrap();
+        assert_eq!(config.bearer_token, "sk-env");
+        assert_eq!(config.temperature, Some(0.5));
+        assert_eq!(config.proxy.as_deref(), Some("socks5://127.0.0.1:1080"));
+
+        // 未设置的可选字符串项不按 TOML 解析为数字或布尔值
+        let env = |key: &str| match key {
+            "ROSBOT_TIMEZONE" => Some("8".to_string()),
+            "ROSBOT_SLEEP_REPLY" => Some("true".to_string()),
+            "ROSBOT_GROUP_QUOTA" => Some("20".to_string()),
+            _ => None,
+        };
+        let config = config.with_env(env).unwrap();
+        assert_eq!(config.timezone.as_deref(), Some("8"));
+        assert_eq!(config.sleep_reply.as_deref(), Some("true"));
+        assert_eq!(config.group_quota, Some(20));
+
+        // 缺少的配置项使用默认值
+        let config = Config {
+            command_prefix: None,
+            ..config
+        };
+        assert_eq!(config.command_prefix(), DEFAULT_COMMAND_PREFIX);
     }
 }
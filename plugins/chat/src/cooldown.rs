@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 命令冷却时间，为 None 时不限制
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cooldown {
+    /// 同一用户两次调用的间隔
+    pub user: Option<Duration>,
+    /// 同一群内两次调用的间隔
+    pub group: Option<Duration>,
+    /// 所有会话中两次调用的间隔
+    pub global: Option<Duration>,
+}
+
+impl Cooldown {
+    /// 仅限制同一用户
+    pub fn per_user(secs: u64) -> Self {
+        Cooldown {
+            user: Some(Duration::from_secs(secs)),
+            ..Default::default()
+        }
+    }
+
+    /// 追加同一群内的限制
+    pub fn group(mut self, secs: u64) -> Self {
+        self.group = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// 追加全局限制
+    pub fn global(mut self, secs: u64) -> Self {
+        self.global = Some(Duration::from_secs(secs));
+        self
+    }
+}
+
+/// 命令可用范围
+// 内置命令尚未用到全部范围
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// 私聊与群聊均可
+    #[default]
+    Any,
+    /// 仅私聊
+    PrivateOnly,
+    /// 仅群聊
+    GroupOnly,
+    /// 仅指定的群
+    Groups(Vec<i64>),
+}
+
+impl Scope {
+    /// 检查会话是否在范围内，不在时返回提示
+    pub fn check(&self, group_id: Option<i64>) -> Result<(), &'static str> {
+        match (self, group_id) {
+            (Scope::Any, _) => Ok(()),
+            (Scope::PrivateOnly, None) | (Scope::GroupOnly, Some(_)) => Ok(()),
+            (Scope::PrivateOnly, Some(_)) => Err("此命令仅限私聊使用"),
+            (Scope::GroupOnly, None) => Err("此命令仅限群聊使用"),
+            (Scope::Groups(groups), Some(id)) if groups.contains(&id) => Ok(()),
+            (Scope::Groups(_), _) => Err("此命令在当前会话不可用"),
+        }
+    }
+}
+
+/// 冷却记录的对象
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    User(i64),
+    Group(i64),
+    Global,
+}
+
+/// 记录每个命令上次调用的时间
+#[derive(Default)]
+pub struct CooldownTracker {
+    last: Mutex<HashMap<(&'static str, Key), Instant>>,
+}
+
+impl CooldownTracker {
+    /// 检查并占用冷却，仍在冷却中时返回剩余时间
+    pub fn acquire(
+        &self,
+        command: &'static str,
+        cooldown: &Cooldown,
+        user_id: i64,
+        group_id: Option<i64>,
+    ) -> Result<(), Duration> {
+        let keys = keys(cooldown, user_id, group_id);
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap();
+        let remaining = keys
+            .iter()
+            .filter_map(|(key, limit)| {
+                let elapsed = now.duration_since(*last.get(&(command, *key))?);
+                limit.checked_sub(elapsed).filter(|d| !d.is_zero())
+            })
+            .max();
+        if let Some(remaining) = remaining {
+            return Err(remaining);
+        }
+
+        for (key, _) in keys {
+            last.insert((command, key), now);
+        }
+        // 清理过期记录
+        if last.len() > 1024 {
+            last.retain(|_, t| now.duration_since(*t) < Duration::from_secs(86400));
+        }
+        Ok(())
+    }
+
+    /// 释放冷却，用于命令未实际执行的情况
+    pub fn release(
+        &self,
+        command: &'static str,
+        cooldown: &Cooldown,
+        user_id: i64,
+        group_id: Option<i64>,
+    ) {
+        let mut last = self.last.lock().unwrap();
+        for (key, _) in keys(cooldown, user_id, group_id) {
+            last.remove(&(command, key));
+        }
+    }
+}
+
+/// 获取需要检查的冷却对象及时长
+fn keys(cooldown: &Cooldown, user_id: i64, group_id: Option<i64>) -> Vec<(Key, Duration)> {
+    let mut keys = Vec::new();
+    if let Some(d) = cooldown.user {
+        keys.push((Key::User(user_id), d));
+    }
+    if let (Some(d), Some(group)) = (cooldown.group, group_id) {
+        keys.push((Key::Group(group), d));
+    }
+    if let Some(d) = cooldown.global {
+        keys.push((Key::Global, d));
+    }
+    keys
+}
+
+/// 格式化剩余时间，如 "1分5秒"
+pub fn format_duration(d: Duration) -> String {
+    // 不足一秒按一秒计
+    let secs = d.as_secs() + u64::from(d.subsec_nanos() > 0);
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}秒", s),
+        (0, m, 0) => format!("{}分钟", m),
+        (0, m, s) => format!("{}分{}秒", m, s),
+        (h, m, _) => format!("{}小时{}分", h, m),
+    }
+}
+
+/// 解析时长，如 "30m"、"2小时"、"1天12h"，纯数字按分钟计
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim().to_lowercase();
+    if let Ok(minutes) = s.parse::<u64>() {
+        return (minutes > 0).then(|| Duration::from_secs(minutes * 60));
+    }
+
+    let mut total = 0u64;
+    let mut rest = s.as_str();
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let secs = match rest[..unit].trim() {
+            "s" | "sec" | "秒" | "秒钟" => 1,
+            "m" | "min" | "分" | "分钟" => 60,
+            "h" | "hour" | "时" | "小时" | "个小时" => 3600,
+            "d" | "day" | "天" => 86400,
+            _ => return None,
+        };
+        total = total.checked_add(value.checked_mul(secs)?)?;
+        rest = &rest[unit..];
+    }
+    (total > 0).then(|| Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cooldown::*;
+
+    #[test]
+    fn test_cooldown() {
+        let tracker = CooldownTracker::default();
+        let cooldown = Cooldown::per_user(60).group(10);
+        assert!(tracker.acquire("seedream", &cooldown, 1, Some(100)).is_ok());
+        assert!(tracker.acquire("seedream", &cooldown, 1, None).is_err());
+        assert!(
+            tracker
+                .acquire("seedream", &cooldown, 2, Some(100))
+                .is_err()
+        );
+        assert!(tracker.acquire("seedream", &cooldown, 2, Some(200)).is_ok());
+        assert!(tracker.acquire("image", &cooldown, 1, Some(100)).is_ok());
+        tracker.release("seedream", &cooldown, 1, Some(100));
+        assert!(tracker.acquire("seedream", &cooldown, 1, Some(100)).is_ok());
+
+        assert_eq!(format_duration(Duration::from_millis(1500)), "2秒");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2分5秒");
+        assert_eq!(format_duration(Duration::from_secs(3700)), "1小时1分");
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1天12h"), Some(Duration::from_secs(129600)));
+        assert_eq!(parse_duration("30分钟"), Some(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("0m"), None);
+
+        assert!(Scope::GroupOnly.check(None).is_err());
+        assert!(Scope::Groups(vec![100]).check(Some(100)).is_ok());
+        assert!(Scope::Groups(vec![100]).check(Some(200)).is_err());
+    }
+}
@@ -1,8 +1,7 @@
 use crate::commands::*;
-use anyhow::{Error, anyhow};
+use crate::secrets::{error, info};
 use base64::Engine;
 use base64::engine::general_purpose;
-use kovi::log::{error, info};
 use rand::rng;
 use rand::seq::IndexedRandom;
 use std::fs;
@@ -10,6 +9,7 @@ use std::fs;
 /// 创建命令结构体
 pub struct ImageCommand;
 
+#[async_trait]
 impl Command for ImageCommand {
     /// 命令名称
     fn name(&self) -> &'static str {
@@ -17,55 +17,52 @@ impl Command for ImageCommand {
     }
     /// 命令描述
     fn description(&self) -> &'static str {
-        "随机香图"
+        "随机发送一张迷迭香图片（香图）"
+    }
+    /// 命令别名
+    fn aliases(&self) -> &'static [&'static str] {
+        &["香图"]
+    }
+    /// 冷却时间
+    fn cooldown(&self) -> Cooldown {
+        Cooldown::per_user(10).group(3)
+    }
+    /// 帮助中的分类
+    fn category(&self) -> &'static str {
+        "图片"
+    }
+    /// 开放给 AI 调用
+    fn tool_parameters(&self) -> Option<Value> {
+        Some(json!({ "type": "object", "properties": {} }))
     }
     /// 执行命令
-    fn execute(
-        &self,
-        // 文本信息
-        text: &str,
-        // 原始的 MsgEvent
-        msg: &Arc<MsgEvent>,
-        // 用户信息，目前包含 ID 和与 AI 的聊天记录
-        user: &mut User,
-        // 命令注册器，用于查看或调用其他命令
-        _registry: &CommandRegistry,
-        data_dir: PathBuf,
-    ) -> bool {
-        // 匹配命令则返回 true (返回为 true 时不进行 AI 回复)
-        if text.trim() == "image" {
-            user.history.clear();
-            info!("User {} cleared history", user.id);
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        // 返回 true 时不进行 AI 回复
+        if !ctx.is_tool_call() {
+            ctx.user.history.clear();
+            info!("User {} cleared history", ctx.user.id);
+        }
 
-            // 判断目录是否存在
-            if !data_dir.is_dir() {
-                error!("There is no image library at {}", data_dir.display());
-                let _ = fs::create_dir_all(data_dir);
-                return true;
-            }
+        // 判断目录是否存在
+        if !ctx.data_dir.is_dir() {
+            error!("There is no image library at {}", ctx.data_dir.display());
+            let _ = fs::create_dir_all(&ctx.data_dir);
+            return Err(anyhow!("图库为空"));
+        }
 
-            // 抽取图片
-            let image = match random_file_base64(&data_dir) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("Failed to get image: {}", e);
-                    return true;
-                }
-            };
+        // 抽取图片
+        let image = random_file_base64(&ctx.data_dir)?;
 
-            let reply = KoviMsg::new()
-                .add_reply(msg.message_id)
-                .add_image(format!("base64://{}", image).as_str());
-            msg.reply(reply);
+        let reply = KoviMsg::new()
+            .add_reply(ctx.msg.message_id)
+            .add_image(format!("base64://{}", image).as_str());
+        ctx.reply(reply);
 
-            true
-        } else {
-            false
-        }
+        Ok(true)
     }
 }
 
-pub fn random_file_base64(dir: &PathBuf) -> Result<String, Error> {
+pub fn random_file_base64(dir: &Path) -> Result<String, Error> {
     // 读取目录
     let mut files = Vec::new();
     for entry in fs::read_dir(dir)? {
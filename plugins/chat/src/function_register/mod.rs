@@ -22,6 +22,7 @@ pub fn register_commands(commands_reg: &mut CommandRegistry) {
 }
 
 /// 在此注册 MCP
+#[allow(unused_variables)]
 pub fn register_mcp(mcp_loader: &mut MCPRegistry) {
     // 注册自定义 MCP
     // mcp_loader.register(/* mcp */);
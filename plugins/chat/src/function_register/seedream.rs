@@ -1,5 +1,6 @@
 use crate::commands::*;
-use kovi::log::{error, info};
+use crate::secrets::remember;
+use crate::secrets::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
@@ -9,6 +10,7 @@ const MODEL: &str = "doubao-seedream-4-0-250828";
 /// 创建命令结构体
 pub struct SeedreamCommand;
 
+#[async_trait]
 impl Command for SeedreamCommand {
     /// 命令名称
     fn name(&self) -> &'static str {
@@ -18,56 +20,87 @@ impl Command for SeedreamCommand {
     fn description(&self) -> &'static str {
         "使用 seedream 模型生成图像"
     }
+    /// 命令别名
+    fn aliases(&self) -> &'static [&'static str] {
+        &["画图"]
+    }
+    /// 命令用法
+    fn usage(&self) -> &'static str {
+        "seedream <提示词>"
+    }
+    /// 执行权限 (调用付费 API，仅限信任用户)
+    fn permission(&self) -> Permission {
+        Permission::Trusted
+    }
+    /// 冷却时间
+    fn cooldown(&self) -> Cooldown {
+        Cooldown::per_user(60).global(10)
+    }
+    /// 使用示例
+    fn examples(&self) -> &'static [&'static str] {
+        &["seedream 一只在草地上晒太阳的猫"]
+    }
+    /// 帮助中的分类
+    fn category(&self) -> &'static str {
+        "图片"
+    }
+    /// 开放给 AI 调用
+    fn tool_parameters(&self) -> Option<Value> {
+        Some(json!({
+            "type": "object",
+            "properties": {
+                "prompt": { "type": "string", "description": "图像描述提示词" }
+            },
+            "required": ["prompt"]
+        }))
+    }
     /// 执行命令
-    fn execute(
-        &self,
-        // 文本信息
-        text: &str,
-        // 原始的 MsgEvent
-        msg: &Arc<MsgEvent>,
-        // 用户信息，目前包含 ID 和与 AI 的聊天记录
-        user: &mut User,
-        // 命令注册器，用于查看或调用其他命令
-        _registry: &CommandRegistry,
-        data_dir: PathBuf,
-    ) -> bool {
-        // 匹配命令则返回 true (返回为 true 时不进行 AI 回复)
-        if text.trim().starts_with("seedream ") {
-            info!("User {} generated image", user.id);
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        // 返回 true 时不进行 AI 回复
+        let prompt = ctx.args.rest().to_string();
+        if prompt.is_empty() {
+            return Err(UsageError("缺少提示词".to_string()).into());
+        }
+        info!("User {} generated image", ctx.user.id);
 
-            // 尝试从 token.txt 读取 bearer token
-            let token_file = data_dir.join("token.txt");
-            let token = match std::fs::read_to_string(&token_file) {
-                Ok(v) => v,
-                Err(_) => {
+        // 从密钥存储读取 Token，兼容旧版的 token.txt
+        let token = match ctx.resources.secrets.get("seedream_token")? {
+            Some(v) => v,
+            None => {
+                let token_file = ctx.data_dir.join("token.txt");
+                let Ok(token) = std::fs::read_to_string(&token_file) else {
                     error!(
-                        "Failed to get token, please write the token into the {} file",
-                        token_file.display()
+                        "Failed to get seedream_token, please set ROSBOT_SEEDREAM_TOKEN or add it to secrets.toml"
                     );
-                    return true;
-                }
-            };
-
-            // 解析消息中的图像
-            let origin_msg = crate::message::OneBotMessage::from_json(&msg.original_json)
-                .expect("Failed to parse message");
-            let images = generate_img(
-                token,
-                text.trim().strip_prefix("seedream ").unwrap().to_string(),
-                origin_msg.find_image(),
-            );
-
-            // 构造回复
-            let mut reply = KoviMsg::new().add_reply(msg.message_id);
-            for i in images {
-                reply.push_image(&*i);
+                    return Err(anyhow!("未配置 seedream Token"));
+                };
+                warn!(
+                    "{} is deprecated, please move the token to secrets.toml as seedream_token",
+                    token_file.display()
+                );
+                remember(token.trim());
+                token.trim().to_string()
             }
-            msg.reply(reply);
+        };
+
+        // 解析消息中的图像
+        let origin_msg = crate::message::OneBotMessage::from_json(&ctx.msg.original_json)?;
+        let images = generate_img(
+            &ctx.resources.http_client,
+            &token,
+            prompt,
+            origin_msg.find_image(),
+        )
+        .await?;
 
-            true
-        } else {
-            false
+        // 构造回复
+        let mut reply = KoviMsg::new().add_reply(ctx.msg.message_id);
+        for i in images {
+            reply.push_image(&i);
         }
+        ctx.reply(reply);
+
+        Ok(true)
     }
 }
 
@@ -90,12 +123,12 @@ struct ResData {
 }
 
 /// 调用 API 生成图像
-fn generate_img(token: String, text: String, img: Vec<String>) -> Vec<String> {
-    let client = reqwest::blocking::Client::builder()
-        .timeout(Duration::from_secs(90))
-        .connect_timeout(Duration::from_secs(30))
-        .build()
-        .unwrap();
+async fn generate_img(
+    client: &reqwest::Client,
+    token: &str,
+    text: String,
+    img: Vec<String>,
+) -> Result<Vec<String>, Error> {
     let request = Request {
         model: MODEL,
         prompt: text,
@@ -104,17 +137,21 @@ fn generate_img(token: String, text: String, img: Vec<String>) -> Vec<String> {
     let response = client
         .post(API_URL)
         .bearer_auth(token)
+        .timeout(Duration::from_secs(90))
         .json(&request)
         .send()
-        .expect("Failed to request image generation API")
+        .await?
         .text()
-        .unwrap();
-    let response = match serde_json::from_str::<Response>(&*response) {
+        .await?;
+    let response = match serde_json::from_str::<Response>(&response) {
         Ok(v) => v,
         Err(e) => {
-            error!("Failed to parse image generation response: {e}");
-            panic!("{}\n{}", serde_json::to_string(&request).unwrap(), response)
+            error!(
+                "Failed to parse image generation response: {e}\n{}",
+                response
+            );
+            return Err(anyhow!("图像生成失败"));
         }
     };
-    response.data.into_iter().map(|x| x.url).collect()
+    Ok(response.data.into_iter().map(|x| x.url).collect())
 }
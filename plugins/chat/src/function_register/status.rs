@@ -1,10 +1,10 @@
 use crate::commands::*;
-use kovi::log::info;
 use sysinfo::{Disks, System};
 
 /// 创建命令结构体
 pub struct StatusCommand;
 
+#[async_trait]
 impl Command for StatusCommand {
     /// 命令名称
     fn name(&self) -> &'static str {
@@ -14,28 +14,25 @@ impl Command for StatusCommand {
     fn description(&self) -> &'static str {
         "查询服务器状态"
     }
+    /// 命令别名
+    fn aliases(&self) -> &'static [&'static str] {
+        &["状态"]
+    }
+    /// 帮助中的分类
+    fn category(&self) -> &'static str {
+        "工具"
+    }
+    /// 开放给 AI 调用
+    fn tool_parameters(&self) -> Option<Value> {
+        Some(json!({ "type": "object", "properties": {} }))
+    }
     /// 执行命令
-    fn execute(
-        &self,
-        // 文本信息
-        text: &str,
-        // 原始的 MsgEvent
-        msg: &Arc<MsgEvent>,
-        // 用户信息，目前包含 ID 和与 AI 的聊天记录
-        user: &mut User,
-        // 命令注册器，用于查看或调用其他命令
-        _registry: &CommandRegistry,
-        _data_dir: PathBuf,
-    ) -> bool {
-        // 匹配命令则返回 true (返回为 true 时不进行 AI 回复)
-        if text.trim() == "status" {
-            info!("User {} query server status", user.id);
-            let reply = KoviMsg::from(server_status());
-            msg.reply(reply);
-            true
-        } else {
-            false
-        }
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        // 返回 true 时不进行 AI 回复
+        info!("User {} query server status", ctx.user.id);
+        let reply = KoviMsg::from(server_status());
+        ctx.reply(reply);
+        Ok(true)
     }
 }
 
@@ -0,0 +1,588 @@
+use crate::commands::*;
+use crate::cooldown::{format_duration, parse_duration};
+use kovi::chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// 群聊中触发回复的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    /// 被 @ 时回复
+    #[default]
+    At,
+    /// 回复所有消息
+    All,
+    /// 仅响应命令，不进行 AI 回复
+    Command,
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Trigger::At => "被 @ 时回复",
+            Trigger::All => "回复所有消息",
+            Trigger::Command => "仅响应命令",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "at" | "艾特" => Ok(Trigger::At),
+            "all" | "全部" => Ok(Trigger::All),
+            "command" | "命令" => Ok(Trigger::Command),
+            _ => Err(format!("未知触发方式: {}，可选 at / all / command", s)),
+        }
+    }
+}
+
+/// 对话上下文的范围
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextScope {
+    /// 每个用户独立的聊天记录
+    #[default]
+    User,
+    /// 全群共享的聊天记录
+    Group,
+}
+
+impl Display for ContextScope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ContextScope::User => "每人独立",
+            ContextScope::Group => "全群共享",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ContextScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "user" | "个人" => Ok(ContextScope::User),
+            "group" | "群" | "全群" => Ok(ContextScope::Group),
+            _ => Err(format!("未知上下文范围: {}，可选 user / group", s)),
+        }
+    }
+}
+
+/// 每天的安静时段，如 01:00-07:00，起止相同时表示关闭
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietHours {
+    /// 开始时间，为当天的第几分钟
+    start: u16,
+    /// 结束时间，不包含
+    end: u16,
+}
+
+impl QuietHours {
+    pub fn is_off(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// 当天的第几分钟是否在安静时段内，支持跨越零点
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl Display for QuietHours {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_off() {
+            return write!(f, "off");
+        }
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if matches!(s, "off" | "关闭") {
+            return Ok(QuietHours { start: 0, end: 0 });
+        }
+        let err = || format!("无效的时段: {}，格式如 01:00-07:00", s);
+        let (start, end) = s.split_once(['-', '~', '–', '到', '至']).ok_or_else(err)?;
+        let minute = |t: &str| -> Option<u16> {
+            let (h, m) = t.trim().split_once([':', '：']).unwrap_or((t.trim(), "0"));
+            let (h, m) = (h.parse::<u16>().ok()?, m.parse::<u16>().ok()?);
+            (h < 24 && m < 60).then_some(h * 60 + m)
+        };
+        Ok(QuietHours {
+            start: minute(start).ok_or_else(err)?,
+            end: minute(end).ok_or_else(err)?,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(value: QuietHours) -> Self {
+        value.to_string()
+    }
+}
+
+/// 按配置的时区获取当天的第几分钟
+pub fn local_minute(config: &Config, now: DateTime<Utc>) -> u16 {
+    let time = config.local_time(now).time();
+    (time.hour() * 60 + time.minute()) as u16
+}
+
+/// 群设置，未设置的项使用 config.toml 中的默认值
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupSettings {
+    /// 是否启用 Bot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// 替换系统提示词的人设
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persona: Option<String>,
+    /// 触发方式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<Trigger>,
+    /// 允许使用的命令，为 None 时不限制
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commands: Option<Vec<String>>,
+    /// 上下文范围
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<ContextScope>,
+    /// 每日 AI 回复次数，为 0 时不限制
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u32>,
+    /// 安静时段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHours>,
+    /// 静音到期的时间戳
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub muted_until: Option<i64>,
+}
+
+/// 关闭或静音时仍可使用的命令，避免无法恢复
+pub const ALWAYS_ALLOWED: [&str; 3] = ["groupcfg", "mute", "unmute"];
+
+impl GroupSettings {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn trigger(&self, config: &Config) -> Trigger {
+        self.trigger.or(config.group_trigger).unwrap_or_default()
+    }
+
+    pub fn context(&self, config: &Config) -> ContextScope {
+        self.context.or(config.group_context).unwrap_or_default()
+    }
+
+    /// 每日 AI 回复次数上限，不限制时为 None
+    pub fn quota(&self, config: &Config) -> Option<u32> {
+        self.quota.or(config.group_quota).filter(|&q| q > 0)
+    }
+
+    /// 生效的安静时段，关闭时为 None
+    pub fn quiet_hours(&self, config: &Config) -> Option<QuietHours> {
+        self.quiet_hours
+            .or(config.quiet_hours)
+            .filter(|q| !q.is_off())
+    }
+
+    /// 是否处于静音中
+    pub fn muted(&self, now: DateTime<Utc>) -> bool {
+        self.muted_until.is_some_and(|t| t > now.timestamp())
+    }
+
+    /// 是否处于安静时段
+    pub fn quiet(&self, config: &Config, now: DateTime<Utc>) -> bool {
+        self.quiet_hours(config)
+            .is_some_and(|q| q.contains(local_minute(config, now)))
+    }
+
+    /// 本群是否允许使用命令
+    pub fn allows_command(&self, name: &str) -> bool {
+        if ALWAYS_ALLOWED.contains(&name) {
+            return true;
+        }
+        if !self.enabled() {
+            return false;
+        }
+        self.commands
+            .as_ref()
+            .is_none_or(|c| c.iter().any(|n| n.eq_ignore_ascii_case(name)))
+    }
+
+    /// 修改设置项，值为 reset 时恢复默认，返回设置项的名称
+    pub fn set(&mut self, key: &str, value: &str) -> Result<&'static str, String> {
+        let reset = matches!(value, "reset" | "默认");
+        match key.to_lowercase().as_str() {
+            "enabled" | "开关" => {
+                self.enabled = match value {
+                    _ if reset => None,
+                    "on" | "true" | "开" | "开启" => Some(true),
+                    "off" | "false" | "关" | "关闭" => Some(false),
+                    _ => return Err(format!("无效的开关: {}，可选 on / off", value)),
+                };
+                Ok("开关")
+            }
+            "persona" | "人设" => {
+                self.persona = (!reset).then(|| value.to_string());
+                Ok("人设")
+            }
+            "trigger" | "触发" => {
+                self.trigger = if reset { None } else { Some(value.parse()?) };
+                Ok("触发方式")
+            }
+            "commands" | "命令" => {
+                self.commands = match value {
+                    _ if reset => None,
+                    "all" | "全部" => None,
+                    _ => Some(
+                        value
+                            .split([',', '，'])
+                            .map(|s| s.trim().to_lowercase())
+                            .filter(|s| !s.is_empty())
+                            .collect(),
+                    ),
+                };
+                Ok("允许的命令")
+            }
+            "context" | "上下文" => {
+                self.context = if reset { None } else { Some(value.parse()?) };
+                Ok("上下文范围")
+            }
+            "quota" | "配额" => {
+                self.quota = if reset {
+                    None
+                } else {
+                    Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("无效的次数: {}", value))?,
+                    )
+                };
+                Ok("每日配额")
+            }
+            "quiet" | "安静" | "安静时段" => {
+                self.quiet_hours = if reset { None } else { Some(value.parse()?) };
+                Ok("安静时段")
+            }
+            _ => Err(format!("未知设置项: {}", key)),
+        }
+    }
+
+    /// 显示当前生效的设置
+    pub fn describe(&self, config: &Config) -> String {
+        let persona = match &self.persona {
+            Some(p) if p.chars().count() > 30 => {
+                format!("{}…", p.chars().take(30).collect::<String>())
+            }
+            Some(p) => p.clone(),
+            None => "默认".to_string(),
+        };
+        let commands = match &self.commands {
+            Some(c) => c.join(", "),
+            None => "全部".to_string(),
+        };
+        let quota = match self.quota(config) {
+            Some(q) => format!("{} 次/天", q),
+            None => "不限".to_string(),
+        };
+        let quiet = match self.quiet_hours(config) {
+            Some(q) => q.to_string(),
+            None => "关闭".to_string(),
+        };
+        let now = Utc::now();
+        let muted = match self.muted_until {
+            Some(t) if self.muted(now) => format!(
+                "\n静音中，剩余 {}",
+                format_duration(Duration::from_secs((t - now.timestamp()) as u64))
+            ),
+            _ => String::new(),
+        };
+        format!(
+            "开关 (enabled): {}\n人设 (persona): {}\n触发 (trigger): {}\n命令 (commands): {}\n上下文 (context): {}\n配额 (quota): {}\n安静 (quiet): {}{}",
+            if self.enabled() { "开启" } else { "关闭" },
+            persona,
+            self.trigger(config),
+            commands,
+            self.context(config),
+            quota,
+            quiet,
+            muted
+        )
+    }
+}
+
+/// 全群共享的聊天记录使用负的群号储存
+pub fn context_id(group_id: i64) -> i64 {
+    -group_id
+}
+
+/// groupcfg 命令
+pub struct GroupCfgCommand;
+
+#[async_trait]
+impl Command for GroupCfgCommand {
+    fn name(&self) -> &'static str {
+        "groupcfg"
+    }
+
+    fn description(&self) -> &'static str {
+        "查看或修改本群设置"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["群设置"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "groupcfg [设置项 <值|reset>]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "groupcfg trigger all",
+            "groupcfg commands help,status",
+            "groupcfg quota 50",
+            "groupcfg persona reset",
+            "groupcfg quiet 01:00-07:00",
+        ]
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::GroupAdmin
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::GroupOnly
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let Some(group_id) = ctx.msg.group_id else {
+            return Ok(false);
+        };
+        let mut settings = ctx.resources.user_manager.get_group(group_id).await?;
+
+        if ctx.args.is_empty() {
+            ctx.reply(format!("本群设置:\n{}", settings.describe(&ctx.config)));
+            return Ok(true);
+        }
+
+        let key: String = ctx.args.require(0, "设置项")?;
+        let value = ctx.args.positional()[1..].join(" ");
+        if value.is_empty() {
+            return Err(UsageError("缺少设置值".to_string()).into());
+        }
+        let name = settings.set(&key, &value).map_err(UsageError)?;
+        ctx.resources
+            .user_manager
+            .save_group(group_id, &settings)
+            .await?;
+        info!("User {} set {} of group {}", ctx.user.id, key, group_id);
+
+        ctx.reply(format!(
+            "已修改{}\n{}",
+            name,
+            settings.describe(&ctx.config)
+        ));
+        Ok(true)
+    }
+}
+
+/// 静音时最长的时长
+const MAX_MUTE: Duration = Duration::from_secs(30 * 86400);
+
+/// mute 命令
+pub struct MuteCommand;
+
+#[async_trait]
+impl Command for MuteCommand {
+    fn name(&self) -> &'static str {
+        "mute"
+    }
+
+    fn description(&self) -> &'static str {
+        "让 Bot 在本群静音一段时间"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["静音"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "mute <时长>"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["mute 30m", "mute 2小时", "mute 1天"]
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::GroupAdmin
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::GroupOnly
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let Some(group_id) = ctx.msg.group_id else {
+            return Ok(false);
+        };
+        if ctx.args.is_empty() {
+            return Err(UsageError("缺少时长".to_string()).into());
+        }
+        let duration = parse_duration(ctx.args.rest())
+            .ok_or_else(|| UsageError(format!("无效的时长: {}", ctx.args.rest())))?
+            .min(MAX_MUTE);
+
+        let mut settings = ctx.resources.user_manager.get_group(group_id).await?;
+        settings.muted_until = Some(Utc::now().timestamp() + duration.as_secs() as i64);
+        ctx.resources
+            .user_manager
+            .save_group(group_id, &settings)
+            .await?;
+        info!(
+            "User {} muted group {} for {:?}",
+            ctx.user.id, group_id, duration
+        );
+
+        ctx.reply(format!("已静音 {}", format_duration(duration)));
+        Ok(true)
+    }
+}
+
+/// unmute 命令
+pub struct UnmuteCommand;
+
+#[async_trait]
+impl Command for UnmuteCommand {
+    fn name(&self) -> &'static str {
+        "unmute"
+    }
+
+    fn description(&self) -> &'static str {
+        "解除本群的静音"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["解除静音"]
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::GroupAdmin
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::GroupOnly
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let Some(group_id) = ctx.msg.group_id else {
+            return Ok(false);
+        };
+        let mut settings = ctx.resources.user_manager.get_group(group_id).await?;
+        if !settings.muted(Utc::now()) {
+            ctx.reply("本群没有静音");
+            return Ok(true);
+        }
+        settings.muted_until = None;
+        ctx.resources
+            .user_manager
+            .save_group(group_id, &settings)
+            .await?;
+        info!("User {} unmuted group {}", ctx.user.id, group_id);
+
+        ctx.reply("已解除静音");
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::group_settings::*;
+
+    #[test]
+    fn test_group_settings() {
+        let config = Config {
+            group_quota: Some(20),
+            ..Default::default()
+        };
+        let mut settings = GroupSettings::default();
+        assert_eq!(settings.trigger(&config), Trigger::At);
+        assert_eq!(settings.quota(&config), Some(20));
+
+        settings.set("trigger", "all").unwrap();
+        settings.set("配额", "0").unwrap();
+        settings.set("commands", "help, seedream").unwrap();
+        assert_eq!(settings.trigger(&config), Trigger::All);
+        assert_eq!(settings.quota(&config), None);
+        assert!(settings.allows_command("seedream"));
+        assert!(!settings.allows_command("image"));
+        assert!(settings.set("trigger", "sometimes").is_err());
+
+        settings.set("enabled", "off").unwrap();
+        assert!(!settings.allows_command("help"));
+        assert!(settings.allows_command("groupcfg"));
+        settings.set("quota", "reset").unwrap();
+        assert_eq!(settings.quota(&config), Some(20));
+
+        let quiet: QuietHours = "23:30-07:00".parse().unwrap();
+        assert!(quiet.contains(23 * 60 + 45));
+        assert!(quiet.contains(60));
+        assert!(!quiet.contains(7 * 60));
+        assert_eq!(quiet.to_string(), "23:30-07:00");
+        assert!("25:00-07:00".parse::<QuietHours>().is_err());
+
+        let config = Config {
+            quiet_hours: Some(quiet),
+            timezone: Some("+08:00".to_string()),
+            ..Default::default()
+        };
+        // UTC 16:00 即 UTC+8 的 00:00
+        let midnight = DateTime::from_timestamp(16 * 3600, 0).unwrap();
+        assert!(settings.quiet(&config, midnight));
+        settings.set("quiet", "off").unwrap();
+        assert!(!settings.quiet(&config, midnight));
+    }
+}
@@ -1,25 +1,45 @@
+mod args;
+mod broadcast;
 mod commands;
 mod config;
+mod cooldown;
 mod function_register;
+mod group_settings;
+mod mcp_client;
+mod mcp_http;
 mod mcp_loader;
 mod message;
 mod openai_api;
+mod pending;
+mod permission;
+mod reload;
+mod reminder;
+mod render;
+mod schema;
+mod search;
+mod secrets;
+mod summary;
 mod user_manager;
 
-use crate::commands::{CommandRegistry, KoviMsg};
-use crate::config::Config;
+use crate::commands::{CommandRegistry, CommandTools, GroupSettings, KoviMsg, Resources, User};
+use crate::config::{Config, ConfigStore};
 use crate::function_register::{register_commands, register_mcp};
+use crate::group_settings::{ALWAYS_ALLOWED, ContextScope, Trigger, context_id};
 use crate::mcp_loader::MCPRegistry;
-use crate::message::OneBotMessage;
+use crate::message::{FORWARD_NICKNAME, OneBotMessage, is_synthetic, sender_name};
 use crate::openai_api::OpenaiClient;
+use crate::pending::{PendingReplies, STOP_COMMAND};
+use crate::permission::Role;
+use crate::render::MarkdownRenderer;
+use crate::secrets::Secrets;
+use crate::secrets::{error, info, warn};
+use crate::summary::LoggedMessage;
 use crate::user_manager::UserManager;
 use crate::user_manager::{ChatRole, ContentPart, Message as OpenaiMsg, MessageContent};
 use anyhow::Error;
-use kovi::log::{error, info};
-use kovi::{Message, MsgEvent, NoticeEvent, PluginBuilder as plugin, PluginBuilder, RuntimeBot};
+use kovi::{MsgEvent, NoticeEvent, PluginBuilder as plugin, PluginBuilder};
 use reqwest::Proxy;
 use serde::Deserialize;
-use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -29,8 +49,24 @@ async fn main() {
     let bot = PluginBuilder::get_runtime_bot();
     let data_path = bot.get_data_path();
 
+    // 读取密钥
+    let secrets = match Secrets::load(data_path.join("secrets.toml")) {
+        Ok(v) => Arc::new(v),
+        Err(e) => {
+            error!("Failed to load secrets: {}", e);
+            return;
+        }
+    };
+
     // 读取配置文件
-    let config = Config::from_file(data_path.join("config.toml"));
+    let config_path = data_path.join("config.toml");
+    let config = match Config::load(&config_path, &secrets) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to load config: {}", e);
+            return;
+        }
+    };
 
     // 打开用户管理器
     let user_manager = match UserManager::open(data_path.join("users.db")).await {
@@ -44,6 +80,17 @@ async fn main() {
         }
     };
 
+    // 根据 Kovi 主管理员与副管理员写入角色
+    match bot.get_main_admin() {
+        Ok(owner) => {
+            let deputies = bot.get_deputy_admins().unwrap_or_default();
+            if let Err(e) = user_manager.seed_admins(owner, &deputies).await {
+                error!("Failed to seed admin roles: {}", e);
+            }
+        }
+        Err(e) => warn!("Failed to get main admin: {:?}", e),
+    }
+
     // 构建 HTTP 客户端
     let builder = reqwest::Client::builder()
         .pool_max_idle_per_host(20)
@@ -51,13 +98,21 @@ async fn main() {
         .timeout(Duration::from_secs(90))
         .connect_timeout(Duration::from_secs(30))
         .tcp_nodelay(true);
-    let http_client = Arc::new(match &config.proxy {
-        None => builder.build().expect("Failed to build reqwest client"),
-        Some(v) => builder
-            .proxy(Proxy::all(v).expect("Failed to connect to proxy"))
-            .build()
-            .expect("Failed to build reqwest client"),
-    });
+    let builder = match config.proxy.as_deref().map(Proxy::all).transpose() {
+        Ok(Some(proxy)) => builder.proxy(proxy),
+        Ok(None) => builder,
+        Err(e) => {
+            error!("Invalid proxy: {}", e);
+            return;
+        }
+    };
+    let http_client = match builder.build() {
+        Ok(v) => Arc::new(v),
+        Err(e) => {
+            error!("Failed to build reqwest client: {}", e);
+            return;
+        }
+    };
 
     // 注册命令
     let mut commands = CommandRegistry::default();
@@ -68,57 +123,125 @@ async fn main() {
     // 创建 MCP 加载器
     let mut mcp_loader = MCPRegistry::new();
     register_mcp(&mut mcp_loader);
-    let mcp_loader = Arc::new(Some(mcp_loader));
+    let mcp_loader = Arc::new(mcp_loader);
+    // 连接配置中的 MCP 服务器
+    mcp_client::connect_all(
+        config.mcp_servers.as_deref().unwrap_or_default(),
+        &mcp_loader,
+    )
+    .await;
     info!("MCP functions loaded");
 
+    // 创建 Markdown 渲染器
+    let renderer = Arc::new(MarkdownRenderer::new(&config));
+    info!("Markdown renderer loaded");
+
     // 创建 OpenAI 客户端
-    let client = Arc::new(OpenaiClient::build(config, http_client, mcp_loader).await);
+    let client = Arc::new(OpenaiClient::build(&config, http_client.clone(), mcp_loader).await);
     info!("OpenAI Client loaded");
 
+    // 共享资源
+    let res = Arc::new(Resources {
+        bot,
+        http_client,
+        client,
+        user_manager,
+        renderer,
+        config: Arc::new(ConfigStore::new(config_path, secrets.clone(), config)),
+        secrets,
+        data_path: Arc::new(data_path),
+        replies: Arc::new(PendingReplies::default()),
+    });
+
+    // 监听配置文件变更
+    reload::watch(Arc::clone(&res.config), Arc::clone(&res.client));
+
+    // 发送到期的提醒
+    reminder::spawn_scheduler(Arc::clone(&res));
+
+    // 执行定时任务
+    broadcast::spawn_scheduler(Arc::clone(&res), Arc::clone(&commands));
+
+    // 清理过期的群聊记录
+    summary::spawn_log_cleaner(Arc::clone(&res));
+
     // 回应戳一戳
     plugin::on_notice({
-        let user_manager = Arc::clone(&user_manager);
-        let client = Arc::clone(&client);
-        let bot = Arc::clone(&bot);
-        move |event| notice_handler(event, user_manager.clone(), client.clone(), bot.clone())
+        let res = Arc::clone(&res);
+        move |event| notice_handler(event, res.clone())
     });
 
     // 回应消息
-    let data_path_arc = Arc::new(data_path);
     plugin::on_msg({
         // 第一次 clone
-        let user_manager = Arc::clone(&user_manager);
-        let client = Arc::clone(&client);
         let commands = Arc::clone(&commands);
-        let data_path = Arc::clone(&data_path_arc);
+        let res = Arc::clone(&res);
 
         // move
         move |event| {
             // 第二次 clone
-            msg_handler(
-                event,
-                user_manager.clone(),
-                client.clone(),
-                commands.clone(),
-                data_path.clone(),
-            )
+            msg_handler(event, commands.clone(), res.clone())
         }
     });
 }
 
 async fn msg_handler(
     event: Arc<MsgEvent>,
-    user_manager: Arc<UserManager>,
-    client: Arc<OpenaiClient>,
     commands: Arc<CommandRegistry>,
-    data_path: Arc<PathBuf>,
+    res: Arc<Resources>,
 ) -> Result<(), Error> {
+    let Resources {
+        client,
+        user_manager,
+        renderer,
+        ..
+    } = &*res;
+
     let origin_json =
         OneBotMessage::from_json(&event.original_json).expect("Failed to parse message");
     let images = origin_json.find_image();
 
+    // 读取群设置
+    let config = res.config.load();
+    let group = match event.group_id {
+        Some(id) => Some(user_manager.get_group(id).await?),
+        None => None,
+    };
+    let trigger = group.as_ref().map_or(Trigger::At, |g| g.trigger(&config));
+
+    // 记录群聊消息，用于总结
+    if let (Some(group_id), Some(settings)) = (event.group_id, &group)
+        && settings.enabled()
+        && config.group_log_days != Some(0)
+        && !is_synthetic(&event)
+    {
+        let text = event.borrow_text().unwrap_or_default().trim();
+        let prefix = config.command_prefix();
+        let text = match (text.is_empty(), images.is_empty()) {
+            (true, true) => None,
+            (true, false) => Some("[图片]".to_string()),
+            (false, _) => commands
+                .find(text, prefix)
+                .is_none()
+                .then(|| text.to_string()),
+        };
+        if let Some(text) = text {
+            let message = LoggedMessage {
+                user_id: event.sender.user_id,
+                nickname: sender_name(&event).to_string(),
+                message_id: event.message_id,
+                time: event.time,
+                text,
+            };
+            if let Err(e) = user_manager.log_group_message(group_id, &message).await {
+                error!("Failed to log group message: {}", e);
+            }
+        }
+    }
+
     // 判断是否群聊被 At，私聊不需要 At
-    if event.is_group() && !origin_json.is_at(event.self_id) {
+    let mentioned = !event.is_group() || origin_json.is_at(event.self_id);
+    if trigger == Trigger::At && !mentioned {
         return Ok(());
     }
 
@@ -134,18 +257,110 @@ async fn msg_handler(
         }
     };
 
+    // 忽略被封禁的用户
+    let role = user_manager.get_role(event.sender.user_id).await?;
+    if role == Role::Banned {
+        return Ok(());
+    }
+
+    // 静音或安静时段只处理管理命令
+    if let Some(settings) = &group {
+        let now = kovi::chrono::Utc::now();
+        let muted = settings.muted(now);
+        if muted || settings.quiet(&config, now) {
+            let prefix = config.command_prefix();
+            let allowed = commands
+                .find(text, prefix)
+                .is_some_and(|(cmd, _)| ALWAYS_ALLOWED.contains(&cmd.name()));
+            if !allowed {
+                // 安静时段被 @ 时回复
+                if !muted
+                    && mentioned
+                    && let Some(reply) = &config.sleep_reply
+                {
+                    event.reply(reply.as_str());
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    // 指令与 AI 回复使用同一把锁，避免进行中的回复保存时覆盖指令的修改
+    // stop 需要在回复进行中执行，不加锁，也不修改用户数据
+    let stop = commands
+        .find(text, config.command_prefix())
+        .is_some_and(|(cmd, _)| cmd.name() == STOP_COMMAND);
+    let lock = if stop {
+        None
+    } else {
+        Some(user_manager.lock_context(event.sender.user_id).await)
+    };
+
     // 打开数据库
     let mut user = user_manager.load_user(event.sender.user_id).await?;
 
     // 处理指令
-    if commands.handle(text, &event, &mut user, &*data_path) {
+    if commands
+        .handle(text, &event, &mut user, role, group.as_ref(), &res)
+        .await
+    {
         // 保存用户数据
-        user_manager.save_user(&user).await?;
+        if !stop {
+            user_manager.save_user(&user).await?;
+        }
         return Ok(());
     }
+    // 锁不可重入，AI 回复前释放
+    drop(lock);
+
+    // 群聊的 AI 回复
+    let (context, text) = match (event.group_id, &group) {
+        (Some(group_id), Some(settings)) => {
+            let speaker = format!("{}({})", sender_name(&event), event.sender.user_id);
+            let chat = group_chat(
+                user_manager,
+                &config,
+                group_id,
+                settings,
+                event.sender.user_id,
+                &speaker,
+                text,
+            );
+            match chat.await? {
+                Some(v) => v,
+                None => return Ok(()),
+            }
+        }
+        _ => (event.sender.user_id, text.to_string()),
+    };
+    let text = text.as_str();
+
+    // 同一上下文的回复依次进行，避免互相覆盖聊天记录
+    let _lock = user_manager.lock_context(context).await;
+    let mut user = user_manager.load_user(context).await?;
 
     // 构造消息列表
-    if images.is_empty() {
+    if !images.is_empty() && !client.supports_vision() {
+        // 纯文本模型：先由视觉模型生成图片描述
+        let mut content = text.to_string();
+        for i in images {
+            let caption = match client.caption_image(&i).await {
+                Ok(v) => format!("[图片: {}]", v),
+                Err(e) => {
+                    error!("Failed to caption image: {}", e);
+                    "[图片]".to_string()
+                }
+            };
+            if !content.is_empty() {
+                content.push('\n');
+            }
+            content.push_str(&caption);
+        }
+        user.history.push(OpenaiMsg {
+            role: ChatRole::User,
+            content: MessageContent::Text(content),
+        })
+    } else if images.is_empty() {
         user.history.push(OpenaiMsg {
             role: ChatRole::User,
             content: MessageContent::Text(text.to_string()),
@@ -201,18 +416,40 @@ async fn msg_handler(
         })
     }
 
-    match client.chat(&mut user.history).await {
+    // 开放给 AI 的命令
+    let tools = CommandTools::new(
+        &commands,
+        &event,
+        event.sender.user_id,
+        role,
+        group.as_ref(),
+        &res,
+    );
+    let persona = group.as_ref().and_then(|g| g.persona.as_deref());
+    // 用户发送 stop 时丢弃回复的 future，进行中的工具调用一并取消
+    let pending = res.replies.start(event.sender.user_id);
+    let result = kovi::tokio::select! {
+        result = client.chat(&mut user.history, persona, Some(&tools)) => result,
+        _ = pending.cancelled() => {
+            info!("Reply to {} cancelled", event.sender.user_id);
+            return Ok(());
+        }
+    };
+    drop(pending);
+    match result {
         Ok(reply) => {
-            info!("Reply {} : {:?}", user.id, reply);
-            let reply = match reply {
-                MessageContent::Text(v) => KoviMsg::from(v),
+            info!("Reply {} : {:?}", user.id, reply.content);
+            save_reasoning(&res, &mut user, event.sender.user_id, reply.reasoning).await?;
+            log_chat(&res, &event, &reply.content).await;
+            let reply = match reply.content {
+                MessageContent::Text(v) => renderer.to_message(v).await,
                 MessageContent::Multi(v) => {
                     // 为什么会返回图片？？？
                     KoviMsg::from_value(serde_json::to_value(v)?)?
                 }
             };
             if event.is_group() {
-                let reply = Message::from(reply).add_reply(event.message_id);
+                let reply = reply.add_reply(event.message_id);
                 event.reply(reply)
             } else {
                 event.reply(reply)
@@ -229,12 +466,87 @@ async fn msg_handler(
     Ok(())
 }
 
-async fn notice_handler(
-    event: Arc<NoticeEvent>,
-    user_manager: Arc<UserManager>,
-    client: Arc<OpenaiClient>,
-    bot: Arc<RuntimeBot>,
+/// 群聊 AI 回复前的检查，返回使用的上下文 ID 与发送给 AI 的文本，不回复时返回 None
+async fn group_chat(
+    user_manager: &UserManager,
+    config: &Config,
+    group_id: i64,
+    settings: &GroupSettings,
+    user_id: i64,
+    speaker: &str,
+    text: &str,
+) -> Result<Option<(i64, String)>, Error> {
+    if !settings.enabled() || settings.trigger(config) == Trigger::Command {
+        return Ok(None);
+    }
+    // 检查每日配额
+    if let Some(limit) = settings.quota(config) {
+        let today = config
+            .local_time(kovi::chrono::Utc::now())
+            .format("%Y-%m-%d")
+            .to_string();
+        if !user_manager.take_quota(group_id, &today, limit).await? {
+            info!("Group {} has used up today's quota", group_id);
+            return Ok(None);
+        }
+    }
+    // 共享上下文时使用群的聊天记录，并标注发言者
+    Ok(Some(match settings.context(config) {
+        ContextScope::Group => (context_id(group_id), format!("{}: {}", speaker, text)),
+        ContextScope::User => (user_id, text.to_string()),
+    }))
+}
+
+/// 保存思考过程，共享上下文时记在发言者而非群的聊天记录上
+async fn save_reasoning(
+    res: &Resources,
+    context: &mut User,
+    user_id: i64,
+    reasoning: Option<String>,
 ) -> Result<(), Error> {
+    if context.id == user_id {
+        context.reasoning = reasoning;
+        return Ok(());
+    }
+    res.user_manager.set_reasoning(user_id, reasoning).await
+}
+
+/// 记录对话用于搜索
+async fn log_chat(res: &Resources, event: &MsgEvent, reply: &MessageContent) {
+    let mut messages = vec![];
+    if let Some(text) = event.borrow_text().filter(|t| !t.trim().is_empty()) {
+        messages.push((sender_name(event), event.time, text));
+    }
+    if let MessageContent::Text(text) = reply {
+        let now = kovi::chrono::Utc::now().timestamp();
+        messages.push((FORWARD_NICKNAME, now, text.as_str()));
+    }
+    for (nickname, time, text) in messages {
+        let message = LoggedMessage {
+            user_id: event.sender.user_id,
+            nickname: nickname.to_string(),
+            message_id: event.message_id,
+            time,
+            text: text.to_string(),
+        };
+        if let Err(e) = res
+            .user_manager
+            .log_chat_message(event.group_id, &message)
+            .await
+        {
+            error!("Failed to log chat message: {}", e);
+        }
+    }
+}
+
+async fn notice_handler(event: Arc<NoticeEvent>, res: Arc<Resources>) -> Result<(), Error> {
+    let Resources {
+        bot,
+        client,
+        user_manager,
+        renderer,
+        ..
+    } = &*res;
     #[derive(Deserialize)]
     struct Notice {
         group_id: Option<i64>,
@@ -247,26 +559,72 @@ async fn notice_handler(
         return Ok(());
     }
 
+    // 忽略被封禁的用户
+    if user_manager.get_role(notice.user_id).await? == Role::Banned {
+        return Ok(());
+    }
+
+    // 忽略已关闭、静音或处于安静时段的群
+    let group = match notice.group_id {
+        Some(id) => Some(user_manager.get_group(id).await?),
+        None => None,
+    };
+    let config = res.config.load();
+    let now = kovi::chrono::Utc::now();
+    if group
+        .as_ref()
+        .is_some_and(|g| g.muted(now) || g.quiet(&config, now))
+    {
+        return Ok(());
+    }
+
+    // 与消息相同的触发方式、配额与上下文检查，戳一戳视为被 @
+    let text = "(戳一戳)";
+    let (context, text) = match (notice.group_id, &group) {
+        (Some(group_id), Some(settings)) => {
+            let speaker = notice.user_id.to_string();
+            let chat = group_chat(
+                user_manager,
+                &config,
+                group_id,
+                settings,
+                notice.user_id,
+                &speaker,
+                text,
+            );
+            match chat.await? {
+                Some(v) => v,
+                None => return Ok(()),
+            }
+        }
+        _ => (notice.user_id, text.to_string()),
+    };
+
     info!("User {} send a poke", notice.user_id);
 
     // 打开数据库
-    let mut user = user_manager.load_user(notice.user_id).await?;
+    let _lock = user_manager.lock_context(context).await;
+    let mut user = user_manager.load_user(context).await?;
 
     // 构造消息列表
     user.history.push(OpenaiMsg {
         role: ChatRole::User,
         // 暂时仅支持默认文本
-        content: MessageContent::Text("(戳一戳)".to_string()),
+        content: MessageContent::Text(text),
     });
 
     // 获取 AI 回复
-    let reply = client.chat(&mut user.history).await?;
+    let persona = group.as_ref().and_then(|g| g.persona.as_deref());
+    let reply = client.chat(&mut user.history, persona, None).await?;
+    save_reasoning(&res, &mut user, notice.user_id, reply.reasoning).await?;
     // 仅处理文本回复
-    let reply = KoviMsg::from(if let MessageContent::Text(v) = reply {
-        v
-    } else {
-        return Err(Error::msg("Reply contain Multi"));
-    });
+    let reply = renderer
+        .to_message(if let MessageContent::Text(v) = reply.content {
+            v
+        } else {
+            return Err(Error::msg("Reply contain Multi"));
+        })
+        .await;
 
     info!("Reply {} : {:?}", user.id, reply);
     if let Some(group) = notice.group_id {
@@ -0,0 +1,813 @@
+use crate::mcp_http::{HttpClient, SseClient};
+use crate::mcp_loader::{MCP, MCPRegistry, SharedMCP};
+use crate::secrets::{info, warn};
+use anyhow::{Error, anyhow};
+use async_trait::async_trait;
+use kovi::tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use kovi::tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use kovi::tokio::sync::{Mutex, Notify, oneshot};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+
+/// 使用的 MCP 协议版本
+pub const PROTOCOL_VERSION: &str = "2025-03-26";
+/// 单次请求默认的超时时间
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// 工具名中服务器名与工具名的分隔符
+const NAME_SEPARATOR: &str = "__";
+/// 连接失败后重试的间隔，每次翻倍
+pub const RETRY_DELAY: Duration = Duration::from_secs(5);
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+/// HTTP 服务器的传输方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpTransport {
+    /// streamable HTTP
+    #[default]
+    #[serde(alias = "streamable")]
+    Http,
+    /// 旧版 HTTP+SSE
+    Sse,
+}
+
+/// 配置文件中的 MCP 服务器，command 与 url 二选一
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct McpServerConfig {
+    /// 服务器名，作为工具名的前缀
+    pub name: String,
+    /// 启动命令
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// 额外的环境变量
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// HTTP 服务器地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<HttpTransport>,
+    /// 请求头，如 Authorization，值支持 file: 引用
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// 单次请求的超时秒数，默认 60
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+impl McpServerConfig {
+    /// 检查配置是否有效
+    pub fn check(&self) -> Result<(), String> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "name must only contain letters, digits, - and _, got {:?}",
+                self.name
+            ));
+        }
+        match (&self.command, &self.url) {
+            (Some(command), None) => {
+                if command.trim().is_empty() {
+                    return Err("command is empty".to_string());
+                }
+                if self.transport.is_some() || !self.headers.is_empty() {
+                    return Err("transport and headers only apply to url".to_string());
+                }
+            }
+            (None, Some(url)) => {
+                let scheme = reqwest::Url::parse(url).map(|u| u.scheme().to_string());
+                if !matches!(scheme.as_deref(), Ok("http" | "https")) {
+                    return Err(format!("url must be http(s), got {}", url));
+                }
+                for (key, value) in &self.headers {
+                    if reqwest::header::HeaderName::try_from(key.as_str()).is_err()
+                        || reqwest::header::HeaderValue::try_from(value.as_str()).is_err()
+                    {
+                        return Err(format!("invalid header {}", key));
+                    }
+                }
+            }
+            _ => return Err("exactly one of command and url must be set".to_string()),
+        }
+        if self.timeout == Some(0) {
+            return Err("timeout must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// 单次请求的超时时间
+    pub fn request_timeout(&self) -> Duration {
+        self.timeout.map_or(REQUEST_TIMEOUT, Duration::from_secs)
+    }
+}
+
+/// 服务器提供的工具
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "empty_schema")]
+    pub input_schema: Value,
+}
+
+fn empty_schema() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+/// MCP 客户端，不同传输方式的共同接口
+#[async_trait]
+pub trait McpClient: Send + Sync {
+    /// 服务器名
+    fn name(&self) -> &str;
+
+    /// 分配请求 ID
+    fn next_id(&self) -> i64;
+
+    /// 发送请求并等待 result，超时与取消由 request 处理
+    async fn send(&self, id: i64, method: &str, params: Value) -> Result<Value, Error>;
+
+    /// 发送通知
+    async fn notify(&self, method: &str, params: Value) -> Result<(), Error>;
+
+    /// 单次请求的超时时间
+    fn timeout(&self) -> Duration;
+
+    /// 工具列表变化或重新连接后触发
+    fn tools_changed(&self) -> &Notify;
+}
+
+/// 请求未完成就被丢弃 (超时或用户取消) 时通知服务器取消
+struct CancelGuard {
+    client: Option<Arc<dyn McpClient>>,
+    id: i64,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        let (Some(client), Ok(handle)) = (
+            self.client.take(),
+            kovi::tokio::runtime::Handle::try_current(),
+        ) else {
+            return;
+        };
+        let params = json!({ "requestId": self.id, "reason": "request cancelled" });
+        handle.spawn(async move {
+            if let Err(e) = client.notify("notifications/cancelled", params).await {
+                warn!(
+                    "Failed to cancel request of MCP server {}: {}",
+                    client.name(),
+                    e
+                );
+            }
+        });
+    }
+}
+
+/// 发送请求，future 被丢弃时通知服务器取消
+async fn send_cancellable(
+    client: &Arc<dyn McpClient>,
+    method: &str,
+    params: Value,
+) -> Result<Value, Error> {
+    let id = client.next_id();
+    let mut guard = CancelGuard {
+        client: Some(Arc::clone(client)),
+        id,
+    };
+    let result = client.send(id, method, params).await;
+    guard.client = None;
+    result
+}
+
+/// 发送请求，超时后通知服务器取消
+pub async fn request(
+    client: &Arc<dyn McpClient>,
+    method: &str,
+    params: Value,
+) -> Result<Value, Error> {
+    kovi::tokio::time::timeout(client.timeout(), send_cancellable(client, method, params))
+        .await
+        .map_err(|_| anyhow!("MCP server {} timed out on {}", client.name(), method))?
+}
+
+/// 获取全部工具
+pub async fn list_tools(client: &Arc<dyn McpClient>) -> Result<Vec<ToolInfo>, Error> {
+    let mut tools = vec![];
+    let mut cursor: Option<String> = None;
+    loop {
+        let params = match &cursor {
+            Some(c) => json!({ "cursor": c }),
+            None => json!({}),
+        };
+        let result = request(client, "tools/list", params).await?;
+        let page: Vec<ToolInfo> = serde_json::from_value(result["tools"].clone())?;
+        tools.extend(page);
+        cursor = result["nextCursor"].as_str().map(String::from);
+        if cursor.is_none() {
+            return Ok(tools);
+        }
+    }
+}
+
+/// 调用工具，返回其中的文本内容，工具返回 isError 时为 Err；
+/// 不设超时，由 MCPRegistry::call 按 RemoteTool 的超时时间处理
+pub async fn call_tool(
+    client: &Arc<dyn McpClient>,
+    name: &str,
+    arguments: Value,
+) -> Result<String, Error> {
+    let params = json!({ "name": name, "arguments": arguments });
+    let result = send_cancellable(client, "tools/call", params).await?;
+    let text = tool_result_text(&result);
+    if result["isError"].as_bool() == Some(true) {
+        return Err(anyhow!("{}", text));
+    }
+    Ok(text)
+}
+
+/// initialize 请求的参数
+pub fn initialize_params() -> Value {
+    json!({
+        "protocolVersion": PROTOCOL_VERSION,
+        "capabilities": {},
+        "clientInfo": { "name": "rosmontis-bot", "version": env!("CARGO_PKG_VERSION") }
+    })
+}
+
+/// 若为 id 对应的回复，返回 result 或错误信息
+pub fn parse_response(message: &Value, id: i64) -> Option<Result<Value, String>> {
+    if message.get("method").is_some() || message.get("id").and_then(Value::as_i64) != Some(id) {
+        return None;
+    }
+    if let Some(error) = message.get("error") {
+        let text = error["message"].as_str().unwrap_or("unknown error");
+        return Some(Err(format!("{} ({})", text, error["code"])));
+    }
+    Some(Ok(message.get("result").cloned().unwrap_or(Value::Null)))
+}
+
+/// 处理服务器发来的通知与请求，返回需要发送的回复，目前只回应 ping
+pub fn handle_server_message(message: &Value, tools_changed: &Notify) -> Option<Value> {
+    let method = message.get("method")?.as_str()?;
+    if method == "notifications/tools/list_changed" {
+        tools_changed.notify_one();
+    }
+    let id = message.get("id")?;
+    Some(match method {
+        "ping" => json!({ "jsonrpc": "2.0", "id": id, "result": {} }),
+        _ => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32601, "message": "Method not found" }
+        }),
+    })
+}
+
+/// 等待回复的请求
+pub type Pending = HashMap<i64, oneshot::Sender<Result<Value, String>>>;
+
+/// 离开作用域时移除等待中的请求
+pub struct PendingGuard<'a> {
+    pub pending: &'a std::sync::Mutex<Pending>,
+    pub id: i64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// 子进程的 stdin
+struct Stdin {
+    stdin: ChildStdin,
+    /// 写入中途被取消时为 true，此时消息不完整，需要重启
+    writing: bool,
+}
+
+impl Stdin {
+    async fn send(&mut self, message: &Value) -> Result<(), Error> {
+        let mut line = message.to_string();
+        line.push('\n');
+        self.writing = true;
+        self.stdin.write_all(line.as_bytes()).await?;
+        self.stdin.flush().await?;
+        self.writing = false;
+        Ok(())
+    }
+}
+
+/// 与子进程的连接，回复由读取 stdout 的任务按 ID 交给等待中的请求
+struct Connection {
+    child: Child,
+    stdin: Arc<Mutex<Stdin>>,
+    pending: Arc<std::sync::Mutex<Pending>>,
+    /// stdout 已关闭
+    closed: Arc<AtomicBool>,
+}
+
+impl Connection {
+    fn handle(&self) -> Handle {
+        Handle {
+            stdin: self.stdin.clone(),
+            pending: self.pending.clone(),
+            closed: self.closed.clone(),
+        }
+    }
+
+    /// 连接已断开或写入了不完整的消息
+    fn broken(&mut self) -> Result<bool, Error> {
+        Ok(self.closed.load(Ordering::Relaxed)
+            || self.stdin.try_lock().is_ok_and(|s| s.writing)
+            || self.child.try_wait()?.is_some())
+    }
+
+    /// 读取 stdout，分发回复并回应服务器的请求，关闭时结束所有等待中的请求
+    async fn read(
+        name: String,
+        mut stdout: Lines<BufReader<ChildStdout>>,
+        stdin: Arc<Mutex<Stdin>>,
+        pending: Arc<std::sync::Mutex<Pending>>,
+        closed: Arc<AtomicBool>,
+        tools_changed: Arc<Notify>,
+    ) {
+        while let Ok(Some(line)) = stdout.next_line().await {
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                warn!("Ignore invalid line from MCP server: {}", line);
+                continue;
+            };
+            if let Some(id) = message.get("id").and_then(Value::as_i64)
+                && message.get("method").is_none()
+            {
+                let sender = pending.lock().unwrap().remove(&id);
+                if let (Some(sender), Some(result)) = (sender, parse_response(&message, id)) {
+                    let _ = sender.send(result);
+                }
+                continue;
+            }
+            if let Some(reply) = handle_server_message(&message, &tools_changed)
+                && let Err(e) = stdin.lock().await.send(&reply).await
+            {
+                warn!("Failed to reply MCP server {}: {}", name, e);
+            }
+        }
+        closed.store(true, Ordering::SeqCst);
+        // 丢弃后等待中的请求会收到错误
+        pending.lock().unwrap().clear();
+    }
+}
+
+/// 连接的句柄，请求时不需要持有连接的锁
+struct Handle {
+    stdin: Arc<Mutex<Stdin>>,
+    pending: Arc<std::sync::Mutex<Pending>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Handle {
+    /// 发送请求并等待对应的回复，外层错误表示连接已断开，内层为服务器返回的错误；
+    /// 只在写入时锁住 stdin，同一服务器的多个请求可同时等待
+    async fn request(
+        &self,
+        id: i64,
+        method: &str,
+        params: Value,
+    ) -> Result<Result<Value, String>, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        // 失败或被取消时移除等待中的请求
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            id,
+        };
+        // 读取任务先标记关闭再清空，加入后再检查以免错过
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(anyhow!("Server closed stdout"));
+        }
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.stdin.lock().await.send(&message).await?;
+        receiver.await.map_err(|_| anyhow!("Server closed stdout"))
+    }
+}
+
+/// 通过 stdio 通信的 MCP 客户端，子进程退出后在下次请求时重启
+pub struct StdioClient {
+    config: McpServerConfig,
+    connection: Mutex<Option<Connection>>,
+    next_id: AtomicI64,
+    tools_changed: Arc<Notify>,
+}
+
+impl StdioClient {
+    /// 启动服务器并完成握手
+    pub async fn connect(config: McpServerConfig) -> Result<Arc<Self>, Error> {
+        let client = Arc::new(StdioClient {
+            config,
+            connection: Mutex::new(None),
+            next_id: AtomicI64::new(1),
+            tools_changed: Arc::new(Notify::new()),
+        });
+        *client.connection.lock().await = Some(client.start().await?);
+        Ok(client)
+    }
+
+    /// 启动子进程并发送 initialize
+    async fn start(&self) -> Result<Connection, Error> {
+        let name = &self.config.name;
+        let command = self.config.command.as_deref().unwrap_or_default();
+        let mut child = Command::new(command)
+            .args(&self.config.args)
+            .envs(&self.config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("Failed to start MCP server {}: {}", name, e))?;
+
+        // 转发服务器的日志，子进程退出时结束
+        if let Some(stderr) = child.stderr.take() {
+            let name = name.clone();
+            kovi::tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    info!("[MCP {}] {}", name, line);
+                }
+            });
+        }
+        let stdin = Stdin {
+            stdin: child.stdin.take().ok_or_else(|| anyhow!("No stdin"))?,
+            writing: false,
+        };
+        let stdout =
+            BufReader::new(child.stdout.take().ok_or_else(|| anyhow!("No stdout"))?).lines();
+        let connection = Connection {
+            child,
+            stdin: Arc::new(Mutex::new(stdin)),
+            pending: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            closed: Arc::new(AtomicBool::new(false)),
+        };
+        // 读取任务在子进程退出、stdout 关闭后结束
+        kovi::tokio::spawn(Connection::read(
+            name.clone(),
+            stdout,
+            connection.stdin.clone(),
+            connection.pending.clone(),
+            connection.closed.clone(),
+            self.tools_changed.clone(),
+        ));
+
+        let handle = connection.handle();
+        let result = handle
+            .request(self.next_id(), "initialize", initialize_params())
+            .await?
+            .map_err(|e| anyhow!("MCP server {} refused initialize: {}", name, e))?;
+        handle
+            .stdin
+            .lock()
+            .await
+            .send(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+            .await?;
+        info!(
+            "MCP server {} started: {}",
+            name,
+            result.get("serverInfo").unwrap_or(&Value::Null)
+        );
+        Ok(connection)
+    }
+
+    /// 获取连接的句柄，子进程已退出时先重启
+    async fn handle(&self) -> Result<Handle, Error> {
+        let mut guard = self.connection.lock().await;
+        let broken = match guard.as_mut() {
+            Some(c) => c.broken()?,
+            None => true,
+        };
+        if broken {
+            warn!("MCP server {} is not running, restarting", self.config.name);
+            *guard = None;
+            *guard = Some(self.start().await?);
+            // 重启后的工具可能不同
+            self.tools_changed.notify_one();
+        }
+        guard
+            .as_ref()
+            .map(Connection::handle)
+            .ok_or_else(|| anyhow!("MCP server {} is not running", self.config.name))
+    }
+}
+
+#[async_trait]
+impl McpClient for StdioClient {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn next_id(&self) -> i64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// 子进程已退出时先重启，请求中途断开时不重试以免重复执行
+    async fn send(&self, id: i64, method: &str, params: Value) -> Result<Value, Error> {
+        let name = &self.config.name;
+        let handle = self.handle().await?;
+        match handle.request(id, method, params).await {
+            Ok(result) => result.map_err(|e| anyhow!("MCP server {} error: {}", name, e)),
+            Err(e) => {
+                // 下次请求时重启
+                handle.closed.store(true, Ordering::Relaxed);
+                Err(anyhow!("MCP server {} disconnected: {}", name, e))
+            }
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), Error> {
+        let stdin = match self.connection.lock().await.as_ref() {
+            Some(c) if !c.closed.load(Ordering::Relaxed) => c.stdin.clone(),
+            _ => return Ok(()),
+        };
+        let mut stdin = stdin.lock().await;
+        if stdin.writing {
+            return Ok(());
+        }
+        stdin
+            .send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .await
+    }
+
+    fn timeout(&self) -> Duration {
+        self.config.request_timeout()
+    }
+
+    fn tools_changed(&self) -> &Notify {
+        &self.tools_changed
+    }
+}
+
+/// 提取工具结果中的文本，其他类型的内容以占位符表示
+fn tool_result_text(result: &Value) -> String {
+    let parts: Vec<String> = result["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|c| match c["type"].as_str() {
+            Some("text") => c["text"].as_str().unwrap_or_default().to_string(),
+            Some("resource") => c["resource"]["text"]
+                .as_str()
+                .map_or_else(|| "[资源]".to_string(), String::from),
+            Some(kind) => format!("[{}]", kind),
+            None => String::new(),
+        })
+        .collect();
+    match (parts.is_empty(), result.get("structuredContent")) {
+        (true, Some(v)) => v.to_string(),
+        _ => parts.join("\n"),
+    }
+}
+
+/// 远程 MCP 服务器提供的工具
+struct RemoteTool {
+    client: Arc<dyn McpClient>,
+    /// 注册到 MCPRegistry 的名称，带服务器名前缀
+    name: String,
+    info: ToolInfo,
+}
+
+#[async_trait]
+impl MCP for RemoteTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.info.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.info.input_schema.clone()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Some(self.client.timeout())
+    }
+
+    async fn execute(&self, args: Value) -> Result<Value, Error> {
+        let text = call_tool(&self.client, &self.info.name, args).await?;
+        Ok(Value::String(text))
+    }
+}
+
+/// 按配置的传输方式连接服务器并完成握手
+async fn connect(
+    config: &McpServerConfig,
+    http: &reqwest::Client,
+) -> Result<Arc<dyn McpClient>, Error> {
+    let connect = async {
+        Ok::<Arc<dyn McpClient>, Error>(match (&config.url, config.transport.unwrap_or_default()) {
+            (None, _) => StdioClient::connect(config.clone()).await?,
+            (Some(_), HttpTransport::Http) => HttpClient::connect(config, http.clone()).await?,
+            (Some(_), HttpTransport::Sse) => SseClient::connect(config, http.clone()).await?,
+        })
+    };
+    kovi::tokio::time::timeout(config.request_timeout(), connect)
+        .await
+        .map_err(|_| anyhow!("MCP server {} initialize timed out", config.name))?
+}
+
+/// 获取服务器的工具并替换注册器中的旧工具，返回工具数
+async fn refresh_tools(
+    client: &Arc<dyn McpClient>,
+    name: &str,
+    registry: &MCPRegistry,
+) -> Result<usize, Error> {
+    let prefix = format!("{}{}", name, NAME_SEPARATOR);
+    let tools: Vec<SharedMCP> = list_tools(client)
+        .await?
+        .into_iter()
+        .map(|info| {
+            Arc::new(RemoteTool {
+                client: Arc::clone(client),
+                name: format!("{}{}", prefix, info.name),
+                info,
+            }) as SharedMCP
+        })
+        .collect();
+    let count = tools.len();
+    registry.replace(&prefix, tools);
+    Ok(count)
+}
+
+/// 工具列表变化时刷新注册器
+async fn watch_tools(name: String, registry: Arc<MCPRegistry>, client: Arc<dyn McpClient>) {
+    loop {
+        client.tools_changed().notified().await;
+        match refresh_tools(&client, &name, &registry).await {
+            Ok(n) => info!("MCP server {} tools refreshed, {} tools", name, n),
+            Err(e) => warn!("Failed to refresh tools of MCP server {}: {}", name, e),
+        }
+    }
+}
+
+/// 连接配置中的所有 MCP 服务器并注册其工具，连接失败的服务器在后台重试
+pub async fn connect_all(servers: &[McpServerConfig], registry: &Arc<MCPRegistry>) {
+    let http = reqwest::Client::new();
+    for config in servers {
+        let name = config.name.clone();
+        let result = async {
+            let client = connect(config, &http).await?;
+            let count = refresh_tools(&client, &name, registry).await?;
+            Ok::<_, Error>((client, count))
+        }
+        .await;
+        match result {
+            Ok((client, count)) => {
+                info!("MCP server {} provides {} tools", name, count);
+                kovi::tokio::spawn(watch_tools(name, Arc::clone(registry), client));
+            }
+            Err(e) => {
+                warn!("Failed to connect MCP server {}, retry later: {}", name, e);
+                kovi::tokio::spawn(retry(config.clone(), Arc::clone(registry), http.clone()));
+            }
+        }
+    }
+}
+
+/// 在后台重试连接
+async fn retry(config: McpServerConfig, registry: Arc<MCPRegistry>, http: reqwest::Client) {
+    let mut delay = RETRY_DELAY;
+    loop {
+        kovi::tokio::time::sleep(delay).await;
+        let client = match connect(&config, &http).await {
+            Ok(v) => v,
+            Err(e) => {
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+                warn!("Failed to connect MCP server {}: {}", config.name, e);
+                continue;
+            }
+        };
+        match refresh_tools(&client, &config.name, &registry).await {
+            Ok(n) => info!("MCP server {} connected, {} tools", config.name, n),
+            Err(e) => warn!("Failed to list tools of MCP server {}: {}", config.name, e),
+        }
+        return watch_tools(config.name, registry, client).await;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::mcp_client::*;
+    use crate::mcp_loader::ToolError;
+
+    /// cargo test 会同时编译 examples/mcp_fixture.rs，未编译时直接失败而不是跳过测试
+    pub fn fixture_path() -> String {
+        let exe = std::env::current_exe().unwrap();
+        let path = exe
+            .parent()
+            .and_then(|p| p.parent())
+            .unwrap()
+            .join("examples")
+            .join("mcp_fixture");
+        let path = if cfg!(windows) {
+            path.with_extension("exe")
+        } else {
+            path
+        };
+        assert!(
+            path.is_file(),
+            "{} not found, run `cargo build --example mcp_fixture` first",
+            path.display()
+        );
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_stdio_client() {
+        let path = fixture_path();
+        let config = McpServerConfig {
+            name: "fixture".to_string(),
+            command: Some(path),
+            timeout: Some(1),
+            ..Default::default()
+        };
+        assert!(config.check().is_ok());
+        let runtime = kovi::tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let registry = Arc::new(MCPRegistry::new());
+            connect_all(std::slice::from_ref(&config), &registry).await;
+            let mut names: Vec<String> = registry.functions().into_iter().map(|f| f.name).collect();
+            names.sort();
+            assert_eq!(
+                names,
+                [
+                    "fixture__add",
+                    "fixture__cancelled",
+                    "fixture__change",
+                    "fixture__crash",
+                    "fixture__echo",
+                    "fixture__expire",
+                    "fixture__sleep"
+                ]
+            );
+
+            let client: Arc<dyn McpClient> = StdioClient::connect(config).await.unwrap();
+            let sum = call_tool(&client, "add", json!({ "a": 1, "b": 2 })).await;
+            assert_eq!(sum.unwrap(), "3");
+            assert!(call_tool(&client, "missing", json!({})).await.is_err());
+
+            // 崩溃后下次调用自动重启
+            assert!(call_tool(&client, "crash", json!({})).await.is_err());
+            let echo = call_tool(&client, "echo", json!({ "text": "hi" })).await;
+            assert_eq!(echo.unwrap(), "hi");
+
+            // 通过 MCP trait 调用
+            let timeout = Duration::from_secs(10);
+            let echo = registry
+                .call("fixture__echo", json!({ "text": "ok" }), timeout)
+                .await;
+            assert_eq!(echo.unwrap(), json!("ok"));
+
+            // 超时后通知服务器取消
+            let sleep = registry
+                .call("fixture__sleep", json!({ "seconds": 5 }), timeout)
+                .await;
+            assert!(matches!(sleep, Err(ToolError::Timeout(_))));
+            let mut cancelled = String::new();
+            for _ in 0..20 {
+                cancelled = registry
+                    .call("fixture__cancelled", json!({}), timeout)
+                    .await
+                    .unwrap()
+                    .to_string();
+                if cancelled == "\"1\"" {
+                    break;
+                }
+                kovi::tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            assert_eq!(cancelled, "\"1\"");
+
+            // 等待回复时不阻塞同一服务器的其他请求
+            let slow = call_tool(&client, "sleep", json!({ "seconds": 5 }));
+            let fast = call_tool(&client, "echo", json!({ "text": "fast" }));
+            kovi::tokio::select! {
+                echo = fast => assert_eq!(echo.unwrap(), "fast"),
+                _ = slow => panic!("echo waited for sleep"),
+            }
+        });
+    }
+}
@@ -0,0 +1,692 @@
+use crate::mcp_client::{
+    MAX_RETRY_DELAY, McpClient, McpServerConfig, Pending, PendingGuard, RETRY_DELAY,
+    handle_server_message, initialize_params, parse_response,
+};
+use crate::secrets::{info, warn};
+use anyhow::{Error, anyhow};
+use async_trait::async_trait;
+use kovi::tokio::sync::{Mutex, Notify, oneshot, watch};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Response, StatusCode};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// 会话 ID 的请求头
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+
+/// 一个 SSE 事件
+#[derive(Debug, PartialEq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+/// 增量解析 SSE 事件流
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// 输入一段数据，返回其中完整的事件
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = vec![];
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(SseEvent {
+                        event: self.event.take().unwrap_or_else(|| "message".to_string()),
+                        data: std::mem::take(&mut self.data).join("\n"),
+                    });
+                }
+                self.event = None;
+                continue;
+            }
+            // 以冒号开头的是注释
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// 把配置中的请求头转换为 HeaderMap
+fn header_map(config: &McpServerConfig) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+    for (key, value) in &config.headers {
+        headers.insert(
+            HeaderName::try_from(key.as_str())?,
+            HeaderValue::try_from(value.as_str())?,
+        );
+    }
+    Ok(headers)
+}
+
+/// streamable HTTP 的 MCP 客户端，会话失效时自动重新初始化
+pub struct HttpClient {
+    name: String,
+    url: String,
+    headers: HeaderMap,
+    http: reqwest::Client,
+    /// 服务器分配的会话 ID
+    session: std::sync::RwLock<Option<String>>,
+    next_id: AtomicI64,
+    timeout: Duration,
+    tools_changed: Notify,
+    /// 避免多个请求同时重新初始化
+    init: Mutex<()>,
+}
+
+impl HttpClient {
+    /// 连接服务器并完成握手
+    pub async fn connect(
+        config: &McpServerConfig,
+        http: reqwest::Client,
+    ) -> Result<Arc<Self>, Error> {
+        let client = Arc::new(HttpClient {
+            name: config.name.clone(),
+            url: config.url.clone().unwrap_or_default(),
+            headers: header_map(config)?,
+            http,
+            session: std::sync::RwLock::new(None),
+            next_id: AtomicI64::new(1),
+            timeout: config.request_timeout(),
+            tools_changed: Notify::new(),
+            init: Mutex::new(()),
+        });
+        client.initialize().await?;
+        kovi::tokio::spawn(Self::listen(Arc::downgrade(&client)));
+        Ok(client)
+    }
+
+    fn session(&self) -> Option<String> {
+        self.session.read().unwrap().clone()
+    }
+
+    /// 发送一条消息
+    async fn post(&self, message: &Value) -> Result<Response, Error> {
+        let mut request = self
+            .http
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .header(ACCEPT, "application/json, text/event-stream")
+            .json(message);
+        if let Some(session) = self.session() {
+            request = request.header(SESSION_HEADER, session);
+        }
+        Ok(request.send().await?)
+    }
+
+    /// 发送 initialize 并保存会话 ID
+    async fn initialize(&self) -> Result<(), Error> {
+        *self.session.write().unwrap() = None;
+        let id = self.next_id();
+        let message = json!({
+            "jsonrpc": "2.0", "id": id, "method": "initialize", "params": initialize_params()
+        });
+        let response = self.post(&message).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "MCP server {} refused initialize: {}",
+                self.name,
+                response.status()
+            ));
+        }
+        let session = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let result = self
+            .read_response(response, id)
+            .await?
+            .map_err(|e| anyhow!("MCP server {} refused initialize: {}", self.name, e))?;
+        *self.session.write().unwrap() = session;
+        self.post(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+            .await?;
+        info!(
+            "MCP server {} connected: {}",
+            self.name,
+            result.get("serverInfo").unwrap_or(&Value::Null)
+        );
+        Ok(())
+    }
+
+    /// 读取回复，可能是 JSON 或事件流，事件流中的通知与请求一并处理
+    async fn read_response(
+        &self,
+        mut response: Response,
+        id: i64,
+    ) -> Result<Result<Value, String>, Error> {
+        let is_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        if !is_stream {
+            let message: Value = response.json().await?;
+            return parse_response(&message, id)
+                .ok_or_else(|| anyhow!("MCP server {} sent unexpected reply", self.name));
+        }
+        let mut parser = SseParser::default();
+        while let Some(bytes) = response.chunk().await? {
+            for event in parser.feed(&bytes) {
+                if let Some(result) = self.handle_event(&event, Some(id)).await {
+                    return Ok(result);
+                }
+            }
+        }
+        Err(anyhow!("MCP server {} closed the stream", self.name))
+    }
+
+    /// 处理事件流中的一条消息，若为 id 对应的回复则返回
+    async fn handle_event(
+        &self,
+        event: &SseEvent,
+        id: Option<i64>,
+    ) -> Option<Result<Value, String>> {
+        if event.event != "message" {
+            return None;
+        }
+        let Ok(message) = serde_json::from_str::<Value>(&event.data) else {
+            warn!("Ignore invalid event from MCP server {}", self.name);
+            return None;
+        };
+        if let Some(result) = id.and_then(|id| parse_response(&message, id)) {
+            return Some(result);
+        }
+        if let Some(reply) = handle_server_message(&message, &self.tools_changed)
+            && let Err(e) = self.post(&reply).await
+        {
+            warn!("Failed to reply MCP server {}: {}", self.name, e);
+        }
+        None
+    }
+
+    /// 发送请求，会话不存在时返回 None
+    async fn send_request(
+        &self,
+        id: i64,
+        method: &str,
+        params: &Value,
+    ) -> Result<Option<Value>, Error> {
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let response = self.post(&message).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND if self.session().is_some() => return Ok(None),
+            status if !status.is_success() => {
+                return Err(anyhow!("MCP server {} returned {}", self.name, status));
+            }
+            _ => {}
+        }
+        self.read_response(response, id)
+            .await?
+            .map(Some)
+            .map_err(|e| anyhow!("MCP server {} error: {}", self.name, e))
+    }
+
+    /// 会话失效后重新初始化，其他请求已完成时跳过
+    async fn reconnect(&self, expired: Option<String>) -> Result<(), Error> {
+        let _guard = self.init.lock().await;
+        if self.session() != expired {
+            return Ok(());
+        }
+        warn!("MCP server {} session expired, reconnecting", self.name);
+        self.initialize().await?;
+        // 新会话中的工具可能不同
+        self.tools_changed.notify_one();
+        Ok(())
+    }
+
+    /// 通过 GET 接收服务器主动发送的通知，服务器不支持时结束
+    async fn listen(client: Weak<Self>) {
+        let mut delay = RETRY_DELAY;
+        loop {
+            let Some(this) = client.upgrade() else {
+                return;
+            };
+            let mut request = this
+                .http
+                .get(&this.url)
+                .headers(this.headers.clone())
+                .header(ACCEPT, "text/event-stream");
+            if let Some(session) = this.session() {
+                request = request.header(SESSION_HEADER, session);
+            }
+            match request.send().await {
+                Ok(response) if response.status() == StatusCode::METHOD_NOT_ALLOWED => return,
+                Ok(mut response) if response.status().is_success() => {
+                    delay = RETRY_DELAY;
+                    let mut parser = SseParser::default();
+                    while let Ok(Some(bytes)) = response.chunk().await {
+                        for event in parser.feed(&bytes) {
+                            this.handle_event(&event, None).await;
+                        }
+                    }
+                }
+                Ok(response) => warn!(
+                    "MCP server {} rejected event stream: {}",
+                    this.name,
+                    response.status()
+                ),
+                Err(e) => warn!("MCP server {} event stream failed: {}", this.name, e),
+            }
+            drop(this);
+            kovi::tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+}
+
+#[async_trait]
+impl McpClient for HttpClient {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn next_id(&self) -> i64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn send(&self, id: i64, method: &str, params: Value) -> Result<Value, Error> {
+        let session = self.session();
+        if let Some(result) = self.send_request(id, method, &params).await? {
+            return Ok(result);
+        }
+        self.reconnect(session).await?;
+        self.send_request(id, method, &params)
+            .await?
+            .ok_or_else(|| anyhow!("MCP server {} session expired", self.name))
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), Error> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        let status = self.post(&message).await?.status();
+        if !status.is_success() {
+            return Err(anyhow!("MCP server {} returned {}", self.name, status));
+        }
+        Ok(())
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn tools_changed(&self) -> &Notify {
+        &self.tools_changed
+    }
+}
+
+/// 旧版 HTTP+SSE 的 MCP 客户端，回复通过 GET 的事件流返回，断开后自动重连
+pub struct SseClient {
+    name: String,
+    url: String,
+    headers: HeaderMap,
+    http: reqwest::Client,
+    /// 发送消息的地址，事件流断开时为 None
+    endpoint: watch::Sender<Option<String>>,
+    /// 已完成初始化的地址
+    initialized: Mutex<Option<String>>,
+    /// 等待回复的请求
+    pending: std::sync::Mutex<Pending>,
+    next_id: AtomicI64,
+    timeout: Duration,
+    tools_changed: Notify,
+}
+
+impl SseClient {
+    /// 打开事件流并完成握手
+    pub async fn connect(
+        config: &McpServerConfig,
+        http: reqwest::Client,
+    ) -> Result<Arc<Self>, Error> {
+        let client = Arc::new(SseClient {
+            name: config.name.clone(),
+            url: config.url.clone().unwrap_or_default(),
+            headers: header_map(config)?,
+            http,
+            endpoint: watch::channel(None).0,
+            initialized: Mutex::new(None),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            next_id: AtomicI64::new(1),
+            timeout: config.request_timeout(),
+            tools_changed: Notify::new(),
+        });
+        let response = client.open().await?;
+        kovi::tokio::spawn(Self::listen(Arc::downgrade(&client), Some(response)));
+        client.ensure_initialized().await?;
+        Ok(client)
+    }
+
+    /// 发送 GET 打开事件流
+    async fn open(&self) -> Result<Response, Error> {
+        let response = self
+            .http
+            .get(&self.url)
+            .headers(self.headers.clone())
+            .header(ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "MCP server {} rejected event stream: {}",
+                self.name,
+                response.status()
+            ));
+        }
+        Ok(response)
+    }
+
+    /// 读取事件流，断开后清空等待中的请求并重连
+    async fn listen(client: Weak<Self>, mut response: Option<Response>) {
+        let mut delay = RETRY_DELAY;
+        loop {
+            let Some(this) = client.upgrade() else {
+                return;
+            };
+            let opened = match response.take() {
+                Some(r) => Ok(r),
+                None => this.open().await,
+            };
+            match opened {
+                Ok(mut response) => {
+                    delay = RETRY_DELAY;
+                    let mut parser = SseParser::default();
+                    while let Ok(Some(bytes)) = response.chunk().await {
+                        for event in parser.feed(&bytes) {
+                            this.handle_event(event).await;
+                        }
+                    }
+                    warn!("MCP server {} event stream closed", this.name);
+                }
+                Err(e) => warn!("{}", e),
+            }
+            this.endpoint.send_replace(None);
+            for (_, sender) in this.pending.lock().unwrap().drain() {
+                let _ = sender.send(Err("event stream closed".to_string()));
+            }
+            drop(this);
+            kovi::tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    async fn handle_event(&self, event: SseEvent) {
+        if event.event == "endpoint" {
+            match reqwest::Url::parse(&self.url).and_then(|base| base.join(&event.data)) {
+                Ok(url) => {
+                    self.endpoint.send_replace(Some(url.to_string()));
+                }
+                Err(e) => warn!("MCP server {} sent invalid endpoint: {}", self.name, e),
+            }
+            return;
+        }
+        if event.event != "message" {
+            return;
+        }
+        let Ok(message) = serde_json::from_str::<Value>(&event.data) else {
+            warn!("Ignore invalid event from MCP server {}", self.name);
+            return;
+        };
+        if let Some(id) = message.get("id").and_then(Value::as_i64)
+            && message.get("method").is_none()
+        {
+            let sender = self.pending.lock().unwrap().remove(&id);
+            if let (Some(sender), Some(result)) = (sender, parse_response(&message, id)) {
+                let _ = sender.send(result);
+            }
+            return;
+        }
+        let endpoint = self.endpoint.borrow().clone();
+        if let Some(reply) = handle_server_message(&message, &self.tools_changed)
+            && let Some(endpoint) = endpoint
+            && let Err(e) = self.post(&endpoint, &reply).await
+        {
+            warn!("Failed to reply MCP server {}: {}", self.name, e);
+        }
+    }
+
+    async fn post(&self, endpoint: &str, message: &Value) -> Result<(), Error> {
+        let response = self
+            .http
+            .post(endpoint)
+            .headers(self.headers.clone())
+            .json(message)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "MCP server {} returned {}",
+                self.name,
+                response.status()
+            ));
+        }
+        Ok(())
+    }
+
+    /// 等待事件流就绪，地址变化 (即重连) 后重新初始化，返回发送地址
+    async fn ensure_initialized(&self) -> Result<String, Error> {
+        let endpoint = self
+            .endpoint
+            .subscribe()
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| anyhow!("MCP server {} is closed", self.name))?
+            .clone()
+            .unwrap_or_default();
+        let mut initialized = self.initialized.lock().await;
+        if initialized.as_deref() == Some(endpoint.as_str()) {
+            return Ok(endpoint);
+        }
+        let id = self.next_id();
+        let result = self
+            .send_request(&endpoint, id, "initialize", initialize_params())
+            .await?;
+        self.post(
+            &endpoint,
+            &json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+        )
+        .await?;
+        info!(
+            "MCP server {} connected: {}",
+            self.name,
+            result.get("serverInfo").unwrap_or(&Value::Null)
+        );
+        // 首次连接之后的重新初始化可能带来不同的工具
+        if initialized.replace(endpoint.clone()).is_some() {
+            self.tools_changed.notify_one();
+        }
+        Ok(endpoint)
+    }
+
+    /// 发送请求并等待事件流中的回复
+    async fn send_request(
+        &self,
+        endpoint: &str,
+        id: i64,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        // 失败或被取消时移除等待中的请求
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            id,
+        };
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.post(endpoint, &message).await?;
+        receiver
+            .await
+            .map_err(|_| anyhow!("MCP server {} dropped request", self.name))?
+            .map_err(|e| anyhow!("MCP server {} error: {}", self.name, e))
+    }
+}
+
+#[async_trait]
+impl McpClient for SseClient {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn next_id(&self) -> i64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn send(&self, id: i64, method: &str, params: Value) -> Result<Value, Error> {
+        let endpoint = self.ensure_initialized().await?;
+        self.send_request(&endpoint, id, method, params).await
+    }
+
+    /// 事件流断开时服务器已丢弃会话，无需发送
+    async fn notify(&self, method: &str, params: Value) -> Result<(), Error> {
+        let endpoint = self.endpoint.borrow().clone();
+        let Some(endpoint) = endpoint else {
+            return Ok(());
+        };
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        self.post(&endpoint, &message).await
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn tools_changed(&self) -> &Notify {
+        &self.tools_changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mcp_client::tests::fixture_path;
+    use crate::mcp_client::{HttpTransport, McpServerConfig, connect_all};
+    use crate::mcp_http::*;
+    use crate::mcp_loader::MCPRegistry;
+    use std::io::BufRead;
+    use std::process::{Child, Stdio};
+    use std::time::Duration;
+
+    /// 启动 HTTP 模式的测试服务器，返回进程与地址
+    fn spawn_fixture(path: &str, mode: &str) -> (Child, String) {
+        let mut child = std::process::Command::new(path)
+            .arg(mode)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut url = String::new();
+        std::io::BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut url)
+            .unwrap();
+        (child, url.trim().to_string())
+    }
+
+    async fn wait_for_tool(registry: &MCPRegistry, name: &str) -> bool {
+        for _ in 0..50 {
+            if registry.get(name).is_some() {
+                return true;
+            }
+            kovi::tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    #[test]
+    fn test_http_transports() {
+        let mut parser = SseParser::default();
+        assert!(
+            parser
+                .feed(b": ping\nevent: endpoint\r\ndata: /a")
+                .is_empty()
+        );
+        assert_eq!(
+            parser.feed(b"\r\n\r\ndata: 1\ndata: 2\n\n"),
+            [
+                SseEvent {
+                    event: "endpoint".to_string(),
+                    data: "/a".to_string()
+                },
+                SseEvent {
+                    event: "message".to_string(),
+                    data: "1\n2".to_string()
+                }
+            ]
+        );
+
+        let path = fixture_path();
+        let runtime = kovi::tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        for (name, mode, transport) in [
+            ("http", "--http", HttpTransport::Http),
+            ("sse", "--sse", HttpTransport::Sse),
+        ] {
+            let (mut child, url) = spawn_fixture(&path, mode);
+            let config = McpServerConfig {
+                name: name.to_string(),
+                url: Some(url),
+                transport: Some(transport),
+                headers: HashMap::from([(
+                    "Authorization".to_string(),
+                    "Bearer test-token".to_string(),
+                )]),
+                ..Default::default()
+            };
+            assert!(config.check().is_ok());
+            runtime.block_on(async {
+                let registry = Arc::new(MCPRegistry::new());
+                connect_all(std::slice::from_ref(&config), &registry).await;
+                let echo = registry.get(&format!("{}__echo", name)).unwrap();
+                assert_eq!(
+                    echo.execute(json!({ "text": "hi" })).await.unwrap(),
+                    json!("hi")
+                );
+
+                // 服务器通知工具变化后刷新
+                let change = registry.get(&format!("{}__change", name)).unwrap();
+                assert_eq!(change.execute(json!({})).await.unwrap(), json!("changed"));
+                assert!(wait_for_tool(&registry, &format!("{}__extra", name)).await);
+
+                // 会话失效后自动重新初始化
+                let expire = registry.get(&format!("{}__expire", name)).unwrap();
+                assert_eq!(expire.execute(json!({})).await.unwrap(), json!("expired"));
+                assert_eq!(
+                    echo.execute(json!({ "text": "again" })).await.unwrap(),
+                    json!("again")
+                );
+
+                // 缺少认证时连接失败
+                let http = reqwest::Client::new();
+                let unauthorized = McpServerConfig {
+                    headers: HashMap::new(),
+                    ..config.clone()
+                };
+                let failed = match transport {
+                    HttpTransport::Http => HttpClient::connect(&unauthorized, http).await.is_err(),
+                    HttpTransport::Sse => SseClient::connect(&unauthorized, http).await.is_err(),
+                };
+                assert!(failed);
+            });
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+    }
+}
@@ -1,35 +1,155 @@
+use crate::schema::validate;
+use anyhow::Error;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 /// MCP trait
-pub trait MCP {
-    fn name(&self) -> &'static str;
-    fn description(&self) -> &'static str;
-    fn parameters(&self) -> serde_json::Value;
-    fn execute(&self, args: serde_json::Value) -> serde_json::Value;
+#[allow(clippy::upper_case_acronyms)]
+#[async_trait]
+pub trait MCP: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    /// 参数的 JSON Schema，执行前会按此检查参数
+    fn parameters(&self) -> Value;
+    /// 执行的超时时间，None 时使用配置的 tool_timeout
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+    /// 执行工具，返回的错误会作为工具错误交给模型；超时或请求被取消时 future 会被丢弃
+    async fn execute(&self, args: Value) -> Result<Value, Error>;
+}
+
+/// 共享的 MCP
+pub type SharedMCP = Arc<dyn MCP>;
+
+/// 工具调用失败的原因
+#[derive(Debug)]
+pub enum ToolError {
+    /// 参数无法解析
+    InvalidArguments(String),
+    /// 工具不存在
+    NotFound(String),
+    /// 执行超时
+    Timeout(Duration),
+    /// 执行失败
+    Failed(String),
+    /// 超出单次回复的调用次数或时间预算
+    BudgetExceeded(String),
+}
+
+impl ToolError {
+    /// 交给模型的 JSON
+    pub fn to_json(&self) -> Value {
+        let kind = match self {
+            ToolError::InvalidArguments(_) => "invalid_arguments",
+            ToolError::NotFound(_) => "not_found",
+            ToolError::Timeout(_) => "timeout",
+            ToolError::Failed(_) => "failed",
+            ToolError::BudgetExceeded(_) => "budget_exceeded",
+        };
+        json!({ "error": { "type": kind, "message": self.to_string() } })
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolError::InvalidArguments(e) => write!(f, "参数无效: {}", e),
+            ToolError::NotFound(name) => write!(f, "未知工具: {}", name),
+            ToolError::Timeout(d) => write!(f, "执行超时 ({} 秒)", d.as_secs_f32()),
+            ToolError::Failed(e) => write!(f, "{}", e),
+            ToolError::BudgetExceeded(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// 把参数反序列化为 T，失败时返回 ToolError::InvalidArguments
+///
+/// 用于 execute 中代替手动解析 Value：`let args: SumArgs = parse_args(args)?;`
+#[allow(dead_code)] // 供自定义 MCP 使用
+pub fn parse_args<T: DeserializeOwned>(args: Value) -> Result<T, Error> {
+    serde_json::from_value(args).map_err(|e| ToolError::InvalidArguments(e.to_string()).into())
+}
+
+/// 在超时时间内执行，超时后丢弃 future 以取消执行
+pub async fn with_timeout<T>(
+    timeout: Duration,
+    future: impl Future<Output = Result<T, Error>>,
+) -> Result<T, ToolError> {
+    match kovi::tokio::time::timeout(timeout, future).await {
+        Ok(Ok(v)) => Ok(v),
+        // 保留 parse_args 等返回的 ToolError
+        Ok(Err(e)) => Err(e
+            .downcast::<ToolError>()
+            .unwrap_or_else(|e| ToolError::Failed(e.to_string()))),
+        Err(_) => Err(ToolError::Timeout(timeout)),
+    }
 }
 
-/// MCP 注册器
+/// MCP 注册器，运行中可替换远程服务器提供的工具
 #[derive(Default)]
 pub struct MCPRegistry {
-    pub(crate) registry: HashMap<String, Box<dyn MCP + Send + Sync>>,
+    pub(crate) registry: RwLock<HashMap<String, SharedMCP>>,
 }
 
 impl MCPRegistry {
     pub fn new() -> Self {
         Self {
-            registry: HashMap::new(),
+            registry: RwLock::new(HashMap::new()),
         }
     }
 
     /// 注册 MCP
-    pub fn register<M: MCP + Send + Sync + 'static>(&mut self, mcp: M) {
-        self.registry.insert(mcp.name().to_string(), Box::new(mcp));
+    #[allow(dead_code)] // 供 register_mcp 注册自定义 MCP
+    pub fn register<M: MCP + 'static>(&mut self, mcp: M) {
+        self.registry
+            .get_mut()
+            .unwrap()
+            .insert(mcp.name().to_string(), Arc::new(mcp));
+    }
+
+    /// 以 prefix 开头的 MCP 全部替换为 tools
+    pub fn replace(&self, prefix: &str, tools: Vec<SharedMCP>) {
+        let mut registry = self.registry.write().unwrap();
+        registry.retain(|name, _| !name.starts_with(prefix));
+        for tool in tools {
+            registry.insert(tool.name().to_string(), tool);
+        }
+    }
+
+    /// 按名称获取 MCP
+    pub fn get(&self, name: &str) -> Option<SharedMCP> {
+        self.registry.read().unwrap().get(name).cloned()
+    }
+
+    /// 检查参数后执行 MCP，default_timeout 用于未指定超时时间的 MCP
+    pub async fn call(
+        &self,
+        name: &str,
+        args: Value,
+        default_timeout: Duration,
+    ) -> Result<Value, ToolError> {
+        let mcp = self
+            .get(name)
+            .ok_or_else(|| ToolError::NotFound(name.to_string()))?;
+        validate(&mcp.parameters(), &args).map_err(ToolError::InvalidArguments)?;
+        let timeout = mcp.timeout().unwrap_or(default_timeout);
+        with_timeout(timeout, mcp.execute(args)).await
     }
 
     /// 获取所有 MCP 的 functions 列表
     pub fn functions(&self) -> Vec<FunctionDef> {
         self.registry
+            .read()
+            .unwrap()
             .values()
             .map(|m| FunctionDef {
                 name: m.name().to_string(),
@@ -40,8 +160,11 @@ impl MCPRegistry {
     }
 
     /// 获取 function_call 列表，返回所有注册的 MCP
+    #[allow(dead_code)]
     pub fn function_calls(&self) -> Vec<FunctionCall> {
         self.registry
+            .read()
+            .unwrap()
             .keys()
             .map(|n| FunctionCall { name: n.clone() })
             .collect()
@@ -57,6 +180,7 @@ pub struct FunctionDef {
 }
 
 /// 序列化的 function_call
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FunctionCall {
     pub name: String,
@@ -65,20 +189,26 @@ pub struct FunctionCall {
 #[cfg(test)]
 mod tests {
     use crate::mcp_loader::*;
-    use serde_json::json;
 
     struct SumMCP;
 
+    #[derive(Deserialize)]
+    struct SumArgs {
+        a: i64,
+        b: i64,
+    }
+
+    #[async_trait]
     impl MCP for SumMCP {
-        fn name(&self) -> &'static str {
+        fn name(&self) -> &str {
             "calculate_sum"
         }
 
-        fn description(&self) -> &'static str {
+        fn description(&self) -> &str {
             "计算两个整数的和"
         }
 
-        fn parameters(&self) -> serde_json::Value {
+        fn parameters(&self) -> Value {
             json!({
                 "type": "object",
                 "properties": {
@@ -89,10 +219,35 @@ mod tests {
             })
         }
 
-        fn execute(&self, args: serde_json::Value) -> serde_json::Value {
-            let a = args.get("a").and_then(|v| v.as_i64()).unwrap_or(0);
-            let b = args.get("b").and_then(|v| v.as_i64()).unwrap_or(0);
-            json!({ "result": a + b })
+        async fn execute(&self, args: Value) -> Result<Value, Error> {
+            let args: SumArgs = parse_args(args)?;
+            Ok(json!({ "result": args.a + args.b }))
+        }
+    }
+
+    /// 一直等待的 MCP，用于测试超时
+    struct SlowMCP;
+
+    #[async_trait]
+    impl MCP for SlowMCP {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn description(&self) -> &str {
+            "不会返回"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({ "type": "object", "properties": {} })
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(50))
+        }
+
+        async fn execute(&self, _args: Value) -> Result<Value, Error> {
+            std::future::pending().await
         }
     }
 
@@ -100,6 +255,40 @@ mod tests {
     fn test_mcp_registry() {
         let mut registry = MCPRegistry::new();
         registry.register(SumMCP);
+        registry.register(SlowMCP);
+
+        let runtime = kovi::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let timeout = Duration::from_secs(10);
+            let sum = registry
+                .call("calculate_sum", json!({ "a": 1, "b": 2 }), timeout)
+                .await;
+            assert_eq!(sum.unwrap(), json!({ "result": 3 }));
+            // 参数不符合 schema 时不会执行
+            let invalid = registry
+                .call("calculate_sum", json!({ "a": "1" }), timeout)
+                .await;
+            assert_eq!(
+                invalid.unwrap_err().to_string(),
+                "参数无效: $: 缺少必填字段 b；$.a: 应为 integer，实际为 string"
+            );
+            // schema 无法表达的类型错误由 parse_args 报告
+            let overflow = registry
+                .call("calculate_sum", json!({ "a": 1, "b": u64::MAX }), timeout)
+                .await;
+            assert!(matches!(overflow, Err(ToolError::InvalidArguments(_))));
+            // MCP 自身的超时时间优先
+            let slow = registry.call("slow", json!({}), timeout).await;
+            assert!(matches!(slow, Err(ToolError::Timeout(_))));
+            let missing = registry.call("missing", json!({}), timeout).await;
+            assert_eq!(
+                missing.unwrap_err().to_json(),
+                json!({ "error": { "type": "not_found", "message": "未知工具: missing" } })
+            );
+        });
 
         let funcs = registry.functions();
         println!(
@@ -1,7 +1,17 @@
-use anyhow::Error;
+use anyhow::{Error, anyhow};
+use kovi::bot::runtimebot::send_api_request_with_response;
+use kovi::bot::{BotInformation, SendApi, Server};
+use kovi::event::{Event, InternalEvent};
+use kovi::{Message as KoviMsg, MsgEvent, RuntimeBot};
 use serde::Deserialize;
-use serde_json;
-use serde_json::Value;
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// 合并转发消息中 Bot 的昵称
+pub const FORWARD_NICKNAME: &str = "Rosmontis";
+/// 虚拟消息事件的消息 ID
+const SYNTHETIC_MESSAGE_ID: i32 = 0;
 
 #[derive(Deserialize)]
 pub struct OneBotMessage {
@@ -28,18 +38,22 @@ impl OneBotMessage {
 
     /// 判断自己是否被 At
     pub fn is_at(&self, self_id: i64) -> bool {
-        if self
-            .message
+        self.message
             .iter()
             .filter(|msg| msg.msg_type == "at")
             .filter_map(|msg| msg.data.qq.as_deref())
             .filter_map(|qq| qq.parse::<i64>().ok())
             .any(|id| id == self_id)
-        {
-            true
-        } else {
-            false
-        }
+    }
+
+    /// 获取所有被 At 的用户 ID
+    pub fn find_at(&self) -> Vec<i64> {
+        self.message
+            .iter()
+            .filter(|msg| msg.msg_type == "at")
+            .filter_map(|msg| msg.data.qq.as_deref())
+            .filter_map(|qq| qq.parse::<i64>().ok())
+            .collect()
     }
 
     /// 检查是否包含图片，返回 URL 列表
@@ -53,3 +67,99 @@ impl OneBotMessage {
         image_url
     }
 }
+
+/// 发送者的群名片，为空时使用昵称
+pub fn sender_name(event: &MsgEvent) -> &str {
+    event
+        .sender
+        .card
+        .as_deref()
+        .filter(|s| !s.is_empty())
+        .or(event.sender.nickname.as_deref())
+        .unwrap_or_default()
+}
+
+/// 以合并转发消息回复，每个元素为一个节点
+pub async fn reply_forward(
+    bot: &RuntimeBot,
+    event: &MsgEvent,
+    nodes: Vec<KoviMsg>,
+) -> Result<(), Error> {
+    let messages: Vec<Value> = nodes
+        .into_iter()
+        .map(|content| {
+            json!({
+                "type": "node",
+                "data": {
+                    "user_id": event.self_id.to_string(),
+                    "nickname": FORWARD_NICKNAME,
+                    "content": content,
+                }
+            })
+        })
+        .collect();
+    let send_api = match event.group_id {
+        Some(group_id) => SendApi::new(
+            "send_group_forward_msg",
+            json!({ "group_id": group_id, "messages": messages }),
+        ),
+        None => SendApi::new(
+            "send_private_forward_msg",
+            json!({ "user_id": event.sender.user_id, "messages": messages }),
+        ),
+    };
+    send_api_request_with_response(&bot.api_tx, send_api)
+        .await
+        .map_err(|e| anyhow!("Failed to send forward message: {}", e))?;
+    Ok(())
+}
+
+/// 构造发往指定会话的虚拟消息事件，用于主动执行命令
+pub fn synthetic_event(
+    bot: &RuntimeBot,
+    group_id: Option<i64>,
+    user_id: i64,
+    text: &str,
+) -> Result<Arc<MsgEvent>, Error> {
+    let (message_type, sub_type) = match group_id {
+        Some(_) => ("group", "normal"),
+        None => ("private", "friend"),
+    };
+    let json = json!({
+        "time": kovi::chrono::Utc::now().timestamp(),
+        "self_id": 0,
+        "post_type": "message",
+        "message_type": message_type,
+        "sub_type": sub_type,
+        "message_id": SYNTHETIC_MESSAGE_ID,
+        "group_id": group_id,
+        "user_id": user_id,
+        "message": [{ "type": "text", "data": { "text": text } }],
+        "raw_message": text,
+        "font": 0,
+        "sender": { "user_id": user_id }
+    });
+    let info = BotInformation {
+        main_admin: user_id,
+        deputy_admins: HashSet::new(),
+        server: Server::new(bot.host.clone(), bot.port, String::new(), false),
+    };
+    MsgEvent::de(
+        &InternalEvent::OneBotEvent(json.to_string()),
+        &info,
+        &bot.api_tx,
+    )
+    .map(Arc::new)
+    .ok_or_else(|| anyhow!("Failed to build message event"))
+}
+
+/// 是否为虚拟消息事件
+pub fn is_synthetic(event: &MsgEvent) -> bool {
+    event.message_id == SYNTHETIC_MESSAGE_ID
+}
+
+/// 去除引用回复，虚拟消息事件没有可引用的消息
+pub fn without_reply(msg: KoviMsg) -> KoviMsg {
+    let segments: Vec<_> = msg.iter().filter(|s| s.type_ != "reply").cloned().collect();
+    KoviMsg::from(segments)
+}
@@ -1,22 +1,156 @@
 use crate::config::Config;
-use crate::mcp_loader::MCPRegistry;
-use crate::user_manager::{ChatRole, Message, MessageContent};
+use crate::mcp_loader::{FunctionDef, MCPRegistry, ToolError, with_timeout};
+use crate::schema::validate;
+use crate::secrets::{error, info, warn};
+use crate::user_manager::{ChatRole, ContentPart, Message, MessageContent};
 use anyhow::{Error, anyhow};
-use kovi::log::error;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose;
+use futures_util::future::join_all;
+use kovi::tokio::time::{Instant, timeout_at};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 use tiktoken_rs::o200k_base;
 
 #[derive(Debug, Serialize)]
 pub struct ChatRequest {
     pub model: String,
-    pub messages: Vec<Message>,
+    pub messages: Vec<RequestMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Value>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+}
+
+/// 请求中的消息，工具调用相关的消息不会写入历史记录
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl From<Message> for RequestMessage {
+    fn from(message: Message) -> Self {
+        RequestMessage {
+            message,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+}
+
+/// 模型发起的工具调用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub kind: String,
+    pub function: ToolFunction,
+}
+
+/// 工具调用的函数名与 JSON 参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolFunction {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
+/// MCP 之外的工具来源，如开放给 AI 的命令
+#[async_trait]
+pub trait ToolHandler: Send + Sync {
+    /// 当前可用的工具
+    fn definitions(&self) -> Vec<FunctionDef>;
+
+    /// 调用工具，返回交给模型的结果，错误会作为工具错误交给模型
+    async fn call(&self, name: &str, arguments: Value) -> Result<String, Error>;
+
+    /// 工具调用耗时较长时调用一次，用于提示用户
+    fn progress(&self, _text: &str) {}
+}
+
+/// 采样参数
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SamplingParams {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    /// Chat Completions API 的 Token 限制
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// OpenAI 推理模型 (o 系列、gpt-5) 的 Token 限制
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    /// Responses API 的 Token 限制
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_output_tokens: Option<u32>,
 }
 
+impl SamplingParams {
+    /// 从配置读取采样参数，并按 API 选择 Token 限制字段
+    fn from_config(config: &Config) -> Self {
+        let mut params = SamplingParams {
+            temperature: config.temperature,
+            top_p: config.top_p,
+            presence_penalty: config.presence_penalty,
+            frequency_penalty: config.frequency_penalty,
+            seed: config.seed,
+            stop: config.stop.clone(),
+            response_format: config.response_format.clone(),
+            ..Default::default()
+        };
+        let field = config
+            .max_tokens_field
+            .clone()
+            .unwrap_or_else(|| max_tokens_field(&config.api_url, &config.model).to_string());
+        match field.as_str() {
+            "max_completion_tokens" => params.max_completion_tokens = config.max_output_tokens,
+            "max_output_tokens" => params.max_output_tokens = config.max_output_tokens,
+            _ => params.max_tokens = config.max_output_tokens,
+        }
+        params
+    }
+}
+
+/// 根据 API 地址与模型推断 Token 限制字段
+fn max_tokens_field(api_url: &str, model: &str) -> &'static str {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    if api_url.trim_end_matches('/').ends_with("/responses") {
+        "max_output_tokens"
+    } else if ["o1", "o3", "o4", "gpt-5"]
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+    {
+        "max_completion_tokens"
+    } else {
+        "max_tokens"
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ChatResponse {
     pub choices: Vec<ChatChoice>,
@@ -24,57 +158,189 @@ pub struct ChatResponse {
 
 #[derive(Debug, Deserialize)]
 pub struct ChatChoice {
-    pub message: Message,
+    pub message: ResponseMessage,
+    /// 结束原因：stop / length / content_filter 等
+    pub finish_reason: Option<String>,
 }
 
-pub struct OpenaiClient {
+/// API 返回的消息，被过滤时 content 可能为空
+#[derive(Debug, Deserialize)]
+pub struct ResponseMessage {
+    pub content: Option<MessageContent>,
+    /// 推理模型的思考过程 (DeepSeek-R1 等)
+    #[serde(alias = "reasoning")]
+    pub reasoning_content: Option<String>,
+    /// 工具调用
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+/// AI 回复
+#[derive(Debug, Clone)]
+pub struct ChatReply {
+    /// 回复内容
+    pub content: MessageContent,
+    /// 思考过程，不会写入历史记录
+    pub reasoning: Option<String>,
+}
+
+/// 回复因长度截断时追加的提示
+const TRUNCATED_MARKER: &str = "…(truncated)";
+/// 自动续写时发送的提示
+const CONTINUE_PROMPT: &str =
+    "（你的上一条回复因长度限制被截断，请从中断处直接继续，不要重复已输出的内容）";
+/// 未配置时自动续写的最大次数
+pub const DEFAULT_MAX_CONTINUATIONS: u32 = 2;
+/// 未配置时被内容过滤后的回复
+const DEFAULT_FILTER_REPLY: &str = "这个话题我不能回答哦。";
+/// 未配置时生成图片描述的提示词
+const DEFAULT_VISION_PROMPT: &str =
+    "请用简洁的中文客观描述这张图片的内容，包括主要物体、人物、文字和场景，不要加入评价。";
+/// 未配置时工具调用的最大轮数
+pub const DEFAULT_MAX_TOOL_ROUNDS: u32 = 3;
+/// 未配置时单次工具调用的超时时间
+const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(60);
+/// 未配置时单次回复最多的工具调用次数
+const DEFAULT_MAX_TOOL_CALLS: u32 = 10;
+/// 未配置时单次回复中工具调用的总时长
+const DEFAULT_TOOL_TIME_BUDGET: Duration = Duration::from_secs(120);
+/// 未配置时工具调用超过此时长后提示用户
+const DEFAULT_TOOL_PROGRESS_DELAY: u64 = 5;
+/// 工具调用耗时较长时的提示
+const TOOL_PROGRESS_TEXT: &str = "正在查询…";
+/// 图片描述缓存的最大条数
+const CAPTION_CACHE_LIMIT: usize = 512;
+
+/// 从配置生成的客户端设置，可在运行时整体替换
+struct ClientSettings {
     api_url: String,
     bearer_token: String,
     model: String,
     system_prompt: String,
-    temperature: Option<f32>,
-    max_output_tokens: Option<u32>,
-    http_client: Arc<reqwest::Client>,
-    mcp_loader: Arc<Option<MCPRegistry>>,
+    sampling: SamplingParams,
+    extra_body: Map<String, Value>,
     msg_limit: usize,
     token_limit: usize,
+    max_continuations: u32,
+    content_filter_reply: String,
+    max_tool_rounds: u32,
+    tool_timeout: Duration,
+    max_tool_calls: u32,
+    tool_time_budget: Duration,
+    /// 为 None 时不提示
+    tool_progress_delay: Option<Duration>,
+    model_vision: bool,
+    vision_model: Option<String>,
+    vision_prompt: String,
+}
+
+impl ClientSettings {
+    fn from_config(config: &Config) -> Self {
+        ClientSettings {
+            api_url: config.api_url.clone(),
+            bearer_token: config.bearer_token.clone(),
+            model: config.model.clone(),
+            sampling: SamplingParams::from_config(config),
+            extra_body: config.extra_body.clone().unwrap_or_default(),
+            system_prompt: config.system_prompt.clone(),
+            msg_limit: config.msg_limit.unwrap_or(0),
+            token_limit: config.token_limit.unwrap_or(0),
+            max_continuations: config
+                .max_continuations
+                .unwrap_or(DEFAULT_MAX_CONTINUATIONS),
+            content_filter_reply: config
+                .content_filter_reply
+                .clone()
+                .unwrap_or_else(|| DEFAULT_FILTER_REPLY.to_string()),
+            max_tool_rounds: config.max_tool_rounds.unwrap_or(DEFAULT_MAX_TOOL_ROUNDS),
+            tool_timeout: config
+                .tool_timeout
+                .map_or(DEFAULT_TOOL_TIMEOUT, Duration::from_secs),
+            max_tool_calls: config.max_tool_calls.unwrap_or(DEFAULT_MAX_TOOL_CALLS),
+            tool_time_budget: config
+                .tool_time_budget
+                .map_or(DEFAULT_TOOL_TIME_BUDGET, Duration::from_secs),
+            tool_progress_delay: match config
+                .tool_progress_delay
+                .unwrap_or(DEFAULT_TOOL_PROGRESS_DELAY)
+            {
+                0 => None,
+                v => Some(Duration::from_secs(v)),
+            },
+            model_vision: config.model_vision.unwrap_or(true),
+            vision_model: config.vision_model.clone(),
+            vision_prompt: config
+                .vision_prompt
+                .clone()
+                .unwrap_or_else(|| DEFAULT_VISION_PROMPT.to_string()),
+        }
+    }
+}
+
+pub struct OpenaiClient {
+    settings: RwLock<Arc<ClientSettings>>,
+    http_client: Arc<reqwest::Client>,
+    mcp_loader: Arc<MCPRegistry>,
+    caption_cache: Mutex<HashMap<String, String>>,
 }
 
 impl OpenaiClient {
     /// 构建 OpenAI 客户端
     pub async fn build(
-        config: Config,
+        config: &Config,
         http_client: Arc<reqwest::Client>,
-        mcp_loader: Arc<Option<MCPRegistry>>,
+        mcp_loader: Arc<MCPRegistry>,
     ) -> Self {
         OpenaiClient {
-            api_url: config.api_url,
-            bearer_token: config.bearer_token,
-            model: config.model,
-            system_prompt: config.system_prompt,
-            temperature: config.temperature,
-            max_output_tokens: config.max_output_tokens,
+            settings: RwLock::new(Arc::new(ClientSettings::from_config(config))),
             http_client,
             mcp_loader,
-            msg_limit: config.msg_limit.unwrap_or(0),
-            token_limit: config.token_limit.unwrap_or(0),
+            caption_cache: Mutex::new(HashMap::new()),
         }
     }
 
+    /// 替换为新的配置，进行中的请求继续使用旧配置
+    pub fn apply_config(&self, config: &Config) {
+        *self.settings.write().unwrap() = Arc::new(ClientSettings::from_config(config));
+    }
+
+    /// 当前设置的快照
+    fn settings(&self) -> Arc<ClientSettings> {
+        Arc::clone(&self.settings.read().unwrap())
+    }
+
+    /// 主模型是否支持图片输入
+    pub fn supports_vision(&self) -> bool {
+        self.settings().model_vision
+    }
+
     /// 发送 API 请求
-    async fn request(&self, messages: &[Message]) -> Result<ChatResponse, Error> {
+    async fn request(
+        &self,
+        s: &ClientSettings,
+        model: &str,
+        messages: &[RequestMessage],
+        tools: &[Value],
+    ) -> Result<ChatResponse, Error> {
         let request = ChatRequest {
-            model: self.model.clone(),
+            model: model.to_string(),
             messages: messages.to_vec(),
-            temperature: self.temperature,
-            max_output_tokens: self.max_output_tokens,
+            tools: tools.to_vec(),
+            sampling: s.sampling.clone(),
         };
 
+        // 合并额外参数，同名时覆盖
+        let mut body = serde_json::to_value(&request)?;
+        if let Some(body) = body.as_object_mut() {
+            for (k, v) in &s.extra_body {
+                body.insert(k.clone(), v.clone());
+            }
+        }
+
         let response_text = self
             .http_client
-            .post(&self.api_url)
-            .bearer_auth(&self.bearer_token)
-            .json(&request)
+            .post(&s.api_url)
+            .bearer_auth(&s.bearer_token)
+            .json(&body)
             .send()
             .await?
             .text()
@@ -94,50 +360,369 @@ impl OpenaiClient {
         Ok(response)
     }
 
-    /// 使用 API 进行聊天
-    pub async fn chat(&self, messages: &mut Vec<Message>) -> Result<MessageContent, Error> {
-        // 插入系统提示词
-        if messages
-            .first()
-            .map_or(true, |m| !matches!(m.role, ChatRole::System))
+    /// 发送请求并取出第一个 choice
+    async fn request_choice(
+        &self,
+        s: &ClientSettings,
+        messages: &[RequestMessage],
+        tools: &[Value],
+    ) -> Result<ChatChoice, Error> {
+        let response = match self.request(s, &s.model, messages, tools).await {
+            Ok(r) => r,
+            Err(e) => {
+                return Err(anyhow!("OpenAI request failed: {}", e));
+            }
+        };
+
+        // 检查 choice
+        match response.choices.into_iter().next() {
+            Some(c) => Ok(c),
+            None => Err(Error::msg("No choice returned from OpenAI")),
+        }
+    }
+
+    /// 使用视觉模型生成图片描述，按图片哈希缓存
+    pub async fn caption_image(&self, url: &str) -> Result<String, Error> {
+        let s = self.settings();
+        let vision_model = s
+            .vision_model
+            .as_deref()
+            .ok_or_else(|| anyhow!("No vision model configured"))?;
+
+        // 下载图片并计算哈希
+        let bytes = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let hash = format!("{:x}", Sha256::digest(&bytes));
+        if let Some(caption) = self.caption_cache.lock().unwrap().get(&hash) {
+            return Ok(caption.clone());
+        }
+
+        // 请求视觉模型
+        let image = format!(
+            "data:{};base64,{}",
+            image_mime(&bytes),
+            general_purpose::STANDARD.encode(&bytes)
+        );
+        let messages = [RequestMessage::from(Message {
+            role: ChatRole::User,
+            content: MessageContent::Multi(vec![
+                ContentPart {
+                    kind: "text".to_string(),
+                    text: Some(s.vision_prompt.clone()),
+                    image_url: None,
+                },
+                ContentPart {
+                    kind: "image_url".to_string(),
+                    text: None,
+                    image_url: Some(image),
+                },
+            ]),
+        })];
+        let response = self.request(&s, vision_model, &messages, &[]).await?;
+        let mut message = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::msg("No choice returned from vision model"))?
+            .message;
+        take_reasoning(&mut message);
+        let caption = match message.content {
+            Some(MessageContent::Text(v)) => v.trim().to_string(),
+            _ => return Err(Error::msg("Vision model returned no text")),
+        };
+        info!("Captioned image {}: {}", hash, caption);
+
+        let mut cache = self.caption_cache.lock().unwrap();
+        if cache.len() >= CAPTION_CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(hash, caption.clone());
+        Ok(caption)
+    }
+
+    /// 汇总可用工具，同名时 MCP 让位于 tools 提供的工具
+    fn tool_definitions(&self, s: &ClientSettings, extra: &[FunctionDef]) -> Vec<Value> {
+        if s.max_tool_rounds == 0 {
+            return Vec::new();
+        }
+        let mcp_defs = self.mcp_loader.functions();
+        extra
+            .iter()
+            .chain(
+                mcp_defs
+                    .iter()
+                    .filter(|f| !extra.iter().any(|e| e.name == f.name)),
+            )
+            .map(|f| json!({ "type": "function", "function": f }))
+            .collect()
+    }
+
+    /// 执行一次工具调用，超时后丢弃执行中的 future
+    async fn call_tool(
+        &self,
+        s: &ClientSettings,
+        call: &ToolCall,
+        tools: Option<&dyn ToolHandler>,
+        extra: &[FunctionDef],
+    ) -> Result<String, ToolError> {
+        let name = call.function.name.as_str();
+        let arguments = match call.function.arguments.trim() {
+            "" => Value::Object(Map::new()),
+            v => serde_json::from_str(v).map_err(|e| ToolError::InvalidArguments(e.to_string()))?,
+        };
+        info!("Tool call {}: {}", name, arguments);
+
+        if let Some(tools) = tools
+            && let Some(def) = extra.iter().find(|f| f.name == name)
+        {
+            validate(&def.parameters, &arguments).map_err(ToolError::InvalidArguments)?;
+            return with_timeout(s.tool_timeout, tools.call(name, arguments)).await;
+        }
+        match self
+            .mcp_loader
+            .call(name, arguments, s.tool_timeout)
+            .await?
         {
-            messages.insert(
+            Value::String(v) => Ok(v),
+            v => Ok(v.to_string()),
+        }
+    }
+
+    /// 并发执行一轮工具调用，按调用顺序返回交给模型的结果，超出预算的调用不会执行
+    async fn run_tool_calls(
+        &self,
+        s: &ClientSettings,
+        calls: &[ToolCall],
+        tools: Option<&dyn ToolHandler>,
+        extra: &[FunctionDef],
+        budget: &mut ToolBudget,
+    ) -> Vec<String> {
+        let allowed = calls.len().min(budget.calls as usize);
+        budget.calls -= allowed as u32;
+        let deadline = budget.deadline;
+        let results = calls.iter().enumerate().map(|(i, call)| async move {
+            if i >= allowed {
+                return Err(ToolError::BudgetExceeded(
+                    "本次回复的工具调用次数已用完".to_string(),
+                ));
+            }
+            timeout_at(deadline, self.call_tool(s, call, tools, extra))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(ToolError::BudgetExceeded(
+                        "本次回复的工具调用时间已用完".to_string(),
+                    ))
+                })
+        });
+        let mut results = std::pin::pin!(join_all(results));
+
+        // 耗时较长时提示用户，每次回复只提示一次
+        let results = match budget.progress_at.take() {
+            Some(at) => match timeout_at(at, &mut results).await {
+                Ok(v) => {
+                    budget.progress_at = Some(at);
+                    v
+                }
+                Err(_) => {
+                    if let Some(tools) = tools {
+                        tools.progress(TOOL_PROGRESS_TEXT);
+                    }
+                    results.await
+                }
+            },
+            None => results.await,
+        };
+
+        // 错误以 JSON 交给模型，由模型决定如何回复
+        calls
+            .iter()
+            .zip(results)
+            .map(|(call, result)| match result {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Tool call {} failed: {}", call.function.name, e);
+                    e.to_json().to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// 使用 API 进行聊天，persona 替换默认的系统提示词，tools 为 MCP 之外的额外工具；
+    /// 丢弃返回的 future 即可取消回复，进行中的工具调用会一并取消
+    pub async fn chat(
+        &self,
+        messages: &mut Vec<Message>,
+        persona: Option<&str>,
+        tools: Option<&dyn ToolHandler>,
+    ) -> Result<ChatReply, Error> {
+        let s = self.settings();
+        self.complete(&s, messages, persona, tools, s.max_tool_rounds)
+            .await
+    }
+
+    /// 不提供任何工具 (包括 MCP) 的聊天，用于处理不可信的文本，如群聊记录
+    pub async fn chat_without_tools(
+        &self,
+        messages: &mut Vec<Message>,
+        persona: Option<&str>,
+    ) -> Result<ChatReply, Error> {
+        let s = self.settings();
+        self.complete(&s, messages, persona, None, 0).await
+    }
+
+    /// 发送请求并执行工具调用，max_rounds 为 0 时不提供工具
+    async fn complete(
+        &self,
+        s: &ClientSettings,
+        messages: &mut Vec<Message>,
+        persona: Option<&str>,
+        tools: Option<&dyn ToolHandler>,
+        max_rounds: u32,
+    ) -> Result<ChatReply, Error> {
+        // 插入或更新系统提示词
+        let system_prompt = persona.unwrap_or(&s.system_prompt);
+        match messages.first_mut() {
+            Some(m) if matches!(m.role, ChatRole::System) => {
+                m.content = MessageContent::Text(system_prompt.to_string());
+            }
+            _ => messages.insert(
                 0,
                 Message {
                     role: ChatRole::System,
-                    content: MessageContent::Text(self.system_prompt.clone()),
+                    content: MessageContent::Text(system_prompt.to_string()),
                 },
-            );
+            ),
         }
 
-        // MCP (未实现)
-        let _ = self.mcp_loader;
+        // 可用工具
+        let extra = tools.map(|t| t.definitions()).unwrap_or_default();
+        let tool_defs = match max_rounds {
+            0 => Vec::new(),
+            _ => self.tool_definitions(s, &extra),
+        };
 
         // 发送请求
-        let response = match self
-            .request(&*history_preprocessing(
-                messages,
-                self.msg_limit,
-                self.token_limit,
-            ))
-            .await
-        {
-            Ok(r) => r,
-            Err(e) => {
-                return Err(anyhow!("OpenAI request failed: {}", e));
+        let mut history = history_preprocessing(messages, s.msg_limit, s.token_limit);
+        if !s.model_vision {
+            // 纯文本模型无法接收历史中的图片
+            strip_images(&mut history);
+        }
+        let mut history: Vec<RequestMessage> = history.into_iter().map(Into::into).collect();
+        let mut choice = self.request_choice(s, &history, &tool_defs).await?;
+        let mut reasoning = take_reasoning(&mut choice.message);
+
+        // 执行工具调用并将结果交给模型，直到模型给出回复
+        let mut budget = ToolBudget::new(s);
+        let mut round = 0;
+        while let Some(calls) = choice.message.tool_calls.take().filter(|c| !c.is_empty()) {
+            if round >= max_rounds {
+                warn!("Tool call rounds exceeded");
+                break;
             }
-        };
+            round += 1;
+            history.push(RequestMessage {
+                message: Message {
+                    role: ChatRole::Assistant,
+                    content: choice
+                        .message
+                        .content
+                        .take()
+                        .unwrap_or(MessageContent::Text(String::new())),
+                },
+                tool_calls: Some(calls.clone()),
+                tool_call_id: None,
+            });
+            let results = self
+                .run_tool_calls(s, &calls, tools, &extra, &mut budget)
+                .await;
+            for (call, result) in calls.into_iter().zip(results) {
+                history.push(RequestMessage {
+                    message: Message {
+                        role: ChatRole::Tool,
+                        content: MessageContent::Text(result),
+                    },
+                    tool_calls: None,
+                    tool_call_id: Some(call.id),
+                });
+            }
+            // 最后一轮或预算用完后不再提供工具，让模型给出回复
+            let exhausted = budget.exhausted();
+            if exhausted {
+                warn!("Tool call budget exhausted");
+            }
+            let tool_defs = if round < max_rounds && !exhausted {
+                &tool_defs[..]
+            } else {
+                &[]
+            };
+            choice = self.request_choice(s, &history, tool_defs).await?;
+            if let Some(v) = take_reasoning(&mut choice.message) {
+                reasoning.get_or_insert_default().push_str(&v);
+            }
+        }
 
-        // 检查 choice
-        let choice = match response.choices.first() {
-            Some(c) => c,
-            None => {
-                return Err(Error::msg("No choice returned from OpenAI"));
+        let content = match choice.finish_reason.as_deref() {
+            // 被内容过滤时使用设定好的回复
+            Some("content_filter") => {
+                warn!("Reply blocked by content filter");
+                MessageContent::Text(s.content_filter_reply.clone())
             }
+            // 因长度截断时尝试续写
+            Some("length") => match choice.message.content {
+                Some(MessageContent::Text(mut text)) => {
+                    let mut finished = false;
+                    for round in 1..=s.max_continuations {
+                        warn!(
+                            "Reply truncated, continuing ({}/{})",
+                            round, s.max_continuations
+                        );
+                        history.push(
+                            Message {
+                                role: ChatRole::Assistant,
+                                content: MessageContent::Text(text.clone()),
+                            }
+                            .into(),
+                        );
+                        history.push(
+                            Message {
+                                role: ChatRole::User,
+                                content: MessageContent::Text(CONTINUE_PROMPT.to_string()),
+                            }
+                            .into(),
+                        );
+                        let mut next = self.request_choice(s, &history, &[]).await?;
+                        history.truncate(history.len() - 2);
+                        if let Some(v) = take_reasoning(&mut next.message) {
+                            reasoning.get_or_insert_default().push_str(&v);
+                        }
+                        if let Some(MessageContent::Text(v)) = next.message.content {
+                            text.push_str(&v);
+                        }
+                        if next.finish_reason.as_deref() != Some("length") {
+                            finished = true;
+                            break;
+                        }
+                    }
+                    if !finished {
+                        text.push_str(TRUNCATED_MARKER);
+                    }
+                    MessageContent::Text(text)
+                }
+                Some(v) => v,
+                None => MessageContent::Text(TRUNCATED_MARKER.to_string()),
+            },
+            _ => choice
+                .message
+                .content
+                .ok_or_else(|| Error::msg("Empty content returned from OpenAI"))?,
         };
 
-        let content = choice.message.content.clone();
-
         // 把 "\\n" 转换成真实换行
         let content = match content {
             MessageContent::Text(v) => MessageContent::Text(v.replace("\\n", "\n")),
@@ -150,8 +735,124 @@ impl OpenaiClient {
             content: content.clone(),
         });
 
-        Ok(content)
+        Ok(ChatReply { content, reasoning })
+    }
+}
+
+/// 单次回复中工具调用的预算，从第一轮工具调用开始计时
+struct ToolBudget {
+    /// 剩余的调用次数
+    calls: u32,
+    /// 所有调用需在此前完成
+    deadline: Instant,
+    /// 到达此时仍未完成时提示用户，已提示或不提示时为 None
+    progress_at: Option<Instant>,
+}
+
+impl ToolBudget {
+    fn new(s: &ClientSettings) -> Self {
+        let now = Instant::now();
+        ToolBudget {
+            calls: s.max_tool_calls,
+            deadline: now + s.tool_time_budget,
+            progress_at: s.tool_progress_delay.map(|d| now + d),
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.calls == 0 || Instant::now() >= self.deadline
+    }
+}
+
+/// 将历史中的图片替换为文字占位
+fn strip_images(history: &mut [Message]) {
+    for message in history {
+        if let MessageContent::Multi(parts) = &message.content {
+            let text = parts
+                .iter()
+                .map(|p| match &p.text {
+                    Some(t) => t.as_str(),
+                    None => "[图片]",
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            message.content = MessageContent::Text(text);
+        }
+    }
+}
+
+/// 识别图片类型
+fn image_mime(bytes: &[u8]) -> &'static str {
+    if bytes.starts_with(&[0x89, 0x50, 0x4E, 0x47]) {
+        "image/png"
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if bytes.starts_with(b"GIF8") {
+        "image/gif"
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else {
+        "application/octet-stream" // 实在认不出来
+    }
+}
+
+/// 取出思考过程，并从文本内容中去除 <think> 标签
+fn take_reasoning(message: &mut ResponseMessage) -> Option<String> {
+    let mut reasoning = message
+        .reasoning_content
+        .take()
+        .filter(|v| !v.trim().is_empty());
+    if let Some(MessageContent::Text(text)) = &mut message.content {
+        let (content, think) = split_think(text);
+        if let Some(think) = think {
+            let reasoning = reasoning.get_or_insert_default();
+            if !reasoning.is_empty() {
+                reasoning.push('\n');
+            }
+            reasoning.push_str(&think);
+        }
+        *text = content;
     }
+    reasoning
+}
+
+/// 分离文本中的 <think>...</think> 思考内容
+fn split_think(text: &str) -> (String, Option<String>) {
+    const OPEN: &str = "<think>";
+    const CLOSE: &str = "</think>";
+
+    let mut content = String::new();
+    let mut thoughts = Vec::new();
+    let mut rest = text;
+
+    // 部分服务商会省略开头的 <think>
+    if let Some(end) = rest.find(CLOSE)
+        && !rest[..end].contains(OPEN)
+    {
+        thoughts.push(rest[..end].trim().to_string());
+        rest = &rest[end + CLOSE.len()..];
+    }
+
+    while let Some(start) = rest.find(OPEN) {
+        content.push_str(&rest[..start]);
+        let after = &rest[start + OPEN.len()..];
+        match after.find(CLOSE) {
+            Some(end) => {
+                thoughts.push(after[..end].trim().to_string());
+                rest = &after[end + CLOSE.len()..];
+            }
+            // 未闭合（被截断）时剩余内容都视为思考
+            None => {
+                thoughts.push(after.trim().to_string());
+                rest = "";
+            }
+        }
+    }
+    content.push_str(rest);
+
+    let thoughts: Vec<String> = thoughts.into_iter().filter(|t| !t.is_empty()).collect();
+    let reasoning = (!thoughts.is_empty()).then(|| thoughts.join("\n"));
+    (content.trim().to_string(), reasoning)
 }
 
 /// 预处理历史记录
@@ -170,7 +871,7 @@ fn history_preprocessing(
     let mut msg_count: usize = 0;
     let processed = chat_prompt.rev().take_while(|x| {
         token_count += match &x.content {
-            MessageContent::Text(v) => bpe.encode_with_special_tokens(&*v).len(),
+            MessageContent::Text(v) => bpe.encode_with_special_tokens(v).len(),
             _ => 0,
         };
         msg_count += 1;
@@ -187,3 +888,174 @@ fn history_preprocessing(
     history_rev.reverse();
     history_rev
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::openai_api::*;
+
+    #[test]
+    fn test_max_tokens_field() {
+        let url = "https://api.openai.com/v1/chat/completions";
+        assert_eq!(max_tokens_field(url, "deepseek-chat"), "max_tokens");
+        assert_eq!(max_tokens_field(url, "o3-mini"), "max_completion_tokens");
+        assert_eq!(
+            max_tokens_field(url, "openai/gpt-5"),
+            "max_completion_tokens"
+        );
+        assert_eq!(
+            max_tokens_field("https://api.openai.com/v1/responses", "gpt-4o"),
+            "max_output_tokens"
+        );
+    }
+
+    #[test]
+    fn test_split_think() {
+        assert_eq!(split_think("你好"), ("你好".to_string(), None));
+        assert_eq!(
+            split_think("<think>\n想想\n</think>\n\n你好"),
+            ("你好".to_string(), Some("想想".to_string()))
+        );
+        assert_eq!(
+            split_think("想想</think>你好"),
+            ("你好".to_string(), Some("想想".to_string()))
+        );
+        assert_eq!(
+            split_think("<think>想了很久"),
+            (String::new(), Some("想了很久".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_tool_call_messages() {
+        let response: ChatResponse = serde_json::from_str(
+            r#"{"choices":[{"message":{"content":null,"tool_calls":[{"id":"call_1","type":"function",
+            "function":{"name":"image","arguments":"{}"}}]},"finish_reason":"tool_calls"}]}"#,
+        )
+        .unwrap();
+        let calls = response.choices[0].message.tool_calls.clone().unwrap();
+        assert_eq!(calls[0].function.name, "image");
+
+        let result = RequestMessage {
+            message: Message {
+                role: ChatRole::Tool,
+                content: MessageContent::Text("ok".to_string()),
+            },
+            tool_calls: None,
+            tool_call_id: Some(calls[0].id.clone()),
+        };
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            json!({ "role": "tool", "content": "ok", "tool_call_id": "call_1" })
+        );
+    }
+
+    /// 等待 ms 毫秒后返回 ms
+    struct SleepMCP;
+
+    #[async_trait]
+    impl crate::mcp_loader::MCP for SleepMCP {
+        fn name(&self) -> &str {
+            "sleep"
+        }
+
+        fn description(&self) -> &str {
+            "等待一段时间"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({ "type": "object", "properties": { "ms": { "type": "integer" } } })
+        }
+
+        async fn execute(&self, args: Value) -> Result<Value, Error> {
+            let ms = args["ms"].as_u64().unwrap_or(0);
+            kovi::tokio::time::sleep(Duration::from_millis(ms)).await;
+            Ok(json!(ms))
+        }
+    }
+
+    /// 记录进度提示
+    #[derive(Default)]
+    struct Progress(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl ToolHandler for Progress {
+        fn definitions(&self) -> Vec<FunctionDef> {
+            vec![]
+        }
+
+        async fn call(&self, name: &str, _arguments: Value) -> Result<String, Error> {
+            Err(anyhow!("未知工具: {}", name))
+        }
+
+        fn progress(&self, text: &str) {
+            self.0.lock().unwrap().push(text.to_string());
+        }
+    }
+
+    #[test]
+    fn test_parallel_tool_calls() {
+        let mut registry = MCPRegistry::new();
+        registry.register(SleepMCP);
+        let config = Config {
+            max_tool_rounds: Some(3),
+            ..Default::default()
+        };
+        // 暂停时间，空闲时自动快进到下一个计时器，耗时不受机器负载影响
+        let runtime = kovi::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let client = OpenaiClient::build(
+                &config,
+                Arc::new(reqwest::Client::new()),
+                Arc::new(registry),
+            )
+            .await;
+            let s = client.settings();
+            let calls: Vec<ToolCall> = [300, 100, 200, 100]
+                .iter()
+                .enumerate()
+                .map(|(i, ms)| {
+                    serde_json::from_value(json!({
+                        "id": format!("call_{}", i),
+                        "function": { "name": "sleep", "arguments": format!("{{\"ms\":{}}}", ms) }
+                    }))
+                    .unwrap()
+                })
+                .collect();
+            let now = Instant::now();
+            let mut budget = ToolBudget {
+                calls: 3,
+                deadline: now + Duration::from_secs(10),
+                progress_at: Some(now + Duration::from_millis(50)),
+            };
+            let progress = Progress::default();
+
+            // 并发执行，结果按调用顺序返回，超出次数的调用不执行
+            let results = client
+                .run_tool_calls(&s, &calls, Some(&progress), &[], &mut budget)
+                .await;
+            // 串行执行需要 600 毫秒
+            assert_eq!(now.elapsed(), Duration::from_millis(300));
+            assert_eq!(results[..3], ["300", "100", "200"]);
+            assert!(results[3].contains("budget_exceeded"));
+            assert!(budget.exhausted());
+            assert_eq!(*progress.0.lock().unwrap(), [TOOL_PROGRESS_TEXT]);
+
+            // 时间用完后未完成的调用会被取消
+            let mut budget = ToolBudget {
+                calls: 3,
+                deadline: Instant::now() + Duration::from_millis(150),
+                progress_at: None,
+            };
+            let results = client
+                .run_tool_calls(&s, &calls[..2], Some(&progress), &[], &mut budget)
+                .await;
+            assert!(results[0].contains("budget_exceeded"));
+            assert_eq!(results[1], "100");
+            assert_eq!(progress.0.lock().unwrap().len(), 1);
+        });
+    }
+}
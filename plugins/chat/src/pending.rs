@@ -0,0 +1,134 @@
+use crate::commands::*;
+use kovi::tokio::sync::Notify;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 一个用户进行中的回复，ID 与取消信号
+type Replies = Vec<(u64, Arc<Notify>)>;
+
+/// 进行中的 AI 回复，用户可通过 stop 命令取消
+#[derive(Default)]
+pub struct PendingReplies {
+    /// 用户 ID 到其进行中的回复
+    replies: Mutex<HashMap<i64, Replies>>,
+    next_id: AtomicU64,
+}
+
+impl PendingReplies {
+    /// 登记一个回复，返回的守卫在回复结束时注销
+    pub fn start(self: &Arc<Self>, user_id: i64) -> PendingReply {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let notify = Arc::new(Notify::new());
+        self.replies
+            .lock()
+            .unwrap()
+            .entry(user_id)
+            .or_default()
+            .push((id, Arc::clone(&notify)));
+        PendingReply {
+            replies: Arc::clone(self),
+            user_id,
+            id,
+            notify,
+        }
+    }
+
+    /// 取消用户所有进行中的回复，返回取消的数量
+    pub fn cancel(&self, user_id: i64) -> usize {
+        let replies = self.replies.lock().unwrap().remove(&user_id);
+        let replies = replies.unwrap_or_default();
+        for (_, notify) in &replies {
+            notify.notify_one();
+        }
+        replies.len()
+    }
+}
+
+/// 一个进行中的回复
+pub struct PendingReply {
+    replies: Arc<PendingReplies>,
+    user_id: i64,
+    id: u64,
+    notify: Arc<Notify>,
+}
+
+impl PendingReply {
+    /// 等待回复被取消
+    pub async fn cancelled(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for PendingReply {
+    fn drop(&mut self) {
+        let mut replies = self.replies.replies.lock().unwrap();
+        if let Some(list) = replies.get_mut(&self.user_id) {
+            list.retain(|(id, _)| *id != self.id);
+            if list.is_empty() {
+                replies.remove(&self.user_id);
+            }
+        }
+    }
+}
+
+/// stop 命令名称
+pub const STOP_COMMAND: &str = "stop";
+
+/// stop 命令
+pub struct StopCommand;
+
+#[async_trait]
+impl Command for StopCommand {
+    fn name(&self) -> &'static str {
+        STOP_COMMAND
+    }
+
+    fn description(&self) -> &'static str {
+        "停止正在生成的回复，进行中的工具调用会一并取消"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["停止"]
+    }
+
+    fn category(&self) -> &'static str {
+        "基础"
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let count = ctx.resources.replies.cancel(ctx.msg.sender.user_id);
+        if count == 0 {
+            ctx.reply("没有正在生成的回复");
+        } else {
+            info!("User {} stopped {} replies", ctx.msg.sender.user_id, count);
+            ctx.reply("已停止回复");
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pending::*;
+
+    #[test]
+    fn test_pending_replies() {
+        let replies = Arc::new(PendingReplies::default());
+        let runtime = kovi::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let first = replies.start(1);
+            let other = replies.start(2);
+            assert_eq!(replies.cancel(1), 1);
+            // 取消先于等待时也能收到
+            first.cancelled().await;
+            assert_eq!(replies.cancel(1), 0);
+
+            drop(other);
+            assert_eq!(replies.cancel(2), 0);
+        });
+    }
+}
@@ -0,0 +1,131 @@
+use kovi::MsgEvent;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// 用户角色，按权限从低到高排列
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// 被封禁，不响应任何消息
+    Banned,
+    /// 普通用户
+    #[default]
+    User,
+    /// 信任用户
+    Trusted,
+    /// 管理员
+    Admin,
+    /// 所有者，即 Kovi 配置的主管理员
+    Owner,
+}
+
+impl Role {
+    /// 储存在数据库中的名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Banned => "banned",
+            Role::User => "user",
+            Role::Trusted => "trusted",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Role::Banned => "封禁",
+            Role::User => "用户",
+            Role::Trusted => "信任用户",
+            Role::Admin => "管理员",
+            Role::Owner => "所有者",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    /// 同时接受英文名称与中文名称
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "banned" | "ban" | "封禁" => Ok(Role::Banned),
+            "user" | "用户" => Ok(Role::User),
+            "trusted" | "信任" | "信任用户" => Ok(Role::Trusted),
+            "admin" | "管理员" => Ok(Role::Admin),
+            "owner" | "所有者" => Ok(Role::Owner),
+            _ => Err(format!("未知角色: {}", s)),
+        }
+    }
+}
+
+/// 命令所需权限
+// 内置命令尚未用到全部等级
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// 所有未被封禁的用户
+    User,
+    /// 信任用户及以上
+    Trusted,
+    /// 当前群的群主、群管理员，或 Bot 管理员及以上
+    GroupAdmin,
+    /// Bot 管理员及以上
+    Admin,
+    /// 仅所有者
+    Owner,
+}
+
+impl Permission {
+    /// 判断角色是否满足权限，group_admin 表示发送者是当前群的群主或群管理员
+    pub fn allows(self, role: Role, group_admin: bool) -> bool {
+        match self {
+            Permission::User => role >= Role::User,
+            Permission::Trusted => role >= Role::Trusted,
+            Permission::GroupAdmin => role >= Role::Admin || (group_admin && role >= Role::User),
+            Permission::Admin => role >= Role::Admin,
+            Permission::Owner => role == Role::Owner,
+        }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Permission::User => "用户",
+            Permission::Trusted => "信任用户",
+            Permission::GroupAdmin => "群管理员",
+            Permission::Admin => "管理员",
+            Permission::Owner => "所有者",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 发送者是否为当前群的群主或群管理员
+pub fn is_group_admin(event: &MsgEvent) -> bool {
+    event.is_group() && matches!(event.sender.role.as_deref(), Some("owner" | "admin"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::permission::*;
+
+    #[test]
+    fn test_permission() {
+        assert_eq!("管理员".parse::<Role>(), Ok(Role::Admin));
+        assert_eq!("Trusted".parse::<Role>(), Ok(Role::Trusted));
+        assert!("root".parse::<Role>().is_err());
+
+        assert!(Permission::User.allows(Role::User, false));
+        assert!(!Permission::User.allows(Role::Banned, true));
+        assert!(!Permission::Trusted.allows(Role::User, true));
+        assert!(Permission::GroupAdmin.allows(Role::User, true));
+        assert!(Permission::GroupAdmin.allows(Role::Admin, false));
+        assert!(!Permission::GroupAdmin.allows(Role::Trusted, false));
+        assert!(!Permission::Owner.allows(Role::Admin, true));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::commands::*;
+use crate::config::ConfigStore;
+use crate::secrets::error;
+use std::time::{Duration, SystemTime};
+
+/// 检查配置文件修改的间隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 重新加载配置并应用到运行中的组件，返回变更项
+pub fn reload(store: &ConfigStore, client: &OpenaiClient) -> Result<Vec<String>, Error> {
+    let (old, new) = store.reload()?;
+    client.apply_config(&new);
+    let changes = old.diff(&new);
+    if changes.is_empty() {
+        info!("Config reloaded, nothing changed");
+    } else {
+        info!("Config reloaded:\n{}", changes.join("\n"));
+    }
+    Ok(changes)
+}
+
+/// 监听配置文件，修改后自动重载，失败时保留旧配置
+pub fn watch(store: Arc<ConfigStore>, client: Arc<OpenaiClient>) {
+    kovi::spawn(async move {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut last: Option<SystemTime> = modified(store.path());
+        let mut interval = kovi::tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let current = modified(store.path());
+            if current.is_none() || current == last {
+                continue;
+            }
+            // 等待编辑器写入完成
+            kovi::tokio::time::sleep(Duration::from_millis(500)).await;
+            last = modified(store.path());
+            if let Err(e) = reload(&store, &client) {
+                error!("Failed to reload config, keep the old one: {}", e);
+            }
+        }
+    });
+}
+
+/// reload 命令
+pub struct ReloadCommand;
+
+#[async_trait]
+impl Command for ReloadCommand {
+    fn name(&self) -> &'static str {
+        "reload"
+    }
+
+    fn description(&self) -> &'static str {
+        "重新加载配置文件"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["重载"]
+    }
+
+    fn category(&self) -> &'static str {
+        "管理"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let changes = reload(&ctx.resources.config, &ctx.resources.client)?;
+        let reply = if changes.is_empty() {
+            "配置已重载，没有变更".to_string()
+        } else {
+            format!("配置已重载:\n{}", changes.join("\n"))
+        };
+        ctx.reply(reply);
+        Ok(true)
+    }
+}
@@ -0,0 +1,642 @@
+use crate::commands::*;
+use crate::cooldown::format_duration;
+use crate::secrets::{error, warn};
+use kovi::chrono::{
+    DateTime, Datelike, Days, Duration as TimeDelta, FixedOffset, NaiveDate, NaiveTime, Utc,
+};
+use std::time::Duration;
+
+/// 检查到期提醒的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// 晚于此秒数送达的提醒会注明原定时间
+const LATE_THRESHOLD: i64 = 300;
+/// 每个用户最多的待办提醒数
+const MAX_PENDING: usize = 20;
+/// 发送失败的提醒超过此秒数后放弃
+const GIVE_UP_AFTER: i64 = 86400;
+/// 最远可设置的提醒
+const MAX_AHEAD: TimeDelta = TimeDelta::days(366);
+
+/// 提醒
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    pub id: i64,
+    pub user_id: i64,
+    /// 创建提醒的群，私聊为 None
+    pub group_id: Option<i64>,
+    /// 到期的时间戳
+    pub due: i64,
+    pub content: String,
+}
+
+/// 简单的文本扫描器，匹配失败时由调用者恢复位置
+#[derive(Clone, Copy)]
+struct Scanner<'a> {
+    rest: &'a str,
+}
+
+impl Scanner<'_> {
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    /// 匹配列表中第一个符合的词
+    fn eat(&mut self, words: &[&'static str]) -> Option<&'static str> {
+        let word = words.iter().find(|w| self.rest.starts_with(**w))?;
+        self.rest = &self.rest[word.len()..];
+        Some(word)
+    }
+
+    /// 匹配阿拉伯数字或 0-99 的中文数字
+    fn number(&mut self) -> Option<u32> {
+        let len = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        if len > 0 {
+            let value = self.rest[..len].parse().ok()?;
+            self.rest = &self.rest[len..];
+            return Some(value);
+        }
+
+        let digit = |c: char| "零一二三四五六七八九".chars().position(|d| d == c);
+        let len = self
+            .rest
+            .find(|c: char| digit(c).is_none() && c != '十' && c != '两')
+            .unwrap_or(self.rest.len());
+        let chars: Vec<u32> = self.rest[..len]
+            .chars()
+            .map(|c| match c {
+                '十' => 10,
+                '两' => 2,
+                c => digit(c).unwrap_or_default() as u32,
+            })
+            .collect();
+        let value = match chars.as_slice() {
+            [d] => *d,
+            [10, d] if *d < 10 => 10 + d,
+            [t, 10] if *t < 10 => t * 10,
+            [t, 10, d] if *t < 10 && *d < 10 => t * 10 + d,
+            _ => return None,
+        };
+        self.rest = &self.rest[len..];
+        Some(value)
+    }
+}
+
+/// 解析提醒时间，返回时间与剩余的文本
+pub fn parse_time(text: &str, now: DateTime<FixedOffset>) -> Option<(DateTime<FixedOffset>, &str)> {
+    let mut sc = Scanner { rest: text };
+    let time = match relative(&mut sc) {
+        Some(delta) => now.checked_add_signed(delta)?,
+        None => absolute(&mut sc, now)?,
+    };
+    let mut rest = sc.rest.trim_start_matches([' ', ',', '，', ':', '：']);
+    for prefix in ["提醒我", "叫我", "提醒"] {
+        if let Some(r) = rest.strip_prefix(prefix) {
+            rest = r;
+            break;
+        }
+    }
+    Some((time, rest.trim()))
+}
+
+/// 相对时间，如 "10分钟后"、"1小时30分钟后"、"2h"
+fn relative(sc: &mut Scanner) -> Option<TimeDelta> {
+    let start = *sc;
+    let mut total = 0i64;
+    loop {
+        let save = *sc;
+        sc.skip_space();
+        if sc.eat(&["半个小时", "半小时", "半个钟头"]).is_some() {
+            total = total.saturating_add(1800);
+            continue;
+        }
+        let Some(n) = sc.number() else {
+            *sc = save;
+            break;
+        };
+        let secs = match sc.eat(&[
+            "秒钟",
+            "秒",
+            "分钟",
+            "分",
+            "个小时",
+            "小时",
+            "个钟头",
+            "钟头",
+            "天",
+            "个星期",
+            "星期",
+            "周",
+            "min",
+            "s",
+            "m",
+            "h",
+            "d",
+        ]) {
+            Some("秒钟" | "秒" | "s") => 1,
+            Some("分钟" | "分" | "min" | "m") => 60,
+            Some("天" | "d") => 86400,
+            Some("个星期" | "星期" | "周") => 7 * 86400,
+            Some(_) => 3600,
+            None => {
+                *sc = save;
+                break;
+            }
+        };
+        total = (n as i64).saturating_mul(secs).saturating_add(total);
+    }
+    if total == 0 {
+        *sc = start;
+        return None;
+    }
+    sc.skip_space();
+    sc.eat(&["之后", "以后", "后"]);
+    // 超出可设置范围的时长不做日期计算，由调用者拒绝
+    Some(TimeDelta::seconds(total.min(MAX_AHEAD.num_seconds() + 1)))
+}
+
+/// 绝对时间，如 "明天早上8点半"、"周五下午3点"、"2026-12-24 20:00"、"12月1日"
+fn absolute(sc: &mut Scanner, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+    let start = *sc;
+    let today = now.date_naive();
+    let mut period = None;
+    // 时间已过时顺延的天数
+    let mut roll = 1;
+
+    sc.skip_space();
+    let date = if let Some(word) = sc.eat(&[
+        "大后天",
+        "后天",
+        "明天",
+        "明早",
+        "明晚",
+        "今天",
+        "今早",
+        "今晚",
+    ]) {
+        period = match word {
+            "明早" | "今早" => Some("早上"),
+            "明晚" | "今晚" => Some("晚上"),
+            _ => None,
+        };
+        roll = 0;
+        let days = match word {
+            "大后天" => 3,
+            "后天" => 2,
+            "明天" | "明早" | "明晚" => 1,
+            _ => 0,
+        };
+        today.checked_add_days(Days::new(days))
+    } else if let Some(date) = weekday(sc, today) {
+        roll = 7;
+        Some(date)
+    } else if let Some(date) = calendar(sc, today) {
+        roll = 0;
+        Some(date)
+    } else {
+        None
+    };
+
+    sc.skip_space();
+    if let Some(word) = sc.eat(&[
+        "凌晨", "早上", "早晨", "上午", "中午", "下午", "傍晚", "晚上", "夜里",
+    ]) {
+        period = Some(word);
+    }
+    let (time, colon, next_day) = match clock(sc, period) {
+        Some((time, colon, next_day)) => (Some(time), colon, next_day),
+        None => (None, false, false),
+    };
+    if date.is_none() && time.is_none() && period.is_none() {
+        *sc = start;
+        return None;
+    }
+
+    // 只有时段时使用默认时刻
+    let time = time.or_else(|| {
+        let hour = match period {
+            Some("凌晨") => 6,
+            Some("早上" | "早晨") => 8,
+            Some("中午") => 12,
+            Some("下午") => 15,
+            Some("傍晚") => 18,
+            Some("晚上" | "夜里") => 20,
+            _ => 9,
+        };
+        NaiveTime::from_hms_opt(hour, 0, 0)
+    })?;
+    let mut at = date
+        .unwrap_or(today)
+        .checked_add_days(Days::new(next_day as u64))?
+        .and_time(time)
+        .and_local_timezone(*now.offset())
+        .single()?;
+
+    if at <= now && roll > 0 {
+        // "3点" 这类未指明上下午的时间优先理解为今天下午
+        if date.is_none() && period.is_none() && !colon && at + TimeDelta::hours(12) > now {
+            at += TimeDelta::hours(12);
+        } else {
+            at += TimeDelta::days(roll);
+        }
+    }
+    Some(at)
+}
+
+/// 星期，如 "周五"、"下星期一"
+fn weekday(sc: &mut Scanner, today: NaiveDate) -> Option<NaiveDate> {
+    let save = *sc;
+    let next_week = sc.eat(&["下周", "下星期", "下礼拜"]).is_some();
+    if !next_week && sc.eat(&["周", "星期", "礼拜"]).is_none() {
+        *sc = save;
+        return None;
+    }
+    let Some(day) = sc
+        .eat(&["一", "二", "三", "四", "五", "六", "日", "天"])
+        .and_then(|d| {
+            ["一", "二", "三", "四", "五", "六", "日", "天"]
+                .iter()
+                .position(|w| *w == d)
+        })
+    else {
+        *sc = save;
+        return None;
+    };
+    let target = day.min(6) as i64;
+    let current = today.weekday().num_days_from_monday() as i64;
+    let days = if next_week {
+        7 - current + target
+    } else {
+        (target - current).rem_euclid(7)
+    };
+    today.checked_add_days(Days::new(days as u64))
+}
+
+/// 日期，如 "2026-12-24"、"2026年12月24日"、"12月24号"
+fn calendar(sc: &mut Scanner, today: NaiveDate) -> Option<NaiveDate> {
+    let save = *sc;
+    let date = (|| {
+        let first = sc.number()?;
+        if first >= 1000 {
+            sc.eat(&["-", "/", "年"])?;
+            let month = sc.number()?;
+            sc.eat(&["-", "/", "月"])?;
+            let day = sc.number()?;
+            sc.eat(&["日", "号"]);
+            NaiveDate::from_ymd_opt(first as i32, month, day)
+        } else {
+            sc.eat(&["月"])?;
+            let day = sc.number()?;
+            sc.eat(&["日", "号"])?;
+            // 未指明年份且今年的日期已过时理解为明年
+            NaiveDate::from_ymd_opt(today.year(), first, day)
+                .filter(|date| *date >= today)
+                .or_else(|| NaiveDate::from_ymd_opt(today.year() + 1, first, day))
+        }
+    })();
+    if date.is_none() {
+        *sc = save;
+    }
+    date
+}
+
+/// 时刻，如 "8点半"、"15:30"、"3点20分"，同时返回是否为冒号分隔的 24 小时制，
+/// 以及是否为次日 ("晚上12点" 即次日 0 点)
+fn clock(sc: &mut Scanner, period: Option<&str>) -> Option<(NaiveTime, bool, bool)> {
+    let save = *sc;
+    let time = (|| {
+        sc.skip_space();
+        let mut hour = sc.number()?;
+        let colon = sc.eat(&[":", "："]).is_some();
+        let minute = if colon {
+            sc.number()?
+        } else {
+            sc.eat(&["点", "时"])?;
+            if sc.eat(&["半"]).is_some() {
+                30
+            } else if let Some(m) = sc.number() {
+                sc.eat(&["分钟", "分"]);
+                m
+            } else {
+                sc.eat(&["整"]);
+                0
+            }
+        };
+        let mut next_day = false;
+        match period {
+            Some("下午" | "傍晚" | "晚上" | "夜里") if hour < 12 => hour += 12,
+            Some("晚上" | "夜里") if hour == 12 => {
+                hour = 0;
+                next_day = true;
+            }
+            Some("中午") if hour < 11 => hour += 12,
+            Some("凌晨") if hour == 12 => hour = 0,
+            _ => {}
+        }
+        Some((NaiveTime::from_hms_opt(hour, minute, 0)?, colon, next_day))
+    })();
+    if time.is_none() {
+        *sc = save;
+    }
+    time
+}
+
+/// 格式化提醒时间
+fn format_time(config: &Config, timestamp: i64) -> String {
+    let time = config.local_time(DateTime::from_timestamp(timestamp, 0).unwrap_or_default());
+    if time.year() == config.local_time(Utc::now()).year() {
+        time.format("%m月%d日 %H:%M").to_string()
+    } else {
+        time.format("%Y年%m月%d日 %H:%M").to_string()
+    }
+}
+
+/// 后台发送到期的提醒，送达后才删除，重启后继续发送未完成的提醒
+pub fn spawn_scheduler(res: Arc<Resources>) {
+    kovi::spawn(async move {
+        let mut interval = kovi::tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = Utc::now().timestamp();
+            let reminders = match res.user_manager.due_reminders(now).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Failed to load due reminders: {}", e);
+                    continue;
+                }
+            };
+            for reminder in reminders {
+                match deliver(&res, &reminder, now).await {
+                    Ok(()) => {}
+                    // 发送失败时保留，下次轮询重试
+                    Err(e) if now - reminder.due <= GIVE_UP_AFTER => {
+                        warn!("Failed to deliver reminder {}: {}", reminder.id, e);
+                        continue;
+                    }
+                    Err(e) => error!(
+                        "Drop reminder {} for {} due at {}: {}, content: {}",
+                        reminder.id, reminder.user_id, reminder.due, e, reminder.content
+                    ),
+                }
+                if let Err(e) = res.user_manager.cancel_reminder(reminder.id, None).await {
+                    error!("Failed to remove reminder {}: {}", reminder.id, e);
+                }
+            }
+        }
+    });
+}
+
+/// 发送提醒，群聊中 @ 创建者
+async fn deliver(res: &Resources, reminder: &Reminder, now: i64) -> Result<(), Error> {
+    let mut text = format!("提醒: {}", reminder.content);
+    if now - reminder.due > LATE_THRESHOLD {
+        let config = res.config.load();
+        text.push_str(&format!(
+            "\n(原定于 {})",
+            format_time(&config, reminder.due)
+        ));
+    }
+    info!("Deliver reminder {} to {}", reminder.id, reminder.user_id);
+    let result = match reminder.group_id {
+        Some(group) => {
+            res.bot
+                .send_group_msg_return(
+                    group,
+                    KoviMsg::new()
+                        .add_at(&reminder.user_id.to_string())
+                        .add_text(format!(" {}", text)),
+                )
+                .await
+        }
+        None => {
+            res.bot
+                .send_private_msg_return(reminder.user_id, text)
+                .await
+        }
+    };
+    result.map(|_| ()).map_err(|e| anyhow!("{:?}", e))
+}
+
+/// remind 命令
+pub struct RemindCommand;
+
+#[async_trait]
+impl Command for RemindCommand {
+    fn name(&self) -> &'static str {
+        "remind"
+    }
+
+    fn description(&self) -> &'static str {
+        "在指定时间提醒你，也可查看或取消提醒"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["提醒"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "remind <时间> <内容> | remind list | remind cancel <编号>"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "remind 10分钟后 喝水",
+            "remind 明天早上8点半 开会",
+            "remind 周五下午3点 交周报",
+            "remind 2026-12-24 20:00 准备礼物",
+            "remind list",
+            "remind cancel 3",
+        ]
+    }
+
+    fn category(&self) -> &'static str {
+        "工具"
+    }
+
+    fn cooldown(&self) -> Cooldown {
+        Cooldown::per_user(3)
+    }
+
+    fn tool_parameters(&self) -> Option<Value> {
+        Some(json!({
+            "type": "object",
+            "properties": {
+                "time": {
+                    "type": "string",
+                    "description": "提醒时间，如 10分钟后、明天早上8点、周五下午3点半、2026-12-24 20:00"
+                },
+                "content": { "type": "string", "description": "提醒内容" }
+            },
+            "required": ["time", "content"]
+        }))
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        match ctx.args.positional().first().map(String::as_str) {
+            None => Err(UsageError("缺少提醒时间".to_string()).into()),
+            Some("list" | "列表") => list(ctx).await,
+            Some("cancel" | "取消" | "删除") => cancel(ctx).await,
+            Some(_) => create(ctx).await,
+        }
+    }
+}
+
+/// 创建提醒
+async fn create(ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+    let now = Utc::now();
+    let text = ctx.args.rest().to_string();
+    let Some((time, content)) = parse_time(&text, ctx.config.local_time(now)) else {
+        return Err(UsageError(format!("无法识别时间: {}", text)).into());
+    };
+    if content.is_empty() {
+        return Err(UsageError("缺少提醒内容".to_string()).into());
+    }
+    let due = time.with_timezone(&Utc);
+    if due <= now {
+        return Err(UsageError("这个时间已经过去了".to_string()).into());
+    }
+    if due - now > MAX_AHEAD {
+        return Err(UsageError("最多只能设置一年内的提醒".to_string()).into());
+    }
+    let pending = ctx
+        .resources
+        .user_manager
+        .list_reminders(ctx.user.id)
+        .await?;
+    if pending.len() >= MAX_PENDING {
+        return Err(anyhow!("待办提醒已达上限 ({} 条)", MAX_PENDING));
+    }
+
+    let reminder = Reminder {
+        id: 0,
+        user_id: ctx.user.id,
+        group_id: ctx.msg.group_id,
+        due: due.timestamp(),
+        content: content.to_string(),
+    };
+    let id = ctx.resources.user_manager.add_reminder(&reminder).await?;
+    info!("User {} added reminder {}", ctx.user.id, id);
+
+    let remaining = (due - now).to_std().unwrap_or_default();
+    ctx.reply(format!(
+        "好的，将在 {} ({}后) 提醒你: {}\n编号 #{}",
+        format_time(&ctx.config, reminder.due),
+        format_duration(remaining),
+        reminder.content,
+        id
+    ));
+    Ok(true)
+}
+
+/// 列出提醒
+async fn list(ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+    let reminders = ctx
+        .resources
+        .user_manager
+        .list_reminders(ctx.user.id)
+        .await?;
+    if reminders.is_empty() {
+        ctx.reply("没有待办的提醒");
+        return Ok(true);
+    }
+    let lines: Vec<String> = reminders
+        .iter()
+        .map(|r| {
+            let place = match r.group_id {
+                Some(g) => format!("群 {}", g),
+                None => "私聊".to_string(),
+            };
+            format!(
+                "#{} {} [{}] {}",
+                r.id,
+                format_time(&ctx.config, r.due),
+                place,
+                r.content
+            )
+        })
+        .collect();
+    ctx.reply(format!("待办提醒:\n{}", lines.join("\n")));
+    Ok(true)
+}
+
+/// 取消提醒，管理员可取消任何人的提醒
+async fn cancel(ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+    let id: i64 = ctx.args.require(1, "编号")?;
+    let owner = (ctx.role < Role::Admin).then_some(ctx.user.id);
+    if ctx
+        .resources
+        .user_manager
+        .cancel_reminder(id, owner)
+        .await?
+    {
+        info!("User {} cancelled reminder {}", ctx.user.id, id);
+        ctx.reply(format!("已取消提醒 #{}", id));
+    } else {
+        ctx.reply(format!("没有找到提醒 #{}", id));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reminder::*;
+
+    #[test]
+    fn test_parse_time() {
+        // 2026-10-18 为周日
+        let now = DateTime::parse_from_rfc3339("2026-10-18T10:00:00+08:00").unwrap();
+        let parse = |text| {
+            parse_time(text, now).map(|(t, rest)| (t.format("%m-%d %H:%M").to_string(), rest))
+        };
+
+        assert_eq!(parse("10分钟后 喝水"), Some(("10-18 10:10".into(), "喝水")));
+        assert_eq!(
+            parse("1小时30分钟后休息"),
+            Some(("10-18 11:30".into(), "休息"))
+        );
+        assert_eq!(
+            parse("半小时后提醒我收衣服"),
+            Some(("10-18 10:30".into(), "收衣服"))
+        );
+        assert_eq!(
+            parse("明天早上8点半开会"),
+            Some(("10-19 08:30".into(), "开会"))
+        );
+        assert_eq!(
+            parse("下午3点 交作业"),
+            Some(("10-18 15:00".into(), "交作业"))
+        );
+        assert_eq!(parse("三点 喝茶"), Some(("10-18 15:00".into(), "喝茶")));
+        assert_eq!(parse("9:30 早会"), Some(("10-19 09:30".into(), "早会")));
+        assert_eq!(
+            parse("周五下午3点半 交周报"),
+            Some(("10-23 15:30".into(), "交周报"))
+        );
+        assert_eq!(parse("下周一 上班"), Some(("10-19 09:00".into(), "上班")));
+        assert_eq!(parse("12月1日 考试"), Some(("12-01 09:00".into(), "考试")));
+        assert_eq!(
+            parse("2026-12-24 20:00 准备礼物"),
+            Some(("12-24 20:00".into(), "准备礼物"))
+        );
+        assert_eq!(parse("今晚 吃药"), Some(("10-18 20:00".into(), "吃药")));
+        assert_eq!(parse("晚上12点 睡觉"), Some(("10-19 00:00".into(), "睡觉")));
+        assert_eq!(
+            parse("明天晚上12点 抢票"),
+            Some(("10-20 00:00".into(), "抢票"))
+        );
+        // 今年已过的日期顺延到明年
+        let year = |text| parse_time(text, now).map(|(t, _)| t.format("%Y-%m-%d").to_string());
+        assert_eq!(year("3月1日 体检"), Some("2027-03-01".into()));
+        assert_eq!(year("10月18日 晚上8点 聚餐"), Some("2026-10-18".into()));
+        assert_eq!(parse("喝水"), None);
+
+        // 过远的时间不会溢出
+        for text in ["4000000000天 喝水", "4000000000周4000000000周 喝水"] {
+            let (time, rest) = parse_time(text, now).unwrap();
+            assert!(time - now > MAX_AHEAD);
+            assert_eq!(rest, "喝水");
+        }
+    }
+}
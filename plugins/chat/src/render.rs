@@ -0,0 +1,1234 @@
+use crate::config::Config;
+use crate::secrets::{info, warn};
+use anyhow::{Error, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose;
+use cosmic_text::{
+    Align, Attrs, Buffer, Color, Family, FontSystem, Metrics, Shaping, Style, SwashCache, Weight,
+};
+use kovi::Message as KoviMsg;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use tiny_skia::{Paint, Pixmap, Rect, Transform};
+
+/// 图片宽度
+const WIDTH: f32 = 800.0;
+/// 页边距
+const PADDING: f32 = 28.0;
+/// 块之间的间距
+const BLOCK_GAP: f32 = 14.0;
+/// 正文字号
+const BODY_SIZE: f32 = 18.0;
+/// 代码字号
+const CODE_SIZE: f32 = 15.0;
+/// 图片最大高度，超出则放弃渲染
+const MAX_HEIGHT: f32 = 16000.0;
+
+const BACKGROUND: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+const TEXT: Color = Color::rgb(0x24, 0x29, 0x2F);
+const MUTED: Color = Color::rgb(0x65, 0x6D, 0x76);
+const BORDER: Color = Color::rgb(0xD0, 0xD7, 0xDE);
+const CODE_BG: Color = Color::rgb(0xF6, 0xF8, 0xFA);
+const INLINE_CODE: Color = Color::rgb(0xC7, 0x25, 0x4E);
+const LINK: Color = Color::rgb(0x09, 0x69, 0xDA);
+const MATH: Color = Color::rgb(0x1F, 0x3A, 0x93);
+
+/// Markdown 渲染器
+///
+/// 将包含代码、表格或公式的回复离线渲染为 PNG，其余回复去除 Markdown 语法后以纯文本发送
+pub struct MarkdownRenderer {
+    painter: Option<Mutex<Painter>>,
+}
+
+impl MarkdownRenderer {
+    /// 根据配置构建渲染器，关闭渲染时仅做纯文本转换
+    pub fn new(config: &Config) -> Self {
+        if !config.markdown_render.unwrap_or(true) {
+            return MarkdownRenderer { painter: None };
+        }
+
+        let mut font_system = FontSystem::new();
+        for path in config.render_fonts.iter().flatten() {
+            if let Err(e) = font_system.db_mut().load_font_file(path) {
+                warn!("Failed to load font {}: {}", path, e);
+            }
+        }
+        info!(
+            "Markdown renderer loaded {} font faces",
+            font_system.db().len()
+        );
+
+        MarkdownRenderer {
+            painter: Some(Mutex::new(Painter {
+                font_system,
+                swash_cache: SwashCache::new(),
+                syntax_set: SyntaxSet::load_defaults_newlines(),
+                theme: ThemeSet::load_defaults()
+                    .themes
+                    .remove("InspiredGitHub")
+                    .unwrap_or_default(),
+            })),
+        }
+    }
+
+    /// 将 Markdown 渲染为 PNG
+    pub fn render(&self, markdown: &str) -> Result<Vec<u8>, Error> {
+        let painter = self
+            .painter
+            .as_ref()
+            .ok_or_else(|| anyhow!("Markdown render is disabled"))?;
+        let mut painter = painter
+            .lock()
+            .map_err(|_| anyhow!("Markdown renderer poisoned"))?;
+        painter.paint(&parse_blocks(markdown))
+    }
+
+    /// 将 AI 回复转换为 QQ 消息，值得渲染时发送图片，否则发送纯文本
+    pub async fn to_message(self: &Arc<Self>, text: String) -> KoviMsg {
+        if self.painter.is_some() && should_render(&text) {
+            match self.to_image(text.clone()).await {
+                Ok(v) => return v,
+                Err(e) => warn!("Failed to render markdown: {}", e),
+            }
+        }
+        KoviMsg::from(strip_markdown(&text))
+    }
+
+    /// 在后台线程将 Markdown 渲染为图片消息
+    pub async fn to_image(self: &Arc<Self>, markdown: String) -> Result<KoviMsg, Error> {
+        let renderer = Arc::clone(self);
+        let png = kovi::tokio::task::spawn_blocking(move || renderer.render(&markdown)).await??;
+        let image = general_purpose::STANDARD.encode(png);
+        Ok(KoviMsg::new().add_image(&format!("base64://{}", image)))
+    }
+}
+
+/// 判断回复是否值得渲染为图片（包含代码块、表格或公式）
+pub fn should_render(text: &str) -> bool {
+    let text = normalize_math(text);
+    Parser::new_ext(&text, parser_options()).any(|event| {
+        matches!(
+            event,
+            Event::Start(Tag::CodeBlock(_))
+                | Event::Start(Tag::Table(_))
+                | Event::DisplayMath(_)
+                | Event::InlineMath(_)
+        )
+    })
+}
+
+/// 去除 Markdown 语法，得到适合 QQ 的纯文本
+pub fn strip_markdown(text: &str) -> String {
+    let text = normalize_math(text);
+    let mut output = String::new();
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut link_urls: Vec<String> = Vec::new();
+    let mut quote_depth = 0;
+
+    for event in Parser::new_ext(&text, parser_options()) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                if !lists.is_empty() {
+                    push_line_break(&mut output);
+                }
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    output.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                push_line_break(&mut output);
+                let depth = lists.len().saturating_sub(1);
+                output.push_str(&"  ".repeat(depth));
+                match lists.last_mut() {
+                    Some(Some(n)) => {
+                        output.push_str(&format!("{}. ", n));
+                        *n += 1;
+                    }
+                    _ => output.push_str("• "),
+                }
+            }
+            Event::Start(Tag::BlockQuote(_)) => quote_depth += 1,
+            Event::End(TagEnd::BlockQuote(_)) => quote_depth -= 1,
+            Event::Start(Tag::Paragraph) | Event::Start(Tag::Heading { .. }) if quote_depth > 0 => {
+                output.push_str("> ")
+            }
+            Event::End(TagEnd::Paragraph) | Event::End(TagEnd::Heading(_)) if lists.is_empty() => {
+                output.push_str("\n\n")
+            }
+            Event::Start(Tag::CodeBlock(_)) => push_line_break(&mut output),
+            Event::End(TagEnd::CodeBlock) => output.push('\n'),
+            Event::Start(Tag::Link { dest_url, .. }) => link_urls.push(dest_url.to_string()),
+            Event::End(TagEnd::Link) => {
+                if let Some(url) = link_urls.pop()
+                    && !output.ends_with(&url)
+                {
+                    output.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => {
+                trim_cell_separator(&mut output);
+                output.push('\n');
+            }
+            Event::End(TagEnd::TableCell) => output.push_str(" | "),
+            Event::End(TagEnd::Table) => output.push('\n'),
+            Event::Text(v) | Event::Code(v) | Event::Html(v) | Event::InlineHtml(v) => {
+                output.push_str(&v)
+            }
+            Event::InlineMath(v) => output.push_str(&latex_to_unicode(&v)),
+            Event::DisplayMath(v) => {
+                push_line_break(&mut output);
+                output.push_str(&latex_to_unicode(&v));
+                output.push('\n');
+            }
+            Event::SoftBreak | Event::HardBreak => output.push('\n'),
+            Event::Rule => output.push_str("————\n\n"),
+            Event::TaskListMarker(done) => output.push_str(if done { "☑ " } else { "☐ " }),
+            _ => {}
+        }
+    }
+
+    output.trim().to_string()
+}
+
+/// LaTeX 公式转换为 Unicode 近似文本
+pub fn latex_to_unicode(latex: &str) -> String {
+    let chars: Vec<char> = latex.chars().collect();
+    let mut output = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < chars.len() && chars[end].is_ascii_alphabetic() {
+                    end += 1;
+                }
+                if end == start {
+                    // 转义字符，如 \{ \% \,
+                    if let Some(&c) = chars.get(start) {
+                        match c {
+                            ',' | ';' | ' ' => output.push(' '),
+                            '!' => {}
+                            '\\' => output.push('\n'),
+                            c => output.push(c),
+                        }
+                    }
+                    i = start + 1;
+                    continue;
+                }
+                let name: String = chars[start..end].iter().collect();
+                i = end;
+                match name.as_str() {
+                    "frac" | "dfrac" | "tfrac" => {
+                        let (num, next) = latex_group(&chars, i);
+                        let (den, next) = latex_group(&chars, next);
+                        i = next;
+                        output.push_str(&format!(
+                            "{}/{}",
+                            wrap_operand(&latex_to_unicode(&num)),
+                            wrap_operand(&latex_to_unicode(&den))
+                        ));
+                    }
+                    "sqrt" => {
+                        let (body, next) = latex_group(&chars, i);
+                        i = next;
+                        output.push('√');
+                        output.push_str(&wrap_operand(&latex_to_unicode(&body)));
+                    }
+                    "text" | "mathrm" | "mathbf" | "mathit" | "operatorname" | "textbf" => {
+                        let (body, next) = latex_group(&chars, i);
+                        i = next;
+                        output.push_str(&latex_to_unicode(&body));
+                    }
+                    "left" | "right" | "big" | "Big" | "bigg" | "Bigg" | "displaystyle" => {}
+                    "quad" | "qquad" => output.push_str("  "),
+                    "lim" | "log" | "ln" | "sin" | "cos" | "tan" | "exp" | "max" | "min" => {
+                        output.push_str(&name);
+                        output.push(' ');
+                    }
+                    name => match latex_symbol(name) {
+                        Some(symbol) => output.push_str(symbol),
+                        None => output.push_str(name),
+                    },
+                }
+            }
+            c @ ('^' | '_') => {
+                let (body, next) = latex_group(&chars, i + 1);
+                i = next;
+                let body = latex_to_unicode(&body);
+                let map = if c == '^' { superscript } else { subscript };
+                match body.chars().map(map).collect::<Option<String>>() {
+                    Some(v) => output.push_str(&v),
+                    None => {
+                        output.push(c);
+                        output.push_str(&wrap_operand(&body));
+                    }
+                }
+            }
+            '{' | '}' => i += 1,
+            c => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+    output
+}
+
+/// 读取一个 LaTeX 参数（花括号分组或单个字符）
+fn latex_group(chars: &[char], mut i: usize) -> (String, usize) {
+    while i < chars.len() && chars[i] == ' ' {
+        i += 1;
+    }
+    match chars.get(i) {
+        Some('{') => {
+            let mut depth = 0;
+            let start = i + 1;
+            while i < chars.len() {
+                match chars[i] {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return (chars[start..i].iter().collect(), i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            (chars[start..].iter().collect(), chars.len())
+        }
+        Some('\\') => {
+            let mut end = i + 1;
+            while end < chars.len() && chars[end].is_ascii_alphabetic() {
+                end += 1;
+            }
+            (
+                chars[i..end.max(i + 2).min(chars.len())].iter().collect(),
+                end.max(i + 2),
+            )
+        }
+        Some(&c) => (c.to_string(), i + 1),
+        None => (String::new(), i),
+    }
+}
+
+/// 多字符的操作数加括号
+fn wrap_operand(s: &str) -> String {
+    if s.chars().count() > 1 {
+        format!("({})", s)
+    } else {
+        s.to_string()
+    }
+}
+
+fn latex_symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" | "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" | "vartheta" => "θ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "rho" => "ρ",
+        "sigma" => "σ",
+        "tau" => "τ",
+        "phi" | "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "times" => "×",
+        "cdot" => "·",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "le" | "leq" => "≤",
+        "ge" | "geq" => "≥",
+        "ne" | "neq" => "≠",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "propto" => "∝",
+        "infty" => "∞",
+        "sum" => "∑",
+        "prod" => "∏",
+        "int" => "∫",
+        "oint" => "∮",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "forall" => "∀",
+        "exists" => "∃",
+        "in" => "∈",
+        "notin" => "∉",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "cup" => "∪",
+        "cap" => "∩",
+        "emptyset" | "varnothing" => "∅",
+        "to" | "rightarrow" => "→",
+        "leftarrow" => "←",
+        "Rightarrow" | "implies" => "⇒",
+        "Leftrightarrow" | "iff" => "⇔",
+        "mapsto" => "↦",
+        "ldots" | "cdots" | "dots" => "…",
+        "angle" => "∠",
+        "degree" | "circ" => "°",
+        "neg" | "lnot" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        _ => return None,
+    })
+}
+
+fn superscript(c: char) -> Option<char> {
+    Some(match c {
+        '0' => '⁰',
+        '1' => '¹',
+        '2' => '²',
+        '3' => '³',
+        '4' => '⁴',
+        '5' => '⁵',
+        '6' => '⁶',
+        '7' => '⁷',
+        '8' => '⁸',
+        '9' => '⁹',
+        '+' => '⁺',
+        '-' => '⁻',
+        '=' => '⁼',
+        '(' => '⁽',
+        ')' => '⁾',
+        'n' => 'ⁿ',
+        'i' => 'ⁱ',
+        'T' => 'ᵀ',
+        _ => return None,
+    })
+}
+
+fn subscript(c: char) -> Option<char> {
+    Some(match c {
+        '0' => '₀',
+        '1' => '₁',
+        '2' => '₂',
+        '3' => '₃',
+        '4' => '₄',
+        '5' => '₅',
+        '6' => '₆',
+        '7' => '₇',
+        '8' => '₈',
+        '9' => '₉',
+        '+' => '₊',
+        '-' => '₋',
+        '=' => '₌',
+        '(' => '₍',
+        ')' => '₎',
+        'i' => 'ᵢ',
+        'j' => 'ⱼ',
+        'n' => 'ₙ',
+        'x' => 'ₓ',
+        _ => return None,
+    })
+}
+
+fn parser_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_MATH
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+}
+
+/// 表格超出宽度时按比例缩小各列，每列不小于 min；列数过多时以 min 为宽度，超出部分被裁剪
+fn fit_columns(natural: &[f32], width: f32, min: f32) -> Vec<f32> {
+    let total: f32 = natural.iter().sum();
+    if total <= width {
+        return natural.to_vec();
+    }
+    let mins: Vec<f32> = natural.iter().map(|w| w.min(min)).collect();
+    let available = width - mins.iter().sum::<f32>();
+    let extra = total - mins.iter().sum::<f32>();
+    if available <= 0.0 || extra <= 0.0 {
+        return mins;
+    }
+    natural
+        .iter()
+        .zip(&mins)
+        .map(|(w, m)| m + (w - m) / extra * available)
+        .collect()
+}
+
+/// 模型常用 \( \) 与 \[ \] 表示公式，统一转换为 $ 语法，代码中的内容保持不变
+fn normalize_math(text: &str) -> String {
+    // 行内代码与代码块的范围
+    let code: Vec<Range<usize>> = Parser::new_ext(text, parser_options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect();
+    let mut output = String::with_capacity(text.len());
+    let mut pos = 0;
+    for range in code {
+        if range.start < pos {
+            continue;
+        }
+        output.push_str(&convert_delimiters(&text[pos..range.start]));
+        output.push_str(&text[range.clone()]);
+        pos = range.end;
+    }
+    output.push_str(&convert_delimiters(&text[pos..]));
+    escape_currency(output)
+}
+
+/// 把 \( \) 与 \[ \] 替换为 $ 与 $$
+fn convert_delimiters(text: &str) -> String {
+    text.replace("\\[", "$$")
+        .replace("\\]", "$$")
+        .replace("\\(", "$")
+        .replace("\\)", "$")
+}
+
+/// 转义被误认为行内公式的金额，如 `票价$5，套餐$10`
+///
+/// 与 Pandoc 相同，结束的 $ 后紧跟数字时不视为公式
+fn escape_currency(mut text: String) -> String {
+    loop {
+        let dollars: Vec<usize> = Parser::new_ext(&text, parser_options())
+            .into_offset_iter()
+            .filter_map(|(event, range)| match event {
+                Event::InlineMath(_)
+                    if text[range.end..].starts_with(|c: char| c.is_ascii_digit()) =>
+                {
+                    Some([range.start, range.end - 1])
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        if dollars.is_empty() {
+            return text;
+        }
+        for i in dollars.into_iter().rev() {
+            text.insert(i, '\\');
+        }
+    }
+}
+
+fn push_line_break(output: &mut String) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+fn trim_cell_separator(output: &mut String) {
+    if output.ends_with(" | ") {
+        output.truncate(output.len() - 3);
+    }
+}
+
+/// 行内文本样式
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct SpanStyle {
+    bold: bool,
+    italic: bool,
+    code: bool,
+    math: bool,
+    link: bool,
+}
+
+type Spans = Vec<(String, SpanStyle)>;
+
+/// 排版用的块级元素
+#[derive(Debug)]
+enum Block {
+    Heading(HeadingLevel, Spans),
+    Paragraph {
+        indent: usize,
+        quote: bool,
+        spans: Spans,
+    },
+    Code {
+        lang: String,
+        code: String,
+    },
+    Math(String),
+    Table {
+        head: Vec<Spans>,
+        rows: Vec<Vec<Spans>>,
+    },
+    Rule,
+}
+
+/// 将 Markdown 解析为块级元素
+fn parse_blocks(markdown: &str) -> Vec<Block> {
+    let markdown = normalize_math(markdown);
+    let mut blocks = Vec::new();
+    let mut spans: Spans = Vec::new();
+    let mut style = SpanStyle::default();
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut quote_depth = 0;
+    let mut heading: Option<HeadingLevel> = None;
+    let mut code: Option<(String, String)> = None;
+    let mut table: Option<(Vec<Spans>, Vec<Vec<Spans>>)> = None;
+    let mut row: Vec<Spans> = Vec::new();
+
+    fn flush(
+        blocks: &mut Vec<Block>,
+        spans: &mut Spans,
+        heading: Option<HeadingLevel>,
+        lists: &[Option<u64>],
+        quote_depth: usize,
+    ) {
+        if spans.iter().all(|(t, _)| t.trim().is_empty()) {
+            spans.clear();
+            return;
+        }
+        let spans = std::mem::take(spans);
+        blocks.push(match heading {
+            Some(level) => Block::Heading(level, spans),
+            None => Block::Paragraph {
+                indent: lists.len(),
+                quote: quote_depth > 0,
+                spans,
+            },
+        });
+    }
+
+    for event in Parser::new_ext(&markdown, parser_options()) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                heading = Some(level);
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                flush(&mut blocks, &mut spans, heading, &lists, quote_depth);
+                heading = None;
+            }
+            Event::End(TagEnd::Paragraph) if table.is_none() => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth)
+            }
+            Event::Start(Tag::List(start)) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                let marker = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                spans.push((marker, SpanStyle::default()));
+            }
+            Event::End(TagEnd::Item) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                quote_depth += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                quote_depth -= 1;
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) => {
+                        lang.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((lang, code)) = code.take() {
+                    blocks.push(Block::Code {
+                        lang,
+                        code: code.trim_end().to_string(),
+                    });
+                }
+            }
+            Event::Start(Tag::Table(_)) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                table = Some((Vec::new(), Vec::new()));
+            }
+            Event::End(TagEnd::TableCell) => row.push(std::mem::take(&mut spans)),
+            Event::End(TagEnd::TableHead) => {
+                if let Some((head, _)) = table.as_mut() {
+                    *head = std::mem::take(&mut row);
+                }
+            }
+            Event::End(TagEnd::TableRow) => {
+                if let Some((_, rows)) = table.as_mut() {
+                    rows.push(std::mem::take(&mut row));
+                }
+            }
+            Event::End(TagEnd::Table) => {
+                if let Some((head, rows)) = table.take() {
+                    blocks.push(Block::Table { head, rows });
+                }
+            }
+            Event::Start(Tag::Emphasis) => style.italic = true,
+            Event::End(TagEnd::Emphasis) => style.italic = false,
+            Event::Start(Tag::Strong) => style.bold = true,
+            Event::End(TagEnd::Strong) => style.bold = false,
+            Event::Start(Tag::Link { .. }) => style.link = true,
+            Event::End(TagEnd::Link) => style.link = false,
+            Event::Text(v) | Event::Html(v) | Event::InlineHtml(v) => match code.as_mut() {
+                Some((_, code)) => code.push_str(&v),
+                None => spans.push((v.to_string(), style)),
+            },
+            Event::Code(v) => spans.push((
+                v.to_string(),
+                SpanStyle {
+                    code: true,
+                    ..style
+                },
+            )),
+            Event::InlineMath(v) => spans.push((
+                latex_to_unicode(&v),
+                SpanStyle {
+                    math: true,
+                    ..style
+                },
+            )),
+            Event::DisplayMath(v) => {
+                flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+                blocks.push(Block::Math(latex_to_unicode(&v)));
+            }
+            Event::SoftBreak | Event::HardBreak => spans.push(("\n".to_string(), style)),
+            Event::Rule => blocks.push(Block::Rule),
+            Event::TaskListMarker(done) => spans.push((
+                if done { "☑ " } else { "☐ " }.to_string(),
+                SpanStyle::default(),
+            )),
+            _ => {}
+        }
+    }
+    flush(&mut blocks, &mut spans, None, &lists, quote_depth);
+
+    blocks
+}
+
+/// 绘制指令
+enum DrawOp {
+    Rect {
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        color: Color,
+    },
+    Text {
+        buffer: Buffer,
+        x: f32,
+        y: f32,
+        color: Color,
+    },
+}
+
+/// 持有字体与高亮资源的绘制器
+struct Painter {
+    font_system: FontSystem,
+    swash_cache: SwashCache,
+    syntax_set: SyntaxSet,
+    theme: Theme,
+}
+
+impl Painter {
+    /// 排版并绘制所有块
+    fn paint(&mut self, blocks: &[Block]) -> Result<Vec<u8>, Error> {
+        let content_width = WIDTH - PADDING * 2.0;
+        let mut ops = Vec::new();
+        let mut y = PADDING;
+
+        for (i, block) in blocks.iter().enumerate() {
+            // 列表项之间使用较小间距
+            match (i.checked_sub(1).map(|p| &blocks[p]), block) {
+                (None, _) => {}
+                (
+                    Some(Block::Paragraph { indent: 1.., .. }),
+                    Block::Paragraph { indent: 1.., .. },
+                ) => y += BLOCK_GAP / 3.0,
+                _ => y += BLOCK_GAP,
+            }
+            y += self.layout_block(block, PADDING, y, content_width, &mut ops);
+            if y > MAX_HEIGHT {
+                return Err(anyhow!("Rendered image is too tall"));
+            }
+        }
+        let height = (y + PADDING).ceil() as u32;
+
+        let mut pixmap =
+            Pixmap::new(WIDTH as u32, height).ok_or_else(|| anyhow!("Invalid image size"))?;
+        pixmap.fill(skia_color(BACKGROUND));
+        for op in ops {
+            match op {
+                DrawOp::Rect { x, y, w, h, color } => {
+                    if let Some(rect) = Rect::from_xywh(x.round(), y.round(), w.round(), h.round())
+                    {
+                        let mut paint = Paint {
+                            anti_alias: false,
+                            ..Default::default()
+                        };
+                        paint.set_color(skia_color(color));
+                        pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                    }
+                }
+                DrawOp::Text {
+                    mut buffer,
+                    x,
+                    y,
+                    color,
+                } => {
+                    let (ox, oy) = (x.round() as i32, y.round() as i32);
+                    buffer.draw(
+                        &mut self.font_system,
+                        &mut self.swash_cache,
+                        color,
+                        |gx, gy, w, h, color| {
+                            for dy in 0..h as i32 {
+                                for dx in 0..w as i32 {
+                                    blend_pixel(&mut pixmap, ox + gx + dx, oy + gy + dy, color);
+                                }
+                            }
+                        },
+                    );
+                }
+            }
+        }
+
+        Ok(pixmap.encode_png()?)
+    }
+
+    /// 排版单个块，返回占用高度
+    fn layout_block(
+        &mut self,
+        block: &Block,
+        x: f32,
+        y: f32,
+        width: f32,
+        ops: &mut Vec<DrawOp>,
+    ) -> f32 {
+        match block {
+            Block::Heading(level, spans) => {
+                let size = match level {
+                    HeadingLevel::H1 => 28.0,
+                    HeadingLevel::H2 => 24.0,
+                    HeadingLevel::H3 => 21.0,
+                    _ => BODY_SIZE,
+                };
+                let (buffer, _, h) =
+                    self.text_buffer(spans, size, Some(width), Attrs::new().weight(Weight::BOLD));
+                ops.push(DrawOp::Text {
+                    buffer,
+                    x,
+                    y,
+                    color: TEXT,
+                });
+                if matches!(level, HeadingLevel::H1 | HeadingLevel::H2) {
+                    ops.push(DrawOp::Rect {
+                        x,
+                        y: y + h + 6.0,
+                        w: width,
+                        h: 1.0,
+                        color: BORDER,
+                    });
+                    h + 7.0
+                } else {
+                    h
+                }
+            }
+            Block::Paragraph {
+                indent,
+                quote,
+                spans,
+            } => {
+                let offset = *indent as f32 * 20.0 + if *quote { 16.0 } else { 0.0 };
+                let (buffer, _, h) =
+                    self.text_buffer(spans, BODY_SIZE, Some(width - offset), Attrs::new());
+                if *quote {
+                    ops.push(DrawOp::Rect {
+                        x: x + offset - 16.0,
+                        y,
+                        w: 4.0,
+                        h,
+                        color: BORDER,
+                    });
+                }
+                ops.push(DrawOp::Text {
+                    buffer,
+                    x: x + offset,
+                    y,
+                    color: if *quote { MUTED } else { TEXT },
+                });
+                h
+            }
+            Block::Code { lang, code } => {
+                let pad = 12.0;
+                let (buffer, _, h) = self.code_buffer(lang, code, width - pad * 2.0);
+                ops.push(DrawOp::Rect {
+                    x,
+                    y,
+                    w: width,
+                    h: h + pad * 2.0,
+                    color: CODE_BG,
+                });
+                ops.push(DrawOp::Text {
+                    buffer,
+                    x: x + pad,
+                    y: y + pad,
+                    color: TEXT,
+                });
+                h + pad * 2.0
+            }
+            Block::Math(formula) => {
+                let mut buffer = Buffer::new(&mut self.font_system, Metrics::new(22.0, 30.0));
+                buffer.set_size(Some(width), None);
+                buffer.set_text(
+                    formula,
+                    &Attrs::new()
+                        .family(Family::Serif)
+                        .style(Style::Italic)
+                        .color(MATH),
+                    Shaping::Advanced,
+                    Some(Align::Center),
+                );
+                buffer.shape_until_scroll(&mut self.font_system, false);
+                let (_, h) = measure(&buffer);
+                ops.push(DrawOp::Text {
+                    buffer,
+                    x,
+                    y,
+                    color: MATH,
+                });
+                h
+            }
+            Block::Table { head, rows } => self.layout_table(head, rows, x, y, width, ops),
+            Block::Rule => {
+                ops.push(DrawOp::Rect {
+                    x,
+                    y: y + 4.0,
+                    w: width,
+                    h: 2.0,
+                    color: BORDER,
+                });
+                10.0
+            }
+        }
+    }
+
+    /// 排版表格，列宽按内容分配，超出宽度时等比压缩并换行
+    fn layout_table(
+        &mut self,
+        head: &[Spans],
+        rows: &[Vec<Spans>],
+        x: f32,
+        y: f32,
+        width: f32,
+        ops: &mut Vec<DrawOp>,
+    ) -> f32 {
+        let pad = 8.0;
+        let columns = rows
+            .iter()
+            .map(Vec::len)
+            .chain([head.len()])
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return 0.0;
+        }
+
+        // 计算自然列宽
+        let mut natural = vec![0.0f32; columns];
+        for row in std::iter::once(head).chain(rows.iter().map(Vec::as_slice)) {
+            for (i, cell) in row.iter().enumerate() {
+                let (_, w, _) = self.text_buffer(cell, BODY_SIZE, None, Attrs::new());
+                natural[i] = natural[i].max(w + pad * 2.0 + 1.0);
+            }
+        }
+        // 每列至少容纳一个字
+        let col_widths = fit_columns(&natural, width, pad * 2.0 + BODY_SIZE);
+        let table_width: f32 = col_widths.iter().sum();
+
+        let mut cy = y;
+        for (row_i, row) in std::iter::once(head)
+            .chain(rows.iter().map(Vec::as_slice))
+            .enumerate()
+        {
+            let attrs = if row_i == 0 {
+                Attrs::new().weight(Weight::BOLD)
+            } else {
+                Attrs::new()
+            };
+            let mut cells = Vec::new();
+            let mut row_h: f32 = 0.0;
+            let mut cx = x;
+            for (i, col_w) in col_widths.iter().enumerate() {
+                let empty = Vec::new();
+                let cell = row.get(i).unwrap_or(&empty);
+                let (buffer, _, h) =
+                    self.text_buffer(cell, BODY_SIZE, Some(col_w - pad * 2.0), attrs.clone());
+                row_h = row_h.max(h + pad * 2.0);
+                cells.push((buffer, cx));
+                cx += col_w;
+            }
+            if row_i == 0 {
+                ops.push(DrawOp::Rect {
+                    x,
+                    y: cy,
+                    w: table_width,
+                    h: row_h,
+                    color: CODE_BG,
+                });
+            }
+            for (buffer, cx) in cells {
+                ops.push(DrawOp::Text {
+                    buffer,
+                    x: cx + pad,
+                    y: cy + pad,
+                    color: TEXT,
+                });
+            }
+            ops.push(DrawOp::Rect {
+                x,
+                y: cy,
+                w: table_width,
+                h: 1.0,
+                color: BORDER,
+            });
+            cy += row_h;
+        }
+        ops.push(DrawOp::Rect {
+            x,
+            y: cy,
+            w: table_width,
+            h: 1.0,
+            color: BORDER,
+        });
+
+        // 竖线
+        let mut cx = x;
+        for col_w in col_widths.iter().chain([&0.0]) {
+            ops.push(DrawOp::Rect {
+                x: cx.min(x + table_width - 1.0),
+                y,
+                w: 1.0,
+                h: cy - y + 1.0,
+                color: BORDER,
+            });
+            cx += col_w;
+        }
+
+        cy - y + 1.0
+    }
+
+    /// 排版行内文本，返回缓冲区和宽高
+    fn text_buffer(
+        &mut self,
+        spans: &[(String, SpanStyle)],
+        size: f32,
+        width: Option<f32>,
+        base: Attrs,
+    ) -> (Buffer, f32, f32) {
+        let mut buffer = Buffer::new(&mut self.font_system, Metrics::new(size, size * 1.5));
+        buffer.set_size(width, None);
+        let rich = spans.iter().map(|(text, style)| {
+            let mut attrs = base.clone();
+            if style.bold {
+                attrs = attrs.weight(Weight::BOLD);
+            }
+            if style.italic {
+                attrs = attrs.style(Style::Italic);
+            }
+            if style.code {
+                attrs = attrs.family(Family::Monospace).color(INLINE_CODE);
+            } else if style.math {
+                attrs = attrs.family(Family::Serif).style(Style::Italic).color(MATH);
+            } else if style.link {
+                attrs = attrs.color(LINK);
+            }
+            (text.as_str(), attrs)
+        });
+        buffer.set_rich_text(rich, &base, Shaping::Advanced, None);
+        buffer.shape_until_scroll(&mut self.font_system, false);
+        let (w, h) = measure(&buffer);
+        (buffer, w, h)
+    }
+
+    /// 排版代码块，使用 syntect 高亮
+    fn code_buffer(&mut self, lang: &str, code: &str, width: f32) -> (Buffer, f32, f32) {
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        let base = Attrs::new().family(Family::Monospace);
+        let mut spans: Vec<(&str, Attrs)> = Vec::new();
+        for line in LinesWithEndings::from(code) {
+            match highlighter.highlight_line(line, &self.syntax_set) {
+                Ok(ranges) => {
+                    for (style, text) in ranges {
+                        let c = style.foreground;
+                        let mut attrs = base.clone().color(Color::rgba(c.r, c.g, c.b, c.a));
+                        if style
+                            .font_style
+                            .contains(syntect::highlighting::FontStyle::BOLD)
+                        {
+                            attrs = attrs.weight(Weight::BOLD);
+                        }
+                        spans.push((text, attrs));
+                    }
+                }
+                Err(_) => spans.push((line, base.clone())),
+            }
+        }
+
+        let mut buffer = Buffer::new(
+            &mut self.font_system,
+            Metrics::new(CODE_SIZE, CODE_SIZE * 1.45),
+        );
+        buffer.set_size(Some(width), None);
+        buffer.set_rich_text(spans, &base, Shaping::Advanced, None);
+        buffer.shape_until_scroll(&mut self.font_system, false);
+        let (w, h) = measure(&buffer);
+        (buffer, w, h)
+    }
+}
+
+/// 计算缓冲区排版后的宽高
+fn measure(buffer: &Buffer) -> (f32, f32) {
+    buffer.layout_runs().fold((0.0f32, 0.0f32), |(w, h), run| {
+        (w.max(run.line_w), h.max(run.line_top + run.line_height))
+    })
+}
+
+fn skia_color(color: Color) -> tiny_skia::Color {
+    tiny_skia::Color::from_rgba8(color.r(), color.g(), color.b(), color.a())
+}
+
+/// 以 source-over 方式混合单个像素
+fn blend_pixel(pixmap: &mut Pixmap, x: i32, y: i32, color: Color) {
+    if x < 0 || y < 0 || x >= pixmap.width() as i32 || y >= pixmap.height() as i32 {
+        return;
+    }
+    let index = (y as u32 * pixmap.width() + x as u32) as usize * 4;
+    let data = pixmap.data_mut();
+    let alpha = color.a() as u32;
+    if alpha == 0 {
+        return;
+    }
+    for (i, c) in [color.r(), color.g(), color.b()].into_iter().enumerate() {
+        let dst = data[index + i] as u32;
+        data[index + i] = ((c as u32 * alpha + dst * (255 - alpha)) / 255) as u8;
+    }
+    let dst_a = data[index + 3] as u32;
+    data[index + 3] = (alpha + dst_a * (255 - alpha) / 255) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::*;
+
+    #[test]
+    fn test_should_render() {
+        assert!(!should_render("（摇了摇尾巴）博士，早上好喵~"));
+        assert!(!should_render("**重要**：记得吃饭"));
+        assert!(should_render("```rust\nfn main() {}\n```"));
+        assert!(should_render("| a | b |\n|---|---|\n| 1 | 2 |"));
+        assert!(should_render("面积是 $\\pi r^2$"));
+        assert!(should_render("\\[E = mc^2\\]"));
+        // 金额不是公式
+        assert!(!should_render("票价$5，套餐$10"));
+        assert!(!should_render("$5 到 $10"));
+    }
+
+    #[test]
+    fn test_fit_columns() {
+        assert_eq!(fit_columns(&[100.0, 200.0], 800.0, 34.0), [100.0, 200.0]);
+        let widths = fit_columns(&[400.0, 800.0], 600.0, 34.0);
+        assert!((widths.iter().sum::<f32>() - 600.0).abs() < 0.01);
+        assert!(widths[0] < widths[1]);
+        // 窄列保持原宽度，其余列缩小后不小于最小宽度
+        let widths = fit_columns(&[20.0, 34.0, 1000.0], 500.0, 34.0);
+        assert_eq!(widths[..2], [20.0, 34.0]);
+        assert!((widths[2] - 446.0).abs() < 0.01);
+        // 列数过多时每列不小于最小宽度
+        let widths = fit_columns(&[100.0; 40], 744.0, 34.0);
+        assert!(widths.iter().all(|w| *w == 34.0));
+    }
+
+    #[test]
+    fn test_normalize_math() {
+        assert_eq!(normalize_math("\\(x^2\\)"), "$x^2$");
+        // 代码中的转义保持不变
+        assert_eq!(normalize_math("`\\(a\\)` 和 \\(b\\)"), "`\\(a\\)` 和 $b$");
+        assert_eq!(
+            normalize_math("```\nre = r\"\\(\\d+\\)\"\n```"),
+            "```\nre = r\"\\(\\d+\\)\"\n```"
+        );
+        assert_eq!(
+            strip_markdown("`\\[0-9\\]` 票价$5，套餐$10"),
+            "\\[0-9\\] 票价$5，套餐$10"
+        );
+    }
+
+    #[test]
+    fn test_strip_markdown() {
+        assert_eq!(
+            strip_markdown("## 标题\n\n**加粗** 和 *斜体*"),
+            "标题\n\n加粗 和 斜体"
+        );
+        assert_eq!(strip_markdown("- 一\n- 二"), "• 一\n• 二");
+        assert_eq!(
+            strip_markdown("[萌娘百科](https://zh.moegirl.org.cn)"),
+            "萌娘百科 (https://zh.moegirl.org.cn)"
+        );
+        assert_eq!(
+            strip_markdown("| a | b |\n|---|---|\n| 1 | 2 |"),
+            "a | b\n1 | 2"
+        );
+    }
+
+    #[test]
+    fn test_latex_to_unicode() {
+        assert_eq!(latex_to_unicode("E = mc^2"), "E = mc²");
+        assert_eq!(latex_to_unicode("\\frac{a+b}{2}"), "(a+b)/2");
+        assert_eq!(latex_to_unicode("\\alpha \\le \\sqrt{x_1}"), "α ≤ √(x₁)");
+    }
+}
@@ -0,0 +1,266 @@
+use serde_json::{Map, Value};
+
+/// 按 JSON Schema 检查工具参数，返回所有不符合的地方
+///
+/// 支持 type、enum、const、properties、required、additionalProperties、items、
+/// anyOf、oneOf、allOf 以及长度与数值范围，其他关键字 (如 $ref、pattern) 会被忽略
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+    let mut errors = vec![];
+    check(schema, value, "$", &mut errors);
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join("；")),
+    }
+}
+
+/// 值的 JSON 类型名
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// 值是否属于 schema 中的类型
+fn is_type(value: &Value, kind: &str) -> bool {
+    match (kind, value) {
+        // 1.0 也是整数
+        ("integer", Value::Number(n)) => n.as_f64().is_some_and(|f| f.fract() == 0.0),
+        ("number", Value::Number(_)) => true,
+        _ => type_name(value) == kind,
+    }
+}
+
+fn check(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        // true 或空 schema 不作限制，false 不允许任何值
+        if schema == &Value::Bool(false) {
+            errors.push(format!("{}: 不允许此字段", path));
+        }
+        return;
+    };
+
+    let types: Vec<&str> = match &schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(t)) => t.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|t| is_type(value, t)) {
+        errors.push(format!(
+            "{}: 应为 {}，实际为 {}",
+            path,
+            types.join(" 或 "),
+            type_name(value)
+        ));
+        return;
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array)
+        && !options.contains(value)
+    {
+        let options: Vec<String> = options.iter().map(Value::to_string).collect();
+        errors.push(format!("{}: 应为 {} 之一", path, options.join(", ")));
+    }
+    if let Some(expected) = schema.get("const")
+        && expected != value
+    {
+        errors.push(format!("{}: 应为 {}", path, expected));
+    }
+
+    check_combinators(schema, value, path, errors);
+    match value {
+        Value::Object(object) => check_object(schema, object, path, errors),
+        Value::Array(items) => {
+            check_range(
+                schema,
+                "minItems",
+                "maxItems",
+                items.len(),
+                "项",
+                path,
+                errors,
+            );
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{}[{}]", path, i), errors);
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count();
+            check_range(schema, "minLength", "maxLength", len, "个字", path, errors);
+        }
+        Value::Number(n) => check_number(schema, n.as_f64().unwrap_or_default(), path, errors),
+        _ => {}
+    }
+}
+
+fn check_combinators(
+    schema: &Map<String, Value>,
+    value: &Value,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    let matches = |s: &Value| {
+        let mut e = vec![];
+        check(s, value, path, &mut e);
+        e
+    };
+    if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+        for s in all {
+            errors.extend(matches(s));
+        }
+    }
+    if let Some(any) = schema.get("anyOf").and_then(Value::as_array) {
+        let mut results: Vec<Vec<String>> = any.iter().map(matches).collect();
+        if !results.iter().any(Vec::is_empty) {
+            // 只有一个选项时直接给出其中的错误
+            match results.len() {
+                1 => errors.append(&mut results[0]),
+                _ => errors.push(format!("{}: 不符合 anyOf 中的任何一项", path)),
+            }
+        }
+    }
+    if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+        let count = one.iter().filter(|s| matches(s).is_empty()).count();
+        if count != 1 {
+            errors.push(format!(
+                "{}: 应恰好符合 oneOf 中的一项，实际符合 {} 项",
+                path, count
+            ));
+        }
+    }
+}
+
+fn check_object(
+    schema: &Map<String, Value>,
+    object: &Map<String, Value>,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    let empty = Map::new();
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    for key in schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+    {
+        if !object.contains_key(key) {
+            errors.push(format!("{}: 缺少必填字段 {}", path, key));
+        }
+    }
+    for (key, value) in object {
+        let field = format!("{}.{}", path, key);
+        match (properties.get(key), schema.get("additionalProperties")) {
+            (Some(s), _) => check(s, value, &field, errors),
+            (None, Some(Value::Bool(false))) => {
+                let known: Vec<&str> = properties.keys().map(String::as_str).collect();
+                errors.push(format!(
+                    "{}: 未知字段，可用的字段为 {}",
+                    field,
+                    known.join(", ")
+                ));
+            }
+            (None, Some(s)) => check(s, value, &field, errors),
+            (None, None) => {}
+        }
+    }
+}
+
+fn check_range(
+    schema: &Map<String, Value>,
+    min_key: &str,
+    max_key: &str,
+    len: usize,
+    unit: &str,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    let get = |key| schema.get(key).and_then(Value::as_u64);
+    if let Some(min) = get(min_key)
+        && (len as u64) < min
+    {
+        errors.push(format!("{}: 至少 {} {}，实际为 {}", path, min, unit, len));
+    }
+    if let Some(max) = get(max_key)
+        && (len as u64) > max
+    {
+        errors.push(format!("{}: 最多 {} {}，实际为 {}", path, max, unit, len));
+    }
+}
+
+fn check_number(schema: &Map<String, Value>, n: f64, path: &str, errors: &mut Vec<String>) {
+    let get = |key| schema.get(key).and_then(Value::as_f64);
+    if let Some(min) = get("minimum")
+        && n < min
+    {
+        errors.push(format!("{}: 应不小于 {}，实际为 {}", path, min, n));
+    }
+    if let Some(max) = get("maximum")
+        && n > max
+    {
+        errors.push(format!("{}: 应不大于 {}，实际为 {}", path, max, n));
+    }
+    if let Some(min) = get("exclusiveMinimum")
+        && n <= min
+    {
+        errors.push(format!("{}: 应大于 {}，实际为 {}", path, min, n));
+    }
+    if let Some(max) = get("exclusiveMaximum")
+        && n >= max
+    {
+        errors.push(format!("{}: 应小于 {}，实际为 {}", path, max, n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "a": { "type": "integer", "minimum": 0 },
+                "mode": { "enum": ["fast", "slow"] },
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 2 },
+                "name": { "type": ["string", "null"], "minLength": 2 }
+            },
+            "required": ["a"],
+            "additionalProperties": false
+        });
+        assert!(validate(&schema, &json!({ "a": 1 })).is_ok());
+        assert!(validate(&schema, &json!({ "a": 2.0, "name": null })).is_ok());
+        assert_eq!(
+            validate(&schema, &json!({})).unwrap_err(),
+            "$: 缺少必填字段 a"
+        );
+        assert_eq!(
+            validate(&schema, &json!({ "a": "1", "mode": "normal" })).unwrap_err(),
+            "$.a: 应为 integer，实际为 string；$.mode: 应为 \"fast\", \"slow\" 之一"
+        );
+        assert_eq!(
+            validate(&schema, &json!({ "a": -1, "tags": ["x", 1, "y"] })).unwrap_err(),
+            "$.a: 应不小于 0，实际为 -1；$.tags: 最多 2 项，实际为 3；$.tags[1]: 应为 string，实际为 integer"
+        );
+        assert_eq!(
+            validate(&schema, &json!({ "a": 1, "b": 2, "name": "x" })).unwrap_err(),
+            "$.b: 未知字段，可用的字段为 a, mode, name, tags；$.name: 至少 2 个字，实际为 1"
+        );
+        assert_eq!(
+            validate(&json!({ "type": "object" }), &json!([])).unwrap_err(),
+            "$: 应为 object，实际为 array"
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use crate::commands::*;
+use crate::secrets::warn;
+use kovi::chrono::DateTime;
+use std::collections::HashMap;
+
+/// 最多返回的结果数
+const MAX_RESULTS: usize = 10;
+/// 最多检查的候选结果数，用于过滤调用者不在的群
+const MAX_CANDIDATES: i64 = 200;
+/// 最多使用的关键词数
+const MAX_TERMS: usize = 5;
+/// 片段中关键词前后保留的字数
+const SNIPPET_BEFORE: usize = 20;
+const SNIPPET_AFTER: usize = 40;
+
+/// 一条搜索结果
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    /// 所在的群，私聊为 None
+    pub group_id: Option<i64>,
+    /// 发送者的昵称
+    pub nickname: String,
+    /// 原消息 ID，导入的旧记录为 None
+    pub message_id: Option<i32>,
+    /// 发送的时间戳，导入的旧记录为 None
+    pub time: Option<i64>,
+    pub text: String,
+}
+
+/// 转换为 SQL 条件，trigram 分词器只能匹配三个字以上的词，较短的词使用 LIKE
+pub struct SearchQuery {
+    /// FTS5 MATCH 表达式
+    pub fts: Option<String>,
+    /// LIKE 模式
+    pub likes: Vec<String>,
+}
+
+impl SearchQuery {
+    pub fn new(terms: &[String]) -> Self {
+        let mut fts = vec![];
+        let mut likes = vec![];
+        for term in terms {
+            if term.chars().count() >= 3 {
+                fts.push(format!("\"{}\"", term.replace('"', "\"\"")));
+            } else {
+                let escaped = term
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_");
+                likes.push(format!("%{}%", escaped));
+            }
+        }
+        SearchQuery {
+            fts: (!fts.is_empty()).then(|| fts.join(" AND ")),
+            likes,
+        }
+    }
+}
+
+/// 截取关键词附近的片段并标出关键词
+fn snippet(text: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    let found = terms
+        .iter()
+        .filter(|term| !term.is_empty())
+        .filter_map(|term| {
+            let term: Vec<char> = term.to_lowercase().chars().collect();
+            let start = lower.windows(term.len()).position(|w| w == term)?;
+            Some((start, term.len()))
+        })
+        .min();
+    let Some((start, len)) = found else {
+        return chars.iter().take(SNIPPET_BEFORE + SNIPPET_AFTER).collect();
+    };
+
+    let from = start.saturating_sub(SNIPPET_BEFORE);
+    let to = (start + len + SNIPPET_AFTER).min(chars.len());
+    let mut output = String::new();
+    if from > 0 {
+        output.push('…');
+    }
+    output.extend(&chars[from..start]);
+    output.push('【');
+    output.extend(&chars[start..start + len]);
+    output.push('】');
+    output.extend(&chars[start + len..to]);
+    if to < chars.len() {
+        output.push('…');
+    }
+    output.replace('\n', " ")
+}
+
+/// 格式化一条结果
+fn format_hit(ctx: &CommandContext<'_>, hit: &SearchHit, terms: &[String]) -> String {
+    let time = match hit.time.and_then(|t| DateTime::from_timestamp(t, 0)) {
+        Some(t) => ctx
+            .config
+            .local_time(t)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        None => "未知时间".to_string(),
+    };
+    let place = match hit.group_id {
+        Some(id) => format!("群 {}", id),
+        None => "私聊".to_string(),
+    };
+    format!(
+        "{} · {} · {}\n{}",
+        hit.nickname,
+        place,
+        time,
+        snippet(&hit.text, terms)
+    )
+}
+
+/// search 命令
+pub struct SearchCommand;
+
+#[async_trait]
+impl Command for SearchCommand {
+    fn name(&self) -> &'static str {
+        "search"
+    }
+
+    fn description(&self) -> &'static str {
+        "搜索聊天记录，群聊中只搜索本群，私聊中包含你所在的群与你的私聊"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["搜索"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "search <关键词...>"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["search 周报", "search 服务器 重启"]
+    }
+
+    fn category(&self) -> &'static str {
+        "工具"
+    }
+
+    fn cooldown(&self) -> Cooldown {
+        Cooldown::per_user(5)
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let terms: Vec<String> = ctx
+            .args
+            .positional()
+            .iter()
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty())
+            .take(MAX_TERMS)
+            .map(String::from)
+            .collect();
+        if terms.is_empty() {
+            return Err(UsageError("缺少关键词".to_string()).into());
+        }
+        let query = SearchQuery::new(&terms);
+        // 群聊中只搜索本群，避免公开私聊与其他群的记录
+        let candidates = ctx
+            .resources
+            .user_manager
+            .search(&query, ctx.user.id, ctx.msg.group_id, MAX_CANDIDATES)
+            .await?;
+
+        // 过滤调用者不在的群，当前群无需查询
+        let mut member: HashMap<i64, bool> = HashMap::new();
+        if let Some(id) = ctx.msg.group_id {
+            member.insert(id, true);
+        }
+        let mut hits = vec![];
+        for hit in candidates {
+            if let Some(group_id) = hit.group_id {
+                let allowed = match member.get(&group_id) {
+                    Some(v) => *v,
+                    None => {
+                        let v = ctx
+                            .resources
+                            .bot
+                            .get_group_member_info(group_id, ctx.user.id, false)
+                            .await
+                            .is_ok();
+                        member.insert(group_id, v);
+                        v
+                    }
+                };
+                if !allowed {
+                    continue;
+                }
+            }
+            hits.push(hit);
+            if hits.len() >= MAX_RESULTS {
+                break;
+            }
+        }
+        info!(
+            "User {} search {:?}, {} results",
+            ctx.user.id,
+            terms,
+            hits.len()
+        );
+        if hits.is_empty() {
+            ctx.reply("没有找到相关的聊天记录");
+            return Ok(true);
+        }
+
+        let header = format!(
+            "找到 {} 条相关记录 (最多显示 {} 条)",
+            hits.len(),
+            MAX_RESULTS
+        );
+        let mut nodes = vec![KoviMsg::from(header.as_str())];
+        // 当前会话内的结果引用原消息，点击可跳转
+        nodes.extend(hits.iter().map(|hit| {
+            let msg = KoviMsg::from(format_hit(ctx, hit, &terms));
+            match hit.message_id {
+                Some(id) if id != 0 && hit.group_id == ctx.msg.group_id => msg.add_reply(id),
+                _ => msg,
+            }
+        }));
+        match crate::message::reply_forward(&ctx.resources.bot, ctx.msg, nodes).await {
+            Ok(()) => return Ok(true),
+            Err(e) => warn!("{}", e),
+        }
+        // 合并转发失败时以纯文本回复
+        let mut text = header;
+        for hit in &hits {
+            text.push_str("\n\n");
+            text.push_str(&format_hit(ctx, hit, &terms));
+        }
+        ctx.reply(text);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search::*;
+    use crate::user_manager::UserManager;
+
+    #[test]
+    fn test_search() {
+        let terms = ["周报".to_string(), "服务器".to_string()];
+        let query = SearchQuery::new(&terms);
+        assert_eq!(query.fts.as_deref(), Some("\"服务器\""));
+        assert_eq!(query.likes, ["%周报%"]);
+        assert_eq!(
+            snippet("今天要交周报，别忘了", &terms[..1]),
+            "今天要交【周报】，别忘了"
+        );
+        assert_eq!(snippet("今天要交周报", &["".to_string()]), "今天要交周报");
+
+        let dir = std::env::temp_dir().join(format!("rosbot-search-{}", std::process::id()));
+        let runtime = kovi::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let db = UserManager::open(dir.join("users.db")).await.unwrap();
+            let log = |user_id, text: &str| crate::summary::LoggedMessage {
+                user_id,
+                nickname: format!("u{}", user_id),
+                message_id: 1,
+                time: 100,
+                text: text.to_string(),
+            };
+            db.log_group_message(1, &log(10, "服务器今晚重启，周报明天交"))
+                .await
+                .unwrap();
+            db.log_group_message(2, &log(20, "服务器很稳定"))
+                .await
+                .unwrap();
+            db.log_chat_message(None, &log(30, "帮我写一份周报，提到服务器迁移"))
+                .await
+                .unwrap();
+
+            let hits = db.search(&query, 10, None, 10).await.unwrap();
+            assert_eq!(hits.len(), 1);
+            assert_eq!(hits[0].group_id, Some(1));
+            // 其他用户的私聊不可见
+            let hits = db.search(&query, 30, None, 10).await.unwrap();
+            assert_eq!(hits.len(), 2);
+            assert!(hits.iter().any(|h| h.group_id.is_none()));
+            // 群聊中只搜索本群
+            assert!(db.search(&query, 30, Some(2), 10).await.unwrap().is_empty());
+            let hits = db.search(&query, 30, Some(1), 10).await.unwrap();
+            assert_eq!(hits.len(), 1);
+            assert_eq!(hits[0].group_id, Some(1));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Error, anyhow};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// 引用文件的密钥前缀，如 file:/run/secrets/token
+const FILE_PREFIX: &str = "file:";
+/// 覆盖密钥的环境变量前缀，与配置项一致
+const ENV_PREFIX: &str = "ROSBOT_";
+/// 过短的值不做替换，避免误伤日志
+const MIN_REDACT_LEN: usize = 6;
+
+/// 已加载的密钥，用于日志脱敏
+static KNOWN: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// 密钥存储，按环境变量、secrets.toml 的顺序查找
+pub struct Secrets {
+    path: PathBuf,
+    values: RwLock<HashMap<String, String>>,
+}
+
+impl Secrets {
+    /// 读取密钥文件，文件不存在时为空
+    pub fn load(path: PathBuf) -> Result<Self, Error> {
+        let values = read_file(&path)?;
+        Ok(Secrets {
+            path,
+            values: RwLock::new(values),
+        })
+    }
+
+    /// 重新读取密钥文件
+    pub fn reload(&self) -> Result<(), Error> {
+        let values = read_file(&self.path)?;
+        *self.values.write().unwrap() = values;
+        Ok(())
+    }
+
+    /// 获取密钥，变量名为 ROSBOT_ 加大写的密钥名
+    pub fn get(&self, name: &str) -> Result<Option<String>, Error> {
+        let env = format!("{}{}", ENV_PREFIX, name.to_uppercase());
+        let value = match std::env::var(&env) {
+            Ok(v) => v,
+            Err(_) => match self.values.read().unwrap().get(name) {
+                Some(v) => v.clone(),
+                None => return Ok(None),
+            },
+        };
+        self.resolve(&value).map(Some)
+    }
+
+    /// 解析密钥值，file: 开头时读取引用的文件，相对路径基于密钥文件所在目录
+    pub fn resolve(&self, value: &str) -> Result<String, Error> {
+        let value = match value.strip_prefix(FILE_PREFIX) {
+            Some(path) => {
+                let path = self
+                    .path
+                    .parent()
+                    .map_or_else(|| PathBuf::from(path), |dir| dir.join(path));
+                check_permissions(&path)?;
+                std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed to read secret {}: {}", path.display(), e))?
+                    .trim()
+                    .to_string()
+            }
+            None => value.to_string(),
+        };
+        remember(&value);
+        Ok(value)
+    }
+}
+
+/// 读取 secrets.toml
+fn read_file(path: &Path) -> Result<HashMap<String, String>, Error> {
+    if !path.is_file() {
+        return Ok(HashMap::new());
+    }
+    check_permissions(path)?;
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    kovi::toml::from_str(&text).map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// 拒绝其他用户可读写的密钥文件
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(anyhow!(
+            "{} is accessible by other users (mode {:o}), run `chmod 600` on it",
+            path.display(),
+            mode & 0o777
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// 记录密钥以便脱敏
+pub fn remember(value: &str) {
+    if value.len() < MIN_REDACT_LEN {
+        return;
+    }
+    let mut known = KNOWN.write().unwrap();
+    if !known.iter().any(|v| v == value) {
+        known.push(value.to_string());
+    }
+}
+
+/// 替换文本中已知的密钥
+pub fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in KNOWN.read().unwrap().iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), "******");
+        }
+    }
+    text
+}
+
+/// 脱敏后输出日志，插件内的日志均使用以下宏而非 kovi::log
+macro_rules! error {
+    ($($arg:tt)+) => {
+        kovi::log::error!("{}", $crate::secrets::redact(&format!($($arg)+)))
+    };
+}
+
+macro_rules! warn_ {
+    ($($arg:tt)+) => {
+        kovi::log::warn!("{}", $crate::secrets::redact(&format!($($arg)+)))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => {
+        kovi::log::info!("{}", $crate::secrets::redact(&format!($($arg)+)))
+    };
+}
+
+pub(crate) use {error, info};
+// warn 与内置属性同名，需改名后导出
+pub(crate) use warn_ as warn;
+
+#[cfg(test)]
+mod tests {
+    use crate::secrets::*;
+
+    #[test]
+    fn test_secrets() {
+        let dir = std::env::temp_dir().join(format!("rosbot-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secrets.toml");
+        std::fs::write(&path, "seedream_token = \"file:seedream.txt\"\n").unwrap();
+        std::fs::write(dir.join("seedream.txt"), "sk-seedream-123\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let private = std::fs::Permissions::from_mode(0o600);
+            std::fs::set_permissions(&path, private.clone()).unwrap();
+            std::fs::set_permissions(dir.join("seedream.txt"), private).unwrap();
+        }
+
+        let secrets = Secrets::load(path.clone()).unwrap();
+        assert_eq!(
+            secrets.get("seedream_token").unwrap().as_deref(),
+            Some("sk-seedream-123")
+        );
+        assert_eq!(secrets.get("missing").unwrap(), None);
+        assert_eq!(
+            redact("Authorization: Bearer sk-seedream-123"),
+            "Authorization: Bearer ******"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            assert!(secrets.reload().is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
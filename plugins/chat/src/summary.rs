@@ -0,0 +1,318 @@
+use crate::commands::*;
+use crate::cooldown::parse_duration;
+use crate::render::strip_markdown;
+use crate::secrets::{error, warn};
+use crate::user_manager::{ChatRole, Message as OpenaiMsg, MessageContent};
+use kovi::chrono::{DateTime, Duration as TimeDelta, NaiveTime, Utc};
+use std::time::Duration;
+use tiktoken_rs::o200k_base_singleton;
+
+/// 群聊记录默认保留的天数
+pub const DEFAULT_LOG_DAYS: u32 = 3;
+/// 默认总结的消息条数
+const DEFAULT_COUNT: i64 = 100;
+/// 单次最多总结的消息条数
+const MAX_COUNT: i64 = 2000;
+/// 每段记录默认的 Token 上限
+const DEFAULT_CHUNK_TOKENS: usize = 6000;
+/// 超过此字数时以合并转发回复
+const FORWARD_THRESHOLD: usize = 300;
+/// 清理过期记录的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// 总结单段记录的提示词
+const CHUNK_PROMPT: &str = "你是群聊记录的总结助手。请用中文总结用户给出的群聊记录，按话题分条列出讨论内容与结论，\
+每条注明主要发言者的昵称，不要编造记录中没有的内容。";
+/// 合并多段总结的提示词
+const MERGE_PROMPT: &str = "你是群聊记录的总结助手。用户给出的是同一段群聊记录分段得到的多份总结，\
+请合并为一份完整的总结，按话题分条列出并保留发言者的昵称，去除重复的内容。";
+
+/// 群聊记录中的一条消息
+#[derive(Debug, Clone)]
+pub struct LoggedMessage {
+    pub user_id: i64,
+    /// 发送时的群名片或昵称
+    pub nickname: String,
+    /// 原消息 ID
+    pub message_id: i32,
+    /// 发送的时间戳
+    pub time: i64,
+    pub text: String,
+}
+
+/// 总结的范围
+#[derive(Debug, PartialEq)]
+enum Range {
+    /// 最近的若干条
+    Count(i64),
+    /// 某一时刻之后
+    Since(DateTime<Utc>),
+}
+
+/// 解析参数，如 200、2h、since 30分钟、since 14:00
+fn parse_range(args: &[String], config: &Config, now: DateTime<Utc>) -> Option<Range> {
+    let arg = match args {
+        [] => return Some(Range::Count(DEFAULT_COUNT)),
+        [kind, arg] if matches!(kind.as_str(), "since" | "从") => arg,
+        [arg] => {
+            if let Ok(count) = arg.parse::<i64>() {
+                return (1..=MAX_COUNT)
+                    .contains(&count)
+                    .then_some(Range::Count(count));
+            }
+            arg
+        }
+        _ => return None,
+    };
+    if let Some(d) = parse_duration(arg) {
+        return Some(Range::Since(now - TimeDelta::from_std(d).ok()?));
+    }
+    // 今天的某一时刻，尚未到达时为昨天
+    let time = NaiveTime::parse_from_str(&arg.replace('：', ":"), "%H:%M").ok()?;
+    let local = config.local_time(now);
+    let mut since = local.with_time(time).single()?;
+    if since > local {
+        since -= TimeDelta::days(1);
+    }
+    Some(Range::Since(since.with_timezone(&Utc)))
+}
+
+/// 按 Token 上限把记录分段，单行超出上限时独占一段
+fn chunk(lines: &[String], limit: usize, count: impl Fn(&str) -> usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut current = String::new();
+    let mut tokens = 0;
+    for line in lines {
+        let n = count(line) + 1;
+        if !current.is_empty() && tokens + n > limit {
+            chunks.push(std::mem::take(&mut current));
+            tokens = 0;
+        }
+        current.push_str(line);
+        current.push('\n');
+        tokens += n;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// 请求 AI 总结一段文本
+async fn ask(ctx: &CommandContext<'_>, prompt: &str, text: String) -> Result<String, Error> {
+    let mut messages = vec![OpenaiMsg {
+        role: ChatRole::User,
+        content: MessageContent::Text(text),
+    }];
+    let reply = ctx
+        .resources
+        .client
+        .chat_without_tools(&mut messages, Some(prompt))
+        .await?;
+    let MessageContent::Text(text) = reply.content else {
+        return Err(anyhow!("Reply contain Multi"));
+    };
+    Ok(text)
+}
+
+/// 分段总结后逐级合并，直到只剩一份
+async fn summarize(ctx: &CommandContext<'_>, lines: &[String]) -> Result<String, Error> {
+    let limit = ctx
+        .config
+        .summary_chunk_tokens
+        .unwrap_or(DEFAULT_CHUNK_TOKENS);
+    let bpe = o200k_base_singleton();
+    let count = |s: &str| bpe.encode_with_special_tokens(s).len();
+
+    let mut parts = vec![];
+    for text in chunk(lines, limit, count) {
+        parts.push(ask(ctx, CHUNK_PROMPT, text).await?);
+    }
+    while parts.len() > 1 {
+        let chunks = chunk(&parts, limit, count);
+        // 无法继续合并时直接拼接
+        if chunks.len() >= parts.len() {
+            return Ok(parts.join("\n\n"));
+        }
+        let mut merged = vec![];
+        for text in chunks {
+            merged.push(ask(ctx, MERGE_PROMPT, text).await?);
+        }
+        parts = merged;
+    }
+    Ok(parts.pop().unwrap_or_default())
+}
+
+/// 后台定期清理超出保留天数的群聊记录
+pub fn spawn_log_cleaner(res: Arc<Resources>) {
+    kovi::spawn(async move {
+        loop {
+            let days = res.config.load().group_log_days.unwrap_or(DEFAULT_LOG_DAYS);
+            let before = (Utc::now() - TimeDelta::days(days.into())).timestamp();
+            if let Err(e) = res.user_manager.prune_group_messages(before).await {
+                error!("Failed to prune group messages: {}", e);
+            }
+            kovi::tokio::time::sleep(PRUNE_INTERVAL).await;
+        }
+    });
+}
+
+/// summary 命令
+pub struct SummaryCommand;
+
+#[async_trait]
+impl Command for SummaryCommand {
+    fn name(&self) -> &'static str {
+        "summary"
+    }
+
+    fn description(&self) -> &'static str {
+        "总结最近的群聊记录"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["总结"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "summary [条数] | summary since <时长|时刻>"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "summary",
+            "summary 300",
+            "summary since 2h",
+            "summary since 14:00",
+        ]
+    }
+
+    fn category(&self) -> &'static str {
+        "工具"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::GroupOnly
+    }
+
+    fn cooldown(&self) -> Cooldown {
+        Cooldown::per_user(60).group(30)
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<bool, Error> {
+        let Some(group_id) = ctx.msg.group_id else {
+            return Ok(true);
+        };
+        if ctx.config.group_log_days == Some(0) {
+            ctx.reply("未开启群聊记录，无法总结");
+            return Ok(true);
+        }
+        let now = Utc::now();
+        let range = parse_range(ctx.args.positional(), &ctx.config, now).ok_or_else(|| {
+            UsageError(format!(
+                "条数应为 1-{}，或使用 since <时长|时刻>",
+                MAX_COUNT
+            ))
+        })?;
+        let (since, limit) = match range {
+            Range::Count(n) => (0, n),
+            Range::Since(t) => (t.timestamp(), MAX_COUNT),
+        };
+        let messages = ctx
+            .resources
+            .user_manager
+            .group_messages(group_id, since, limit)
+            .await?;
+        let (Some(first), Some(last)) = (messages.first(), messages.last()) else {
+            ctx.reply("这段时间没有聊天记录");
+            return Ok(true);
+        };
+
+        let time = |t: i64| {
+            let t = DateTime::from_timestamp(t, 0).unwrap_or_default();
+            ctx.config.local_time(t).format("%m-%d %H:%M").to_string()
+        };
+        let header = format!(
+            "{} 条消息的总结 ({} - {})",
+            messages.len(),
+            time(first.time),
+            time(last.time)
+        );
+        let lines: Vec<String> = messages
+            .iter()
+            .map(|m| format!("[{}] {}: {}", time(m.time), m.nickname, m.text))
+            .collect();
+        info!(
+            "User {} summarize {} messages of group {}",
+            ctx.user.id,
+            lines.len(),
+            group_id
+        );
+        ctx.reply(format!("正在总结 {} 条消息…", lines.len()));
+        let summary = summarize(ctx, &lines).await?;
+
+        // 内容较长时以合并转发回复
+        let text = strip_markdown(&summary);
+        if text.chars().count() > FORWARD_THRESHOLD {
+            let mut nodes = vec![KoviMsg::from(header.as_str())];
+            nodes.extend(
+                text.split("\n\n")
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(KoviMsg::from),
+            );
+            match crate::message::reply_forward(&ctx.resources.bot, ctx.msg, nodes).await {
+                Ok(()) => return Ok(true),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        let reply = ctx
+            .resources
+            .renderer
+            .to_message(format!("{}\n\n{}", header, summary))
+            .await;
+        ctx.reply(reply);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::summary::*;
+
+    #[test]
+    fn test_summary_range_and_chunk() {
+        let config = Config {
+            timezone: Some("+08:00".to_string()),
+            ..Default::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2026-03-01T10:00:00+08:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+        let since =
+            |s: &str| Range::Since(DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc));
+
+        assert_eq!(parse_range(&[], &config, now), Some(Range::Count(100)));
+        assert_eq!(
+            parse_range(&args("300"), &config, now),
+            Some(Range::Count(300))
+        );
+        assert_eq!(parse_range(&args("0"), &config, now), None);
+        assert_eq!(
+            parse_range(&args("since 2h"), &config, now),
+            Some(since("2026-03-01T08:00:00+08:00"))
+        );
+        assert_eq!(
+            parse_range(&args("since 14:00"), &config, now),
+            Some(since("2026-02-28T14:00:00+08:00"))
+        );
+        assert_eq!(parse_range(&args("since 明天"), &config, now), None);
+
+        let lines: Vec<String> = ["aaaa", "bbbb", "cccccccccc", "dd"]
+            .map(String::from)
+            .to_vec();
+        let chunks = chunk(&lines, 10, str::len);
+        assert_eq!(chunks, ["aaaa\nbbbb\n", "cccccccccc\n", "dd\n"]);
+    }
+}
@@ -1,8 +1,19 @@
+use crate::broadcast::Broadcast;
+use crate::group_settings::GroupSettings;
+use crate::message::FORWARD_NICKNAME;
+use crate::permission::Role;
+use crate::reminder::Reminder;
+use crate::search::{SearchHit, SearchQuery};
+use crate::summary::LoggedMessage;
 use anyhow::Error;
+use kovi::tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
 use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 /// 消息
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -39,7 +50,7 @@ pub enum ChatRole {
     System,
     User,
     Assistant,
-    // Function,
+    Tool,
 }
 
 /// 用户数据
@@ -49,11 +60,15 @@ pub struct User {
     pub id: i64,
     /// 用户聊天历史
     pub history: Vec<Message>,
+    /// 上一条回复的思考过程
+    pub reasoning: Option<String>,
 }
 
 /// 用户管理器
 pub struct UserManager {
     pool: SqlitePool,
+    /// 聊天上下文的锁，只保留正在使用的
+    locks: Mutex<HashMap<i64, Arc<AsyncMutex<()>>>>,
 }
 
 impl UserManager {
@@ -88,7 +103,106 @@ impl UserManager {
         .execute(&pool)
         .await?;
 
-        Ok(Self { pool })
+        // 创建角色表，未记录的用户为普通用户
+        sqlx::query(
+            r#"
+        CREATE TABLE IF NOT EXISTS roles (
+            id INTEGER PRIMARY KEY,
+            role TEXT NOT NULL
+        )
+        "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // 创建群设置表，未记录的群使用默认设置
+        sqlx::query(
+            r#"
+        CREATE TABLE IF NOT EXISTS groups (
+            id INTEGER PRIMARY KEY,
+            settings TEXT NOT NULL
+        )
+        "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // 创建群每日用量表
+        sqlx::query(
+            r#"
+        CREATE TABLE IF NOT EXISTS group_usage (
+            id INTEGER NOT NULL,
+            day TEXT NOT NULL,
+            count INTEGER NOT NULL,
+            PRIMARY KEY (id, day)
+        )
+        "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // 创建提醒表
+        sqlx::query(
+            r#"
+        CREATE TABLE IF NOT EXISTS reminders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            group_id INTEGER,
+            due INTEGER NOT NULL,
+            content TEXT NOT NULL
+        )
+        "#,
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS reminders_due ON reminders (due)")
+            .execute(&pool)
+            .await?;
+
+        // 创建定时任务表
+        sqlx::query(
+            r#"
+        CREATE TABLE IF NOT EXISTS broadcasts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            job TEXT NOT NULL
+        )
+        "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // 创建群聊记录表
+        sqlx::query(
+            r#"
+        CREATE TABLE IF NOT EXISTS group_messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            group_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            nickname TEXT NOT NULL,
+            message_id INTEGER,
+            time INTEGER NOT NULL,
+            text TEXT NOT NULL
+        )
+        "#,
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS group_messages_time ON group_messages (group_id, time)",
+        )
+        .execute(&pool)
+        .await?;
+
+        // 旧数据库补充字段
+        add_column(&pool, "users", "reasoning", "TEXT").await?;
+        add_column(&pool, "group_messages", "message_id", "INTEGER").await?;
+
+        create_search_index(&pool).await?;
+
+        Ok(Self {
+            pool,
+            locks: Mutex::new(HashMap::new()),
+        })
     }
 
     /// 保存或更新用户
@@ -96,32 +210,543 @@ impl UserManager {
         let history_json = serde_json::to_string(&user.history)?;
         sqlx::query(
             r#"
-            INSERT INTO users (id, history) VALUES (?, ?)
-            ON CONFLICT(id) DO UPDATE SET history=excluded.history
+            INSERT INTO users (id, history, reasoning) VALUES (?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET history=excluded.history, reasoning=excluded.reasoning
             "#,
         )
         .bind(user.id)
         .bind(history_json)
+        .bind(&user.reasoning)
         .execute(&self.pool)
         .await?;
         Ok(())
     }
 
+    /// 只保存思考过程，不影响聊天记录
+    pub async fn set_reasoning(&self, id: i64, reasoning: Option<String>) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, history, reasoning) VALUES (?, '[]', ?)
+            ON CONFLICT(id) DO UPDATE SET reasoning=excluded.reasoning
+            "#,
+        )
+        .bind(id)
+        .bind(reasoning)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 锁定聊天上下文，在读取到保存聊天记录期间持有，避免并发的回复互相覆盖
+    pub async fn lock_context(&self, id: i64) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            Arc::clone(locks.entry(id).or_default())
+        };
+        lock.lock_owned().await
+    }
+
     /// 加载用户，如果不存在返回默认用户
     pub async fn load_user(&self, id: i64) -> Result<User, Error> {
-        if let Some(row) = sqlx::query("SELECT history FROM users WHERE id = ?")
+        if let Some(row) = sqlx::query("SELECT history, reasoning FROM users WHERE id = ?")
             .bind(id)
             .fetch_optional(&self.pool)
             .await?
         {
             let history_json: String = row.try_get("history")?;
             let history: Vec<Message> = serde_json::from_str(&history_json)?;
-            Ok(User { id, history })
+            let reasoning: Option<String> = row.try_get("reasoning")?;
+            Ok(User {
+                id,
+                history,
+                reasoning,
+            })
         } else {
             Ok(User {
                 id,
                 history: Vec::new(),
+                reasoning: None,
+            })
+        }
+    }
+
+    /// 获取用户角色
+    pub async fn get_role(&self, id: i64) -> Result<Role, Error> {
+        let role: Option<String> = sqlx::query_scalar("SELECT role FROM roles WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(role.and_then(|r| r.parse().ok()).unwrap_or_default())
+    }
+
+    /// 设置用户角色，设为普通用户时删除记录
+    pub async fn set_role(&self, id: i64, role: Role) -> Result<(), Error> {
+        if role == Role::User {
+            sqlx::query("DELETE FROM roles WHERE id = ?")
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        } else {
+            sqlx::query(
+                "INSERT INTO roles (id, role) VALUES (?, ?) ON CONFLICT(id) DO UPDATE SET role=excluded.role",
+            )
+            .bind(id)
+            .bind(role.as_str())
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// 列出所有非普通用户的角色，按权限从高到低排列
+    pub async fn list_roles(&self) -> Result<Vec<(i64, Role)>, Error> {
+        let rows = sqlx::query("SELECT id, role FROM roles")
+            .fetch_all(&self.pool)
+            .await?;
+        let mut roles = Vec::with_capacity(rows.len());
+        for row in rows {
+            let role: String = row.try_get("role")?;
+            if let Ok(role) = role.parse::<Role>() {
+                roles.push((row.try_get::<i64, _>("id")?, role));
+            }
+        }
+        roles.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(roles)
+    }
+
+    /// 根据 Kovi 配置写入所有者与管理员，旧的所有者降级为管理员
+    pub async fn seed_admins(&self, owner: i64, deputies: &[i64]) -> Result<(), Error> {
+        sqlx::query("UPDATE roles SET role = 'admin' WHERE role = 'owner' AND id != ?")
+            .bind(owner)
+            .execute(&self.pool)
+            .await?;
+        self.set_role(owner, Role::Owner).await?;
+        for id in deputies.iter().filter(|&&id| id != owner) {
+            sqlx::query(
+                r#"
+                INSERT INTO roles (id, role) VALUES (?, 'admin')
+                ON CONFLICT(id) DO UPDATE SET role='admin' WHERE role != 'owner'
+                "#,
+            )
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// 获取群设置，不存在时返回默认设置
+    pub async fn get_group(&self, id: i64) -> Result<GroupSettings, Error> {
+        let settings: Option<String> =
+            sqlx::query_scalar("SELECT settings FROM groups WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        match settings {
+            Some(v) => Ok(serde_json::from_str(&v)?),
+            None => Ok(GroupSettings::default()),
+        }
+    }
+
+    /// 保存群设置
+    pub async fn save_group(&self, id: i64, settings: &GroupSettings) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO groups (id, settings) VALUES (?, ?) ON CONFLICT(id) DO UPDATE SET settings=excluded.settings",
+        )
+        .bind(id)
+        .bind(serde_json::to_string(settings)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 消耗一次群当日配额，已用完时返回 false
+    pub async fn take_quota(&self, id: i64, day: &str, limit: u32) -> Result<bool, Error> {
+        // 清理往日记录
+        sqlx::query("DELETE FROM group_usage WHERE day != ?")
+            .bind(day)
+            .execute(&self.pool)
+            .await?;
+        let result = sqlx::query(
+            r#"
+            INSERT INTO group_usage (id, day, count) VALUES (?, ?, 1)
+            ON CONFLICT(id, day) DO UPDATE SET count = count + 1 WHERE count < ?
+            "#,
+        )
+        .bind(id)
+        .bind(day)
+        .bind(limit)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// 添加提醒，返回编号
+    pub async fn add_reminder(&self, reminder: &Reminder) -> Result<i64, Error> {
+        let result = sqlx::query(
+            "INSERT INTO reminders (user_id, group_id, due, content) VALUES (?, ?, ?, ?)",
+        )
+        .bind(reminder.user_id)
+        .bind(reminder.group_id)
+        .bind(reminder.due)
+        .bind(&reminder.content)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.last_insert_rowid())
+    }
+
+    /// 列出用户的待办提醒，按时间排列
+    pub async fn list_reminders(&self, user_id: i64) -> Result<Vec<Reminder>, Error> {
+        let rows = sqlx::query("SELECT * FROM reminders WHERE user_id = ? ORDER BY due")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(reminder_from_row).collect()
+    }
+
+    /// 取消提醒，user_id 为 None 时不检查创建者
+    pub async fn cancel_reminder(&self, id: i64, user_id: Option<i64>) -> Result<bool, Error> {
+        let result =
+            sqlx::query("DELETE FROM reminders WHERE id = ? AND (? IS NULL OR user_id = ?)")
+                .bind(id)
+                .bind(user_id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// 列出所有到期的提醒，送达后需调用 cancel_reminder 删除
+    pub async fn due_reminders(&self, now: i64) -> Result<Vec<Reminder>, Error> {
+        let rows = sqlx::query("SELECT * FROM reminders WHERE due <= ? ORDER BY due")
+            .bind(now)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(reminder_from_row).collect()
+    }
+}
+
+impl UserManager {
+    /// 添加定时任务，返回编号
+    pub async fn add_broadcast(&self, job: &Broadcast) -> Result<i64, Error> {
+        let result = sqlx::query("INSERT INTO broadcasts (job) VALUES (?)")
+            .bind(serde_json::to_string(job)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.last_insert_rowid())
+    }
+
+    /// 列出所有定时任务
+    pub async fn list_broadcasts(&self) -> Result<Vec<(i64, Broadcast)>, Error> {
+        let rows = sqlx::query("SELECT id, job FROM broadcasts ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+        let mut jobs = Vec::with_capacity(rows.len());
+        for row in rows {
+            let job: String = row.try_get("job")?;
+            jobs.push((row.try_get::<i64, _>("id")?, serde_json::from_str(&job)?));
+        }
+        Ok(jobs)
+    }
+
+    /// 删除定时任务
+    pub async fn remove_broadcast(&self, id: i64) -> Result<bool, Error> {
+        let result = sqlx::query("DELETE FROM broadcasts WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl UserManager {
+    /// 记录一条群聊消息
+    pub async fn log_group_message(
+        &self,
+        group_id: i64,
+        message: &LoggedMessage,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO group_messages (group_id, user_id, nickname, message_id, time, text)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(group_id)
+        .bind(message.user_id)
+        .bind(&message.nickname)
+        .bind(message.message_id)
+        .bind(message.time)
+        .bind(&message.text)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 读取 since 之后最近的 limit 条群聊消息，按时间排列
+    pub async fn group_messages(
+        &self,
+        group_id: i64,
+        since: i64,
+        limit: i64,
+    ) -> Result<Vec<LoggedMessage>, Error> {
+        let rows = sqlx::query(
+            "SELECT * FROM group_messages WHERE group_id = ? AND time >= ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(group_id)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut messages = rows
+            .iter()
+            .map(|row| {
+                Ok(LoggedMessage {
+                    user_id: row.try_get("user_id")?,
+                    nickname: row.try_get("nickname")?,
+                    message_id: row
+                        .try_get::<Option<i32>, _>("message_id")?
+                        .unwrap_or_default(),
+                    time: row.try_get("time")?,
+                    text: row.try_get("text")?,
+                })
             })
+            .collect::<Result<Vec<_>, Error>>()?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// 删除早于 before 的群聊消息
+    pub async fn prune_group_messages(&self, before: i64) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM group_messages WHERE time < ?")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+impl UserManager {
+    /// 记录与 Bot 的对话，用于搜索
+    pub async fn log_chat_message(
+        &self,
+        group_id: Option<i64>,
+        message: &LoggedMessage,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO chat_log (group_id, user_id, nickname, message_id, time, text)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(group_id)
+        .bind(message.user_id)
+        .bind(&message.nickname)
+        .bind(message.message_id)
+        .bind(message.time)
+        .bind(&message.text)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 搜索群聊记录与对话记录，按时间倒序排列
+    /// group_id 为 Some 时只搜索该群，否则包含 user_id 自己的私聊与所有群，群聊记录需由调用者检查是否为群成员
+    pub async fn search(
+        &self,
+        query: &SearchQuery,
+        user_id: i64,
+        group_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<SearchHit>, Error> {
+        let mut hits = vec![];
+        for (table, visible) in [
+            ("group_messages", "TRUE"),
+            ("chat_log", "(group_id IS NOT NULL OR user_id = ?)"),
+        ] {
+            let mut sql = format!(
+                "SELECT group_id, nickname, message_id, time, text FROM {} WHERE {}",
+                table, visible
+            );
+            if group_id.is_some() {
+                sql.push_str(" AND group_id = ?");
+            }
+            if query.fts.is_some() {
+                sql.push_str(&format!(
+                    " AND id IN (SELECT rowid FROM {}_fts WHERE {}_fts MATCH ?)",
+                    table, table
+                ));
+            }
+            for _ in &query.likes {
+                sql.push_str(" AND text LIKE ? ESCAPE '\\'");
+            }
+            sql.push_str(" ORDER BY time DESC LIMIT ?");
+
+            let mut q = sqlx::query(&sql);
+            if table == "chat_log" {
+                q = q.bind(user_id);
+            }
+            if let Some(id) = group_id {
+                q = q.bind(id);
+            }
+            if let Some(fts) = &query.fts {
+                q = q.bind(fts);
+            }
+            for like in &query.likes {
+                q = q.bind(like);
+            }
+            for row in q.bind(limit).fetch_all(&self.pool).await? {
+                hits.push(SearchHit {
+                    group_id: row.try_get("group_id")?,
+                    nickname: row.try_get("nickname")?,
+                    message_id: row.try_get("message_id")?,
+                    time: row.try_get("time")?,
+                    text: row.try_get("text")?,
+                });
+            }
+        }
+        hits.sort_by_key(|h| std::cmp::Reverse(h.time));
+        hits.truncate(limit as usize);
+        Ok(hits)
+    }
+}
+
+/// 为旧数据库补充字段
+async fn add_column(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Error> {
+    let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(pool)
+        .await?;
+    if !columns
+        .iter()
+        .any(|c| c.try_get::<String, _>("name").is_ok_and(|n| n == column))
+    {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// 创建对话记录表与全文索引，首次创建时导入已有记录
+async fn create_search_index(pool: &SqlitePool) -> Result<(), Error> {
+    let exists = sqlx::query("SELECT 1 FROM sqlite_master WHERE name = 'chat_log'")
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS chat_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            group_id INTEGER,
+            user_id INTEGER NOT NULL,
+            nickname TEXT NOT NULL,
+            message_id INTEGER,
+            time INTEGER,
+            text TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // trigram 分词支持中文的任意子串匹配，由触发器与原表保持同步
+    for table in ["group_messages", "chat_log"] {
+        sqlx::query(&format!(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS {t}_fts USING fts5(
+                text, content='{t}', content_rowid='id', tokenize='trigram'
+            )
+            "#,
+            t = table
+        ))
+        .execute(pool)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS {t}_ai AFTER INSERT ON {t} BEGIN
+                INSERT INTO {t}_fts (rowid, text) VALUES (new.id, new.text);
+            END
+            "#,
+            t = table
+        ))
+        .execute(pool)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS {t}_ad AFTER DELETE ON {t} BEGIN
+                INSERT INTO {t}_fts ({t}_fts, rowid, text) VALUES ('delete', old.id, old.text);
+            END
+            "#,
+            t = table
+        ))
+        .execute(pool)
+        .await?;
+    }
+    if exists {
+        return Ok(());
+    }
+
+    // 导入已有的群聊记录与聊天历史，历史中没有时间与消息 ID
+    sqlx::query("INSERT INTO group_messages_fts (group_messages_fts) VALUES ('rebuild')")
+        .execute(pool)
+        .await?;
+    let rows = sqlx::query("SELECT id, history FROM users")
+        .fetch_all(pool)
+        .await?;
+    for row in rows {
+        let id: i64 = row.try_get("id")?;
+        let history: String = row.try_get("history")?;
+        let Ok(history) = serde_json::from_str::<Vec<Message>>(&history) else {
+            continue;
+        };
+        // 负数 ID 为群共享的上下文
+        let group_id = (id < 0).then_some(-id);
+        for message in history {
+            let nickname = match message.role {
+                ChatRole::User => "用户",
+                ChatRole::Assistant => FORWARD_NICKNAME,
+                _ => continue,
+            };
+            let text = match message.content {
+                MessageContent::Text(v) => v,
+                MessageContent::Multi(v) => v
+                    .into_iter()
+                    .filter_map(|p| p.text)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            sqlx::query(
+                "INSERT INTO chat_log (group_id, user_id, nickname, text) VALUES (?, ?, ?, ?)",
+            )
+            .bind(group_id)
+            .bind(id)
+            .bind(nickname)
+            .bind(text)
+            .execute(pool)
+            .await?;
         }
     }
+    Ok(())
+}
+
+fn reminder_from_row(row: &SqliteRow) -> Result<Reminder, Error> {
+    Ok(Reminder {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        group_id: row.try_get("group_id")?,
+        due: row.try_get("due")?,
+        content: row.try_get("content")?,
+    })
 }